@@ -10,11 +10,31 @@ layout(location = 1) out vec3 frag_normal;
 
 layout(set = 0, binding = 1, std430) readonly buffer Voxel {
     uvec4 dimension;
+    uvec4 highlight; // w is 1 when xyz is a highlighted voxel
     uint voxels[];
 } voxel;
 layout(set = 1, binding = 1, std140) uniform Colors {
     uvec4 colors[64];
 };
+layout(set = 1, binding = 2, std430) readonly buffer LargeColors {
+    uint large_colors[];
+};
+struct Material {
+    float emissive;
+    float roughness;
+    float metalness;
+    uint flags;
+};
+layout(set = 1, binding = 3, std140) uniform Materials {
+    Material materials[256];
+};
+
+const float THRESHOLD = 0.0001;
+const uint STORAGE_OCTREE = 1;
+const uint OCTREE_LEAF = 1u << 31;
+const uint MATERIAL_TRANSPARENT = 1;
+const float REFRACTIVE_INDEX = 1.5;
+const Material DEFAULT_MATERIAL = Material(0.0, 1.0, 0.0, 0);
 
 struct HitInfo {
     vec3 intersection;
@@ -41,18 +61,53 @@ HitInfo intersect_nearest(vec3 point, vec3 direction) {
     vec3 intersection = point + direction * distance;
     vec3 normal = vec3(0.0);
     normal[index] = -sign(direction[index]);
-    uvec3 voxel_pos = uvec3(ivec3(floor(intersection - normal * 0.5)) + ivec3(voxel.dimension) / 2);
+    uvec3 voxel_pos = uvec3(ivec3(floor(intersection - normal * 0.5)));
 
     return HitInfo(intersection, normal, voxel_pos);
 }
-uint get_voxel_color(uvec3 voxel_pos) {
+// Walks down the octree to the leaf containing voxel_pos, leaf_size is the width of that leaf in voxels.
+uint get_octree_index(uvec3 voxel_pos, out uint leaf_size) {
+    uint level = voxel.dimension.w >> 8;
+    uint node = voxel.voxels[0];
+    while ((node & OCTREE_LEAF) == 0) {
+        level--;
+        uvec3 bit = (voxel_pos >> level) & 1u;
+        node = voxel.voxels[node + (bit.x | (bit.y << 1) | (bit.z << 2))];
+    }
+    leaf_size = 1u << level;
+    return node & 0xff;
+}
+uint get_voxel_color(uvec3 voxel_pos, out uint leaf_size, out Material material) {
+    leaf_size = 1;
+    material = DEFAULT_MATERIAL;
     uvec3 dimension = voxel.dimension.xyz;
     if (voxel_pos.x >= dimension.x || voxel_pos.y >= dimension.y || voxel_pos.z >= dimension.z)
         return 0;
-    uint index = voxel_pos.x + voxel_pos.y * dimension.x + voxel_pos.z * dimension.x * dimension.y;
-    uint color_index = (voxel.voxels[index / 4] >> ((index % 4) * 8)) & 0xff;
+    uint color_index;
+    if ((voxel.dimension.w & 0xff) == STORAGE_OCTREE) {
+        color_index = get_octree_index(voxel_pos, leaf_size);
+    } else {
+        uint bits = (voxel.dimension.w >> 8) & 0xff;
+        uint per_word = 32 / bits;
+        uint index = voxel_pos.x + voxel_pos.y * dimension.x + voxel_pos.z * dimension.x * dimension.y;
+        color_index = (voxel.voxels[index / per_word] >> ((index % per_word) * bits)) & ((1u << bits) - 1);
+        if (bits == 16) return large_colors[color_index]; // the material table only covers 8 bit palettes
+    }
+    material = materials[color_index];
     return colors[color_index / 4][color_index % 4];
 }
+// Moves point to just before the exit of the octree leaf of the given size containing voxel_pos.
+vec3 skip_leaf(vec3 point, vec3 direction, uvec3 voxel_pos, uint leaf_size) {
+    vec3 leaf_min = vec3(voxel_pos / leaf_size * leaf_size);
+    vec3 leaf_max = leaf_min + float(leaf_size);
+    float distance = float(leaf_size) * 3.0; // longer than any path through the leaf
+    for (uint axis = 0; axis < 3; axis++) {
+        if (direction[axis] == 0.0) continue;
+        float plane = direction[axis] > 0.0 ? leaf_max[axis] : leaf_min[axis];
+        distance = min(distance, (plane - point[axis]) / direction[axis]);
+    }
+    return point + direction * max(distance - THRESHOLD, 0.0);
+}
 vec4 unpack_color(uint packed) {
     return vec4(
         float(packed & 0xff) / 255.0,
@@ -62,36 +117,76 @@ vec4 unpack_color(uint packed) {
     );
 }
 
-const float THRESHOLD = 0.0001;
 const vec3 LIGHT_DIR = normalize(vec3(-3.0, -10.0, -5.0));
+const float OUTLINE_WIDTH = 0.06;
+
+bool is_outline(HitInfo info) {
+    if (voxel.highlight.w == 0 || info.voxel_pos != voxel.highlight.xyz) return false;
+    // the face being hit is the plane of the normal, so only the other two axes can be near an edge
+    vec3 edge_distance = min(fract(info.intersection), 1.0 - fract(info.intersection)) + abs(info.normal);
+    return min(edge_distance.x, min(edge_distance.y, edge_distance.z)) < OUTLINE_WIDTH;
+}
+
+vec4 shade(vec4 color, vec3 normal, Material material, vec3 direction) {
+    float diffuse = (dot(normal, -LIGHT_DIR) + 1) * 0.5;
+    vec3 half_dir = normalize(-LIGHT_DIR - direction);
+    float specular = pow(max(dot(normal, half_dir), 0.0), mix(256.0, 2.0, material.roughness)) * (1.0 - material.roughness);
+    vec3 reflectance = mix(vec3(0.04), color.rgb, material.metalness);
+
+    vec3 lit = color.rgb * diffuse * (1.0 - material.metalness) + reflectance * specular;
+    return vec4(lit + color.rgb * material.emissive, color.a);
+}
 
 void main() {
     vec3 direction = normalize(i_point - i_camera_pos);
     float direction_dot = dot(direction, i_normal);
 
-    vec3 point = (direction_dot < 0.0 ? i_point : i_camera_pos) * vec3(voxel.dimension);
+    // voxel space, where voxel boundaries lie on whole numbers and the volume spans from 0 to its dimension
+    vec3 point = ((direction_dot < 0.0 ? i_point : i_camera_pos) + 0.5) * vec3(voxel.dimension.xyz);
     vec4 color = vec4(0.0);
     vec3 normal = vec3(0.0);
 
     point -= direction * THRESHOLD;
+    bool inside = false; // whether the ray is currently travelling through transparent voxels
 
     for (uint i = 0; i < i_iterations; i++) {
         HitInfo info = intersect_nearest(point, direction);
-        vec4 hit_color = unpack_color(get_voxel_color(info.voxel_pos));
+        uint leaf_size;
+        Material material;
+        vec4 hit_color = unpack_color(get_voxel_color(info.voxel_pos, leaf_size, material));
         if (color.w < 1.0) {
             normal = info.normal;
         }
+        vec4 shaded = shade(hit_color, info.normal, material, direction);
+        if (hit_color.w > 0.0 && is_outline(info)) {
+            shaded = vec4(1.0);
+        }
         vec3 cf = color.xyz; // foreground color
         float af = color.w; // foreground alpha
-        vec3 cb = hit_color.xyz; // background color
+        vec3 cb = shaded.xyz; // background color
         float ab = hit_color.w; // background alpha
         vec3 cr = cf * af + cb * (1.0 - af); // alpha-blending result rgb color
         float ar = af + ab * (1.0 - af); // alpha-blending result alpha channel
         color = vec4(cr, ar);
         point = info.intersection + direction * THRESHOLD;
+
+        // the ray bends when it enters or leaves transparent voxels
+        bool transparent = hit_color.w > 0.0 && (material.flags & MATERIAL_TRANSPARENT) != 0;
+        if (transparent != inside) {
+            vec3 refracted = refract(direction, info.normal, transparent ? 1.0 / REFRACTIVE_INDEX : REFRACTIVE_INDEX);
+            if (refracted == vec3(0.0)) {
+                // total internal reflection keeps the ray inside
+                direction = reflect(direction, info.normal);
+            } else {
+                direction = refracted;
+                inside = transparent;
+            }
+            point = info.intersection + direction * THRESHOLD;
+        } else if (hit_color.w == 0.0 && leaf_size > 1) {
+            point = skip_leaf(point, direction, info.voxel_pos, leaf_size);
+        }
     }
 
-    float light_dot = (dot(normal, -LIGHT_DIR) + 1) * 0.5;
     frag_normal = normal;
-    frag_color = color * light_dot;
+    frag_color = color;
 }
@@ -6,6 +6,7 @@ use bevy::window::*;
 use camera::*;
 use model::*;
 use renderer::*;
+use std::error::Error;
 use std::f32::consts::PI;
 use voxel::*;
 
@@ -16,10 +17,186 @@ fn contains_resource<T: Resource>(resource: Option<Res<T>>) -> bool {
     resource.is_some()
 }
 
+// voxels per unit of world space, matching the size of the generated terrain
+const VOXEL_SCALE: f32 = 64.0;
+// how far away in world space voxels can be picked
+const PICK_DISTANCE: f32 = 4.0;
+// radius of the brush shapes in voxels
+const BRUSH_RADIUS: f32 = 3.0;
+// empty voxels added around or cropped from the model under the crosshair with G
+const RESIZE_MARGIN: u32 = 8;
+// levels of each channel in the 16 bit color cube spawned with H
+const COLOR_CUBE_STEPS: u32 = 16;
+// size of the models spawned with N
+const BLANK_DIMENSION: UVec3 = UVec3::splat(64);
+// voxels along the longest side of voxelized meshes
+const MESH_RESOLUTION: u32 = 64;
+// size of the volume heightmaps are imported into
+const HEIGHTMAP_DIMENSION: UVec3 = uvec3(128, 64, 128);
+const EXPORT_PATH: &str = "voxels.glb";
+const SMOOTH_EXPORT_PATH: &str = "voxels.ply";
+const OBJ_EXPORT_PATH: &str = "voxels.obj";
+const VOX_EXPORT_PATH: &str = "voxels.vox";
+const NATIVE_EXPORT_PATH: &str = "voxels.vxlr";
+// directory of the slices saved with F7, followed by the axis they're cut along
+const SLICES_EXPORT_PATH: &str = "slices";
+// saved next to the slices, .hex keeps the alpha of every entry
+const SLICES_PALETTE: &str = "palette.hex";
+// chunks of the world that is generated around the camera when no model is loaded, the terrain fits
+// into a single chunk vertically
+const CHUNK_SIZE: UVec3 = uvec3(32, 64, 32);
+const VIEW_DISTANCE: u32 = 4;
+// generating a whole row of chunks at once would stall the frame the camera crosses into a new chunk
+const CHUNKS_PER_FRAME: usize = 2;
+
+// A loaded volume, models without a transform are lined up next to each other.
+struct Model {
+    voxel: Voxel,
+    name: Option<String>,
+    transform: Option<Transform>,
+}
+impl From<Voxel> for Model {
+    fn from(voxel: Voxel) -> Self {
+        Self {
+            voxel,
+            name: None,
+            transform: None,
+        }
+    }
+}
+
+// Transform of a volume whose lowest corner is at min and whose voxels are voxel_size apart.
+fn place(dimension: UVec3, min: Vec3, voxel_size: f32) -> Transform {
+    let size = dimension.as_vec3() * voxel_size;
+    Transform::from_translation(min + size * 0.5).with_scale(size)
+}
+// The surface of a heightmap is painted by an image next to it, named like the heightmap with
+// .colors.png or .splat.png in place of its extension. Splat maps pick grass, dirt, stone and water
+// with their red, green, blue and alpha channels.
+fn surface_map(heightmap: &str) -> Result<Option<SurfaceMap>, Box<dyn Error>> {
+    let stem = std::path::Path::new(heightmap).with_extension("");
+    let colors = stem.with_extension("colors.png");
+    if colors.exists() {
+        let image = RgbaImage::open(colors)?;
+        return Ok(Some(SurfaceMap::Color(
+            image,
+            Box::new(VoxelColors::all_color()),
+        )));
+    }
+    let splat = stem.with_extension("splat.png");
+    if splat.exists() {
+        let TerrainLayers {
+            grass,
+            dirt,
+            stone,
+            water,
+            ..
+        } = TerrainLayers::default();
+        let indices = [grass, dirt, stone, water];
+        return Ok(Some(SurfaceMap::Splat(RgbaImage::open(splat)?, indices)));
+    }
+    Ok(None)
+}
+fn load_models(path: &str) -> Result<(Vec<Model>, Option<VoxelColors>), Box<dyn Error>> {
+    if path.ends_with(".vox") {
+        // models split from one volume are put back together where the scene places them
+        let scene = VoxScene::open(path)?;
+        Ok((vec![scene.merge().into()], Some(scene.colors)))
+    } else if path.ends_with(".qb") {
+        let scene = QbScene::open(path)?;
+        let models = scene.matrices.into_iter().map(|matrix| Model {
+            transform: Some(place(
+                matrix.voxel.dimension(),
+                matrix.position.as_vec3() / VOXEL_SCALE,
+                VOXEL_SCALE.recip(),
+            )),
+            name: Some(matrix.name),
+            voxel: matrix.voxel,
+        });
+        Ok((models.collect(), Some(scene.colors)))
+    } else if path.ends_with(".binvox") {
+        // the grid covers scale units of the original mesh along its longest side
+        let binvox = Binvox::open(path)?;
+        let dimension = binvox.voxel.dimension();
+        let voxel_size = binvox.scale / dimension.max_element() as f32;
+        let model = Model {
+            transform: Some(place(dimension, binvox.translate, voxel_size)),
+            ..binvox.voxel.into()
+        };
+        Ok((vec![model], Some(binvox.colors)))
+    } else if path.ends_with(".obj") {
+        // dithering hides the bands of smooth vertex colors reduced to a palette
+        let quantizer = ColorQuantizer {
+            dithering: Dithering::FloydSteinberg,
+            ..Default::default()
+        };
+        let (voxel, colors) = ObjMesh::open(path)?.voxelize(MESH_RESOLUTION, true, &quantizer);
+        Ok((vec![voxel.into()], Some(colors)))
+    } else if std::path::Path::new(path).is_dir() {
+        // a directory of slices in name order, matched against the palette saved with them or the
+        // default one and cut along the axis the name of the directory ends with, like slices_x, or Y
+        let axis = match path.trim_end_matches('/').rsplit('_').next() {
+            Some("x") => SliceAxis::X,
+            Some("z") => SliceAxis::Z,
+            _ => SliceAxis::Y,
+        };
+        let mut slices: Vec<_> = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        slices.retain(|slice| {
+            slice
+                .extension()
+                .is_some_and(|extension| extension == "png")
+        });
+        slices.sort();
+        let colors = match VoxelColors::open(std::path::Path::new(path).join(SLICES_PALETTE)) {
+            Ok(colors) => colors,
+            Err(PaletteError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                VoxelColors::all_color()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let voxel = Voxel::load_slices(&slices, &colors, axis)?;
+        Ok((vec![voxel.into()], Some(colors)))
+    } else if path.ends_with(".png") || path.ends_with(".pgm") {
+        let importer = HeightmapImporter {
+            surface: surface_map(path)?,
+            ..Default::default()
+        };
+        let mut voxel = Voxel::new(HEIGHTMAP_DIMENSION);
+        importer.fill(&mut voxel, &RgbaImage::open(path)?);
+        Ok((vec![voxel.into()], None))
+    } else {
+        // anything else has to be a native file
+        match Voxel::load(path) {
+            Ok((voxel, colors)) => Ok((vec![voxel.into()], colors)),
+            Err(VoxelFileError::InvalidMagic) => Err("unsupported file type".into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+fn spawn_models(
+    commands: &mut Commands,
+    renderer: &Renderer,
+    voxel_pipeline: &Pipeline,
+    models: Vec<Model>,
+) {
+    let mut offset = 0.0;
+    for model in models {
+        let size = model.voxel.dimension().as_vec3() / VOXEL_SCALE;
+        let transform = model.transform.unwrap_or_else(|| {
+            offset += size.x;
+            Transform::from_translation(vec3(offset - size.x * 0.5, 0.0, 0.0)).with_scale(size)
+        });
+        let mut bundle = VoxelBundle::from_voxel(model.voxel, renderer, voxel_pipeline);
+        bundle.transform = TransformBundle::from_transform(transform);
+        let mut entity = commands.spawn((bundle, VoxelHighlight::default()));
+        if let Some(name) = model.name {
+            entity.insert(Name::new(name));
+        }
+    }
+}
 fn setup(
-    mut commands: Commands,
-    renderer: Res<Renderer>,
-    voxel_pipeline: Res<Pipeline>,
     main_camera: Res<MainCamera>,
     mut camera_q: Query<&mut Transform>,
     mut window_q: Query<&mut Window, With<PrimaryWindow>>,
@@ -31,39 +208,737 @@ fn setup(
     camera.translation *= 0.5;
     camera.look_at(Vec3::ZERO, Vec3::Y);
 
-    commands.spawn(VoxelBundle::new(
-        UVec3::splat(64),
-        &renderer,
-        &voxel_pipeline,
-    ));
-
     let mut window = window_q.single_mut();
 
     window.cursor.grab_mode = CursorGrabMode::Locked;
     window.cursor.visible = false;
 }
-fn set_voxel(mut voxel_q: Query<&mut Voxel>) {
-    let Some(mut voxel) = voxel_q.iter_mut().next() else {
-        return;
-    };
-    let water_level = 10.0;
-    voxel.for_each_mut(|v, position| {
-        let scaled_position = position.as_vec3() * (16.0 / 64.0);
-        let scaled_position1 = scaled_position / 3.0;
-        let height = (scaled_position.x.cos() + scaled_position.z.sin()) * 2.0
-            + 7.0
-            + (scaled_position1.x.cos() + scaled_position1.z.cos() + scaled_position1.y.sin())
-                * 2.0;
-        *v = if position.y as f32 > height {
-            if (position.y as f32) < water_level {
-                0x0
+// Fills the chunks of the world that is streamed when no model is loaded.
+#[derive(Resource)]
+enum WorldGenerator {
+    Terrain(TerrainGenerator),
+    Caves(CaveGenerator),
+}
+impl WorldGenerator {
+    fn layers(&self) -> TerrainLayers {
+        match self {
+            Self::Terrain(generator) => generator.layers,
+            Self::Caves(generator) => generator.terrain.layers,
+        }
+    }
+    fn fill(&self, voxel: &mut Voxel, origin: IVec3) {
+        match self {
+            Self::Terrain(generator) => generator.fill(voxel, origin),
+            Self::Caves(generator) => generator.fill(voxel, origin),
+        }
+    }
+}
+// The first argument is either a model to load, optionally followed by a palette, or the kind of
+// world to stream, "terrain" or "caves", optionally followed by its seed. Without one caves are streamed.
+fn spawn_scene(
+    mut commands: Commands,
+    renderer: Res<Renderer>,
+    voxel_pipeline: Res<Pipeline>,
+    mut main_colors: ResMut<MainVoxelColors>,
+    mut main_materials: ResMut<MainVoxelMaterials>,
+    mut colors_q: Query<&mut VoxelColors>,
+) {
+    let mut args = std::env::args().skip(1);
+    let path = match args.next() {
+        Some(path) if path != "terrain" && path != "caves" => path,
+        kind => {
+            let seed = args.next().and_then(|seed| seed.parse().ok()).unwrap_or(0);
+            let generator = if kind.as_deref() == Some("terrain") {
+                WorldGenerator::Terrain(TerrainGenerator::new(seed))
             } else {
-                0x0
+                WorldGenerator::Caves(CaveGenerator::new(seed))
+            };
+            // light bends through the water of the generated world
+            let mut materials = [VoxelMaterial::DEFAULT; 256];
+            materials[generator.layers().water as usize].flags = VoxelMaterial::TRANSPARENT;
+            main_materials.set(commands.spawn(VoxelMaterials::new(materials)).id());
+
+            let voxel_size = VOXEL_SCALE.recip();
+            commands.insert_resource(VoxelWorld::new(CHUNK_SIZE, voxel_size, VIEW_DISTANCE));
+            commands.insert_resource(generator);
+            return;
+        }
+    };
+    match load_models(&path) {
+        Ok((models, colors)) => {
+            if let Some(colors) = colors {
+                *colors_q.get_mut(**main_colors).unwrap() = colors;
             }
-        } else {
-            0b11000100
+            spawn_models(&mut commands, &renderer, &voxel_pipeline, models);
+        }
+        Err(e) => error!("Failed to load {path}: {e}"),
+    }
+    // a .gpl, .hex or .png palette given after the model replaces its colors
+    if let Some(path) = args.next() {
+        match VoxelColors::open(&path) {
+            Ok(colors) => main_colors.set(commands.spawn(colors).id()),
+            Err(e) => error!("Failed to load palette {path}: {e}"),
+        }
+    }
+}
+// Generates the missing chunks within view distance of the camera, nearest first.
+fn generate_chunks(
+    generator: Res<WorldGenerator>,
+    main_camera: Res<MainCamera>,
+    mut world: ResMut<VoxelWorld>,
+    transform_q: Query<&GlobalTransform>,
+) {
+    let Ok(camera) = transform_q.get(**main_camera) else {
+        return;
+    };
+    let (center, _) = world.split_position(world.voxel_position(camera.translation()));
+    let distance = world.view_distance as i32;
+    let mut missing: Vec<IVec3> = (-distance..=distance)
+        .flat_map(|z| (-distance..=distance).map(move |x| ivec3(x, 0, z)))
+        .map(|offset| offset + center * IVec3::new(1, 0, 1))
+        .filter(|&chunk| !world.contains_chunk(chunk))
+        .collect();
+    missing.sort_by_key(|&chunk| (chunk - center).xz().abs().max_element());
+    for chunk in missing.into_iter().take(CHUNKS_PER_FRAME) {
+        let mut voxel = Voxel::new(world.chunk_size());
+        generator.fill(&mut voxel, chunk * world.chunk_size().as_ivec3());
+        world.insert_chunk(chunk, voxel);
+    }
+}
+// The entity and voxel under the crosshair, the cursor is locked so it's always at the center of the screen.
+fn pick<'a>(
+    camera: &GlobalTransform,
+    voxels: impl Iterator<Item = (Entity, &'a Voxel, &'a GlobalTransform)>,
+) -> Option<(Entity, VoxelHit)> {
+    let origin = camera.translation();
+    let direction = camera.forward();
+    voxels
+        .filter_map(|(entity, voxel, transform)| {
+            let hit = voxel.raycast_world(transform, origin, direction, PICK_DISTANCE)?;
+            Some((entity, hit))
+        })
+        .min_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance))
+}
+fn highlight<'a>(
+    target: Option<(Entity, VoxelHit)>,
+    highlights: impl Iterator<Item = (Entity, Mut<'a, VoxelHighlight>)>,
+) {
+    for (entity, mut highlight) in highlights {
+        let position = target
+            .filter(|&(target, _)| target == entity)
+            .map(|(_, hit)| hit.position);
+        highlight.set_if_neq(VoxelHighlight(position));
+    }
+}
+// Shape edited around the voxel under the crosshair, picked with the number keys.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+enum BrushShape {
+    #[default]
+    Voxel,
+    Box,
+    Sphere,
+    // drills into or builds out of the face that is looked at
+    Cylinder,
+    // from the last voxel that was clicked
+    Line,
+    FloodFill,
+    // every voxel with the value of the clicked one, in every model
+    Replace,
+}
+#[derive(Resource, Default)]
+struct Brush {
+    shape: BrushShape,
+    // picked with the middle mouse button, otherwise the value of the clicked voxel is painted
+    value: Option<u8>,
+    line_start: Option<(Entity, IVec3)>,
+}
+// Region of the voxels between the corners a and b, both inclusive, without negative positions.
+fn region(a: IVec3, b: IVec3) -> VoxelRegion {
+    let min = a.min(b).max(IVec3::ZERO);
+    VoxelRegion::new(min.as_uvec3(), (a.max(b) + 1).max(min).as_uvec3())
+}
+fn select_brush(input: Res<ButtonInput<KeyCode>>, mut brush: ResMut<Brush>) {
+    const KEYS: [(KeyCode, BrushShape); 7] = [
+        (KeyCode::Digit1, BrushShape::Voxel),
+        (KeyCode::Digit2, BrushShape::Box),
+        (KeyCode::Digit3, BrushShape::Sphere),
+        (KeyCode::Digit4, BrushShape::Cylinder),
+        (KeyCode::Digit5, BrushShape::Line),
+        (KeyCode::Digit6, BrushShape::FloodFill),
+        (KeyCode::Digit7, BrushShape::Replace),
+    ];
+    for (key, shape) in KEYS {
+        if input.just_pressed(key) {
+            brush.shape = shape;
+            brush.line_start = None;
+            info!("Brush: {shape:?}");
+        }
+    }
+}
+// Highlights the voxel under the crosshair, left click erases the brush at it and right click paints
+// the brush against the face that is looked at. Middle click picks the value that is painted.
+fn interact_voxels(
+    main_camera: Res<MainCamera>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut brush: ResMut<Brush>,
+    mut history: ResMut<VoxelHistory>,
+    transform_q: Query<&GlobalTransform>,
+    mut voxel_q: Query<
+        (Entity, &mut Voxel, &GlobalTransform, &mut VoxelHighlight),
+        Without<VoxelChunk>,
+    >,
+) {
+    let Ok(camera) = transform_q.get(**main_camera) else {
+        return;
+    };
+    let voxels = voxel_q
+        .iter()
+        .map(|(entity, voxel, transform, _)| (entity, voxel, transform));
+    let target = pick(camera, voxels);
+    highlight(
+        target,
+        voxel_q
+            .iter_mut()
+            .map(|(entity, _, _, highlight)| (entity, highlight)),
+    );
+
+    let Some((entity, hit)) = target else {
+        return;
+    };
+    if mouse.just_pressed(MouseButton::Middle) {
+        brush.value = Some(hit.value);
+        return;
+    }
+    let erase = mouse.just_pressed(MouseButton::Left);
+    if !erase && !mouse.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let value = if erase {
+        0
+    } else {
+        brush.value.unwrap_or(hit.value)
+    };
+    // shapes are painted on top of the clicked voxel, unless the ray started inside of it
+    let position = hit.position.as_ivec3();
+    let center = if erase {
+        position
+    } else {
+        position + hit.normal
+    };
+    let radius = BRUSH_RADIUS as i32;
+    let (_, mut voxel, _, _) = voxel_q.get_mut(entity).unwrap();
+    let all = VoxelRegion::new(UVec3::ZERO, voxel.dimension());
+    let voxel = &mut voxel;
+    match brush.shape {
+        BrushShape::Voxel => {
+            history.edit_region(entity, voxel, region(center, center), |voxel| {
+                voxel.fill_box(center, center, value);
+            });
+            None
+        }
+        BrushShape::Box => {
+            let (min, max) = (center - radius, center + radius);
+            history.edit(entity, voxel, region(min, max), |voxel| {
+                voxel.fill_box(min, max, value)
+            })
+        }
+        BrushShape::Sphere => {
+            let bounds = region(center - radius, center + radius);
+            history.edit(entity, voxel, bounds, |voxel| {
+                voxel.fill_sphere(center.as_vec3() + 0.5, BRUSH_RADIUS, value)
+            })
+        }
+        BrushShape::Cylinder => {
+            let normal = if erase { -hit.normal } else { hit.normal };
+            let top = center + normal * radius * 2;
+            let bounds = region(center.min(top) - radius, center.max(top) + radius);
+            history.edit(entity, voxel, bounds, |voxel| {
+                let (base, top) = (center.as_vec3() + 0.5, top.as_vec3() + 0.5);
+                voxel.fill_cylinder(base, top, BRUSH_RADIUS, value)
+            })
+        }
+        BrushShape::Line => {
+            let start = match brush.line_start {
+                Some((start_entity, start)) if start_entity == entity => start,
+                _ => center,
+            };
+            brush.line_start = Some((entity, center));
+            history.edit(entity, voxel, region(start, center), |voxel| {
+                voxel.line(start, center, value)
+            })
+        }
+        BrushShape::FloodFill => history.edit(entity, voxel, all, |voxel| {
+            voxel.flood_fill(hit.position, value)
+        }),
+        BrushShape::Replace => {
+            // every model is recolored, undone as one step
+            history.begin();
+            for (entity, mut voxel, _, _) in voxel_q.iter_mut() {
+                let all = VoxelRegion::new(UVec3::ZERO, voxel.dimension());
+                history.edit(entity, &mut voxel, all, |voxel| {
+                    voxel.replace(hit.value, value)
+                });
+            }
+            history.end();
+            None
+        }
+    };
+}
+// Pasting with this rule never replaces stone, like the floors of the models spawned with N.
+fn keep_stone(current: u8, incoming: u8) -> u8 {
+    if current == TerrainLayers::default().stone {
+        current
+    } else {
+        incoming
+    }
+}
+const CONFLICT_RULES: [(&str, ConflictRule); 5] = [
+    ("overwrite", ConflictRule::Overwrite),
+    ("keep", ConflictRule::Keep),
+    ("max", ConflictRule::Max),
+    ("min", ConflictRule::Min),
+    ("keep stone", ConflictRule::Custom(keep_stone)),
+];
+#[derive(Resource, Default)]
+struct Clipboard {
+    voxel: Option<Voxel>,
+    rotation: VoxelRotation,
+    rule: usize, // of CONFLICT_RULES, for voxels set in both models
+}
+// Ctrl+C copies the model under the crosshair, R turns the copy a quarter around the axis the camera
+// looks along the most and T cycles the conflict rule. The copy is combined with the model under the
+// crosshair with its lowest corner at the highlighted voxel: Ctrl+V adds it, Ctrl+Shift+V stamps it
+// including its empty voxels, Ctrl+X carves it out and Ctrl+I keeps only what it overlaps.
+fn use_clipboard(
+    input: Res<ButtonInput<KeyCode>>,
+    main_camera: Res<MainCamera>,
+    mut clipboard: ResMut<Clipboard>,
+    mut history: ResMut<VoxelHistory>,
+    transform_q: Query<&GlobalTransform>,
+    mut voxel_q: Query<(Entity, &mut Voxel, &VoxelHighlight), Without<VoxelChunk>>,
+) {
+    if input.just_pressed(KeyCode::KeyR) {
+        let Ok(camera) = transform_q.get(**main_camera) else {
+            return;
+        };
+        let look = camera.forward().abs();
+        clipboard.rotation = match clipboard.rotation {
+            VoxelRotation::X(turns) if look.x >= look.y && look.x >= look.z => {
+                VoxelRotation::X((turns + 1) % 4)
+            }
+            VoxelRotation::Y(turns) if look.y > look.x && look.y >= look.z => {
+                VoxelRotation::Y((turns + 1) % 4)
+            }
+            VoxelRotation::Z(turns) if look.z > look.x && look.z > look.y => {
+                VoxelRotation::Z((turns + 1) % 4)
+            }
+            _ if look.x >= look.y && look.x >= look.z => VoxelRotation::X(1),
+            _ if look.y >= look.z => VoxelRotation::Y(1),
+            _ => VoxelRotation::Z(1),
         };
+        info!("Clipboard rotation: {:?}", clipboard.rotation);
+    }
+    if input.just_pressed(KeyCode::KeyT) {
+        clipboard.rule = (clipboard.rule + 1) % CONFLICT_RULES.len();
+        info!("Conflict rule: {}", CONFLICT_RULES[clipboard.rule].0);
+    }
+    if !input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let Some((entity, mut voxel, highlight)) = voxel_q
+        .iter_mut()
+        .find(|(.., highlight)| highlight.0.is_some())
+    else {
+        return;
+    };
+    if input.just_pressed(KeyCode::KeyC) {
+        clipboard.voxel = Some(voxel.clone());
+        clipboard.rotation = VoxelRotation::None;
+        info!("Copied a model of {}", voxel.dimension());
+        return;
+    }
+    let Some(copy) = &clipboard.voxel else {
+        return;
+    };
+    let offset = highlight.0.unwrap().as_ivec3();
+    let size = clipboard
+        .rotation
+        .rotate_dimension(copy.dimension())
+        .as_ivec3();
+    let covered = region(offset, offset + size - 1);
+    let all = VoxelRegion::new(UVec3::ZERO, voxel.dimension());
+    let (rotation, (_, rule)) = (clipboard.rotation, CONFLICT_RULES[clipboard.rule]);
+    let shift = input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let voxel = &mut voxel;
+    if input.just_pressed(KeyCode::KeyV) && shift {
+        history.edit(entity, voxel, covered, |voxel| {
+            voxel.stamp(copy, offset, rotation, rule)
+        });
+    } else if input.just_pressed(KeyCode::KeyV) {
+        history.edit(entity, voxel, covered, |voxel| {
+            voxel.union(copy, offset, rotation, rule)
+        });
+    } else if input.just_pressed(KeyCode::KeyX) {
+        history.edit(entity, voxel, covered, |voxel| {
+            voxel.subtract(copy, offset, rotation)
+        });
+    } else if input.just_pressed(KeyCode::KeyI) {
+        history.edit(entity, voxel, all, |voxel| {
+            voxel.intersect(copy, offset, rotation, rule)
+        });
+    }
+}
+// M switches the value picked with the middle mouse button between opaque and transparent.
+fn toggle_transparency(
+    input: Res<ButtonInput<KeyCode>>,
+    brush: Res<Brush>,
+    main_materials: Res<MainVoxelMaterials>,
+    mut materials_q: Query<&mut VoxelMaterials>,
+) {
+    if !input.just_pressed(KeyCode::KeyM) {
+        return;
+    }
+    let Some(value) = brush.value else {
+        warn!("Pick a value with the middle mouse button first");
+        return;
+    };
+    let mut materials = materials_q.get_mut(**main_materials).unwrap();
+    let material = &mut materials[value as usize];
+    material.flags ^= VoxelMaterial::TRANSPARENT;
+    let kind = if material.is_transparent() {
+        "transparent"
+    } else {
+        "opaque"
+    };
+    info!("Value {value} is now {kind}");
+}
+// O turns the model under the crosshair into an octree and Shift+O turns every octree back into a
+// dense model.
+fn convert_octrees(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    renderer: Res<Renderer>,
+    voxel_pipeline: Res<Pipeline>,
+    voxel_q: Query<(Entity, &Voxel, &Transform, &VoxelHighlight), Without<VoxelChunk>>,
+    octree_q: Query<(Entity, &VoxelOctree, &Transform)>,
+) {
+    if !input.just_pressed(KeyCode::KeyO) {
+        return;
+    }
+    if input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        for (entity, octree, transform) in &octree_q {
+            let mut bundle = VoxelBundle::from_voxel(octree.to_voxel(), &renderer, &voxel_pipeline);
+            bundle.transform = TransformBundle::from_transform(*transform);
+            commands
+                .entity(entity)
+                .remove::<VoxelOctreeBundle>()
+                .insert(bundle);
+        }
+        return;
+    }
+    let Some((entity, voxel, transform, _)) =
+        voxel_q.iter().find(|(.., highlight)| highlight.0.is_some())
+    else {
+        return;
+    };
+    let octree = VoxelOctree::from_voxel(voxel);
+    let mut bundle = VoxelOctreeBundle::new(octree, &renderer, &voxel_pipeline);
+    bundle.transform = TransformBundle::from_transform(*transform);
+    commands
+        .entity(entity)
+        .remove::<VoxelBundle>()
+        .insert((bundle, VoxelHighlight::default()));
+}
+// Left click removes the voxel of an octree under the crosshair and right click places a copy of it,
+// like the voxel brush. Octrees are only picked on clicks since that takes a dense copy of them.
+fn interact_octrees(
+    main_camera: Res<MainCamera>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    transform_q: Query<&GlobalTransform>,
+    voxel_q: Query<(Entity, &Voxel, &GlobalTransform)>,
+    mut octree_q: Query<(Entity, &mut VoxelOctree, &GlobalTransform)>,
+) {
+    let erase = mouse.just_pressed(MouseButton::Left);
+    if !erase && !mouse.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let Ok(camera) = transform_q.get(**main_camera) else {
+        return;
+    };
+    let dense: Vec<_> = octree_q
+        .iter()
+        .map(|(entity, octree, transform)| (entity, octree.to_voxel(), *transform))
+        .collect();
+    let voxels = dense
+        .iter()
+        .map(|(entity, voxel, transform)| (*entity, voxel, transform));
+    let Some((entity, hit)) = pick(camera, voxels) else {
+        return;
+    };
+    // dense models in front of the octree are edited by interact_voxels instead
+    if pick(camera, voxel_q.iter()).is_some_and(|(_, nearer)| nearer.distance < hit.distance) {
+        return;
+    }
+    let (_, mut octree, _) = octree_q.get_mut(entity).unwrap();
+    if erase {
+        octree.set(hit.position, 0);
+    } else if hit.normal != IVec3::ZERO {
+        let position = hit.position.as_ivec3() + hit.normal;
+        if position.cmpge(IVec3::ZERO).all() && octree.get(position.as_uvec3()) == Some(0) {
+            octree.set(position.as_uvec3(), hit.value);
+        }
+    }
+}
+// G pads the model under the crosshair with RESIZE_MARGIN empty voxels on every side and Shift+G
+// crops as many away, without moving the voxels that are kept.
+fn resize_model(
+    input: Res<ButtonInput<KeyCode>>,
+    mut voxel_q: Query<(&mut Voxel, &mut Transform, &VoxelHighlight), Without<VoxelChunk>>,
+) {
+    if !input.just_pressed(KeyCode::KeyG) {
+        return;
+    }
+    let Some((mut voxel, mut transform, _)) = voxel_q
+        .iter_mut()
+        .find(|(.., highlight)| highlight.0.is_some())
+    else {
+        return;
+    };
+    let dimension = voxel.dimension();
+    let resized = if input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        dimension
+            .saturating_sub(UVec3::splat(RESIZE_MARGIN * 2))
+            .max(UVec3::ONE)
+    } else {
+        dimension + RESIZE_MARGIN * 2
+    };
+    transform.scale *= resized.as_vec3() / dimension.as_vec3();
+    voxel.resize(resized, Vec3::splat(0.5));
+}
+// H spawns a cube of 16 bit voxels in front of the camera with a color for every voxel, more colors than
+// an 8 bit palette can hold.
+fn spawn_color_cube(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    renderer: Res<Renderer>,
+    voxel_pipeline: Res<Pipeline>,
+    main_large_colors: Res<MainLargeVoxelColors>,
+    mut large_colors_q: Query<&mut LargeVoxelColors>,
+    camera_q: Query<&GlobalTransform, With<Camera>>,
+) {
+    if !input.just_pressed(KeyCode::KeyH) {
+        return;
+    }
+    let Ok(camera) = camera_q.get_single() else {
+        return;
+    };
+    let dimension = UVec3::splat(COLOR_CUBE_STEPS);
+    // index 0 stays empty, every other one is a color of the cube
+    let colors = (0..dimension.x * dimension.y * dimension.z).map(|i| {
+        let level =
+            Voxel::get_position(dimension, i as usize).unwrap() * 255 / (COLOR_CUBE_STEPS - 1);
+        [level.x as u8, level.y as u8, level.z as u8, 255]
+    });
+    let colors = LargeVoxelColors::new(std::iter::once([0; 4]).chain(colors).collect());
+    *large_colors_q.get_mut(**main_large_colors).unwrap() = colors;
+
+    let mut voxel = Voxel::<u16>::new(dimension);
+    voxel.for_each_mut(|value, position| {
+        *value = Voxel::get_index(dimension, position).unwrap() as u16 + 1;
     });
+    let mut bundle = VoxelBundle::from_voxel(voxel, &renderer, &voxel_pipeline);
+    let size = dimension.as_vec3() * 4.0 / VOXEL_SCALE;
+    let translation = camera.translation() + camera.forward() * size.max_element();
+    bundle.transform =
+        TransformBundle::from_transform(Transform::from_translation(translation).with_scale(size));
+    commands.spawn(bundle);
+}
+// N spawns an empty model with a stone floor in front of the camera to try the brushes on.
+fn spawn_blank_model(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    renderer: Res<Renderer>,
+    voxel_pipeline: Res<Pipeline>,
+    main_camera: Res<MainCamera>,
+    transform_q: Query<&GlobalTransform>,
+) {
+    if !input.just_pressed(KeyCode::KeyN) {
+        return;
+    }
+    let Ok(camera) = transform_q.get(**main_camera) else {
+        return;
+    };
+    let mut bundle = VoxelBundle::new(BLANK_DIMENSION, &renderer, &voxel_pipeline);
+    let floor = BLANK_DIMENSION.as_ivec3() * IVec3::new(1, 0, 1) - IVec3::new(1, 0, 1);
+    bundle
+        .voxel
+        .fill_box(IVec3::ZERO, floor, TerrainLayers::default().stone);
+    let size = BLANK_DIMENSION.as_vec3() / VOXEL_SCALE;
+    let translation = camera.translation() + camera.forward() * size.max_element();
+    bundle.transform =
+        TransformBundle::from_transform(Transform::from_translation(translation).with_scale(size));
+    commands.spawn((bundle, VoxelHighlight::default()));
+}
+type ChunkQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Voxel,
+        &'static GlobalTransform,
+        &'static VoxelChunk,
+    ),
+>;
+// Like interact_voxels for the chunks of the world. Edits go through world coordinates, so voxels placed
+// against the border of a chunk end up in its neighbour. They aren't recorded in the history, chunk
+// entities come and go as the camera moves.
+fn interact_world(
+    main_camera: Res<MainCamera>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    transform_q: Query<&GlobalTransform>,
+    mut highlight_q: Query<(Entity, &mut VoxelHighlight), With<VoxelChunk>>,
+    mut chunks: ParamSet<(ChunkQuery, WorldVoxels)>,
+) {
+    let Ok(camera) = transform_q.get(**main_camera) else {
+        return;
+    };
+    let chunk_q = chunks.p0();
+    let voxels = chunk_q
+        .iter()
+        .map(|(entity, voxel, transform, _)| (entity, voxel, transform));
+    let target = pick(camera, voxels);
+    highlight(target, highlight_q.iter_mut());
+
+    let Some((entity, hit)) = target else {
+        return;
+    };
+    let chunk = **chunk_q.get(entity).unwrap().3;
+    let mut voxels = chunks.p1();
+    let position = chunk * voxels.world().chunk_size().as_ivec3() + hit.position.as_ivec3();
+    if mouse.just_pressed(MouseButton::Left) {
+        voxels.set(position, 0);
+    } else if mouse.just_pressed(MouseButton::Right) && hit.normal != IVec3::ZERO {
+        let position = position + hit.normal;
+        if voxels.get(position) == 0 {
+            voxels.set(position, hit.value);
+        }
+    }
+}
+// Ctrl+Z undoes the last edit, Ctrl+Y or Ctrl+Shift+Z redoes it.
+fn undo_voxel_edits(
+    input: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<VoxelHistory>,
+    mut voxel_q: Query<&mut Voxel>,
+) {
+    if !input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let shift = input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if input.just_pressed(KeyCode::KeyY) || (shift && input.just_pressed(KeyCode::KeyZ)) {
+        history.redo(&mut voxel_q);
+    } else if input.just_pressed(KeyCode::KeyZ) {
+        history.undo(&mut voxel_q);
+    }
+}
+// F12 exports every voxel as one blocky mesh and F11 as one smooth mesh, placed where they are drawn.
+// F10 exports the blocky mesh as an .obj textured with the palette.
+fn export_meshes(
+    input: Res<ButtonInput<KeyCode>>,
+    main_colors: Res<MainVoxelColors>,
+    colors_q: Query<&VoxelColors>,
+    voxel_q: Query<(&Voxel, &GlobalTransform)>,
+) {
+    let smooth = input.just_pressed(KeyCode::F11);
+    let obj = input.just_pressed(KeyCode::F10);
+    if !smooth && !obj && !input.just_pressed(KeyCode::F12) {
+        return;
+    }
+    let colors = colors_q.get(**main_colors).unwrap();
+    let mut mesh = VoxelMesh::default();
+    for (voxel, transform) in &voxel_q {
+        let mut part = if smooth {
+            voxel.surface_nets(colors)
+        } else {
+            voxel.greedy_mesh(colors)
+        };
+        part.transform_to_world(transform, voxel.dimension());
+        mesh.append(&part);
+    }
+    let (path, result) = if smooth {
+        (
+            SMOOTH_EXPORT_PATH,
+            mesh.save_ply(SMOOTH_EXPORT_PATH).map_err(ImageError::from),
+        )
+    } else if obj {
+        let colors = MeshColors::PaletteTexture(colors);
+        (OBJ_EXPORT_PATH, mesh.save_obj(OBJ_EXPORT_PATH, colors))
+    } else {
+        (EXPORT_PATH, mesh.save_glb(EXPORT_PATH, MeshColors::Vertex))
+    };
+    match result {
+        Ok(()) => info!("Exported voxels to {path}"),
+        Err(e) => error!("Failed to export {path}: {e}"),
+    }
+}
+// F9 saves the model under the crosshair as a MagicaVoxel file and F8 in the native format, both
+// with the current palette. F7 saves it as PNG slices cut along the axis the camera looks along the
+// most, together with the palette.
+fn save_model(
+    input: Res<ButtonInput<KeyCode>>,
+    main_camera: Res<MainCamera>,
+    main_colors: Res<MainVoxelColors>,
+    colors_q: Query<&VoxelColors>,
+    transform_q: Query<&GlobalTransform>,
+    voxel_q: Query<(&Voxel, &VoxelHighlight)>,
+) {
+    let native = input.just_pressed(KeyCode::F8);
+    let slices = input.just_pressed(KeyCode::F7);
+    if !native && !slices && !input.just_pressed(KeyCode::F9) {
+        return;
+    }
+    let Some((voxel, _)) = voxel_q.iter().find(|(_, highlight)| highlight.0.is_some()) else {
+        warn!("Look at a model to save it");
+        return;
+    };
+    let colors = colors_q.get(**main_colors).unwrap();
+    if slices {
+        let Ok(camera) = transform_q.get(**main_camera) else {
+            return;
+        };
+        let look = camera.forward().abs();
+        let (axis, name) = if look.x >= look.y && look.x >= look.z {
+            (SliceAxis::X, "x")
+        } else if look.y >= look.z {
+            (SliceAxis::Y, "y")
+        } else {
+            (SliceAxis::Z, "z")
+        };
+        let directory = format!("{SLICES_EXPORT_PATH}_{name}");
+        let result = voxel
+            .save_slices(&directory, colors, axis)
+            .map_err(PaletteError::from)
+            .and_then(|paths| {
+                colors.save(std::path::Path::new(&directory).join(SLICES_PALETTE))?;
+                Ok(paths)
+            });
+        match result {
+            Ok(paths) => info!("Saved {} slices to {directory}", paths.len()),
+            Err(e) => error!("Failed to save slices to {directory}: {e}"),
+        }
+        return;
+    }
+    let (path, result) = if native {
+        (
+            NATIVE_EXPORT_PATH,
+            voxel.save(Some(colors), NATIVE_EXPORT_PATH),
+        )
+    } else {
+        (VOX_EXPORT_PATH, voxel.save_vox(colors, VOX_EXPORT_PATH))
+    };
+    match result {
+        Ok(()) => info!("Saved model to {path}"),
+        Err(e) => error!("Failed to save {path}: {e}"),
+    }
 }
 fn camera_movement(
     mut camera_q: Query<&mut Transform, With<Camera>>,
@@ -125,7 +1000,33 @@ fn main() {
     App::new()
         .add_plugins((DefaultPlugins.set(window_plugin), RenderPlugin, VoxelPlugin))
         .insert_resource(ClearColor(wgpu::Color::BLACK))
-        .add_systems(Startup, (setup, set_voxel.after(setup)))
-        .add_systems(Update, camera_movement)
+        .init_resource::<VoxelHistory>()
+        .init_resource::<Brush>()
+        .init_resource::<Clipboard>()
+        .add_systems(Startup, (setup, spawn_scene))
+        .add_systems(
+            Update,
+            (
+                camera_movement,
+                select_brush,
+                generate_chunks
+                    .after(camera_movement)
+                    .run_if(contains_resource::<VoxelWorld>),
+                interact_voxels.after(camera_movement).after(select_brush),
+                interact_world
+                    .after(camera_movement)
+                    .run_if(contains_resource::<VoxelWorld>),
+                spawn_blank_model.after(camera_movement),
+                spawn_color_cube.after(camera_movement),
+                convert_octrees.after(interact_voxels),
+                use_clipboard.after(interact_voxels),
+                toggle_transparency.after(interact_voxels),
+                resize_model.after(interact_voxels),
+                interact_octrees.after(camera_movement),
+                undo_voxel_edits.after(interact_voxels),
+                export_meshes,
+                save_model,
+            ),
+        )
         .run();
 }
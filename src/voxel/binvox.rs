@@ -0,0 +1,217 @@
+use super::bytes::voxel_count;
+use crate::*;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum BinvoxError {
+    Io(io::Error),
+    InvalidHeader(&'static str),
+    InvalidData(&'static str),
+}
+impl fmt::Display for BinvoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::InvalidHeader(reason) => write!(f, "invalid binvox header: {reason}"),
+            Self::InvalidData(reason) => write!(f, "invalid binvox data: {reason}"),
+        }
+    }
+}
+impl std::error::Error for BinvoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for BinvoxError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+// Occupancy grid written by binvox and similar mesh voxelizers. Filled voxels are palette index 1,
+// which is white in colors. translate and scale place the grid in the units of the original mesh.
+pub struct Binvox {
+    pub voxel: Voxel,
+    pub colors: VoxelColors,
+    pub translate: Vec3,
+    pub scale: f32,
+}
+impl Binvox {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, BinvoxError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinvoxError> {
+        let mut rest = bytes;
+        let mut next_line = || -> Result<&str, BinvoxError> {
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or(BinvoxError::InvalidHeader("missing data line"))?;
+            let line = std::str::from_utf8(&rest[..end])
+                .map_err(|_| BinvoxError::InvalidHeader("not text"))?;
+            rest = &rest[end + 1..];
+            Ok(line.trim())
+        };
+        if !next_line()?.starts_with("#binvox") {
+            return Err(BinvoxError::InvalidHeader("missing #binvox"));
+        }
+        let mut size = None;
+        let mut translate = Vec3::ZERO;
+        let mut scale = 1.0;
+        loop {
+            let line = next_line()?;
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let values: Vec<&str> = words.collect();
+            let parse = |reason| {
+                values
+                    .iter()
+                    .map(|value| value.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| BinvoxError::InvalidHeader(reason))
+            };
+            match keyword {
+                "dim" => {
+                    let dim = values
+                        .iter()
+                        .map(|value| value.parse::<u32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .ok()
+                        .and_then(|dim| <[u32; 3]>::try_from(dim).ok())
+                        .ok_or(BinvoxError::InvalidHeader("invalid dim"))?;
+                    if voxel_count(UVec3::from_array(dim))
+                        .filter(|&count| count > 0)
+                        .is_none()
+                    {
+                        return Err(BinvoxError::InvalidHeader("invalid dim"));
+                    }
+                    size = Some(dim);
+                }
+                "translate" => {
+                    let values = parse("invalid translate")?;
+                    translate = Vec3::from_slice(
+                        values
+                            .get(..3)
+                            .ok_or(BinvoxError::InvalidHeader("invalid translate"))?,
+                    );
+                }
+                "scale" => {
+                    scale = *parse("invalid scale")?
+                        .first()
+                        .ok_or(BinvoxError::InvalidHeader("invalid scale"))?;
+                }
+                "data" => break,
+                _ => {}
+            }
+        }
+        // dim is depth, height and width, with y running fastest in the data, then z, then x
+        let [depth, height, width] = size.ok_or(BinvoxError::InvalidHeader("missing dim"))?;
+        let dimension = uvec3(depth, width, height);
+        let count = voxel_count(dimension).unwrap();
+
+        // run length encoded as pairs of a value and a count
+        let mut voxel = Voxel::new(dimension);
+        let mut index = 0;
+        for run in rest.chunks_exact(2) {
+            let (value, len) = (run[0], run[1] as usize);
+            if index + len > count {
+                return Err(BinvoxError::InvalidData("too many voxels"));
+            }
+            if value != 0 {
+                for i in index..index + len {
+                    let (width, height) = (width as usize, height as usize);
+                    let position = uvec3(
+                        (i / (width * height)) as u32,
+                        (i % width) as u32,
+                        (i / width % height) as u32,
+                    );
+                    *voxel.get_mut(position).unwrap() = 1;
+                }
+            }
+            index += len;
+        }
+        if index < count {
+            return Err(BinvoxError::InvalidData("not enough voxels"));
+        }
+
+        let mut colors = [[0; 4]; 256];
+        colors[1] = [255; 4];
+        Ok(Self {
+            voxel,
+            colors: VoxelColors::new(colors),
+            translate,
+            scale,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binvox(header: &str, runs: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("#binvox 1\n{header}data\n").into_bytes();
+        bytes.extend_from_slice(runs);
+        bytes
+    }
+    fn filled(voxel: &Voxel) -> Vec<UVec3> {
+        VoxelRegion::new(UVec3::ZERO, voxel.dimension())
+            .positions()
+            .filter(|&position| voxel.get(position) == Some(&1))
+            .collect()
+    }
+
+    #[test]
+    fn header_is_read() {
+        let bytes = binvox("dim 2 2 2\ntranslate 1 -2 3.5\nscale 0.5\n", &[1, 8]);
+        let binvox = Binvox::from_bytes(&bytes).unwrap();
+        assert_eq!(binvox.voxel.dimension(), UVec3::splat(2));
+        assert_eq!(binvox.translate, vec3(1.0, -2.0, 3.5));
+        assert_eq!(binvox.scale, 0.5);
+        assert_eq!(filled(&binvox.voxel).len(), 8);
+        assert_eq!(binvox.colors[0], [0; 4]);
+        assert_eq!(binvox.colors[1], [255; 4]);
+    }
+
+    #[test]
+    fn axes_are_remapped() {
+        // x is the first dim, y the last and runs fastest, then z
+        let bytes = binvox("dim 2 3 4\n", &[0, 5, 1, 1, 0, 17, 1, 1]);
+        let voxel = Binvox::from_bytes(&bytes).unwrap().voxel;
+        assert_eq!(voxel.dimension(), uvec3(2, 4, 3));
+        assert_eq!(filled(&voxel), [uvec3(0, 1, 1), uvec3(1, 3, 2)]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let invalid_header = |bytes: &[u8], expected: &str| {
+            assert!(matches!(
+                Binvox::from_bytes(bytes),
+                Err(BinvoxError::InvalidHeader(reason)) if reason == expected
+            ));
+        };
+        invalid_header(b"#binvox 1\ndim 2 2 2\n", "missing data line");
+        invalid_header(b"#voxels\ndim 2 2 2\ndata\n", "missing #binvox");
+        invalid_header(&binvox("", &[1, 8]), "missing dim");
+        invalid_header(&binvox("dim 2 2\n", &[1, 4]), "invalid dim");
+        invalid_header(&binvox("dim 0 2 2\n", &[]), "invalid dim");
+        invalid_header(&binvox("dim 2048 2048 2048\n", &[]), "invalid dim");
+        invalid_header(&binvox("dim 2 2 2\nscale\n", &[1, 8]), "invalid scale");
+
+        let invalid_data = |runs: &[u8], expected: &str| {
+            assert!(matches!(
+                Binvox::from_bytes(&binvox("dim 2 2 2\n", runs)),
+                Err(BinvoxError::InvalidData(reason)) if reason == expected
+            ));
+        };
+        invalid_data(&[1, 7], "not enough voxels");
+        invalid_data(&[1, 4, 0], "not enough voxels");
+        invalid_data(&[1, 4, 0, 5], "too many voxels");
+    }
+}
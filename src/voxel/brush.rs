@@ -0,0 +1,335 @@
+use crate::*;
+
+// Axis aligned box of voxel positions, min is inclusive and max is exclusive.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VoxelRegion {
+    pub min: UVec3,
+    pub max: UVec3,
+}
+impl VoxelRegion {
+    pub const fn new(min: UVec3, max: UVec3) -> Self {
+        Self { min, max }
+    }
+    pub fn from_position(position: UVec3) -> Self {
+        Self::new(position, position + 1)
+    }
+    pub fn size(&self) -> UVec3 {
+        self.max.saturating_sub(self.min)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpge(self.max).any()
+    }
+    pub fn extend(&mut self, position: UVec3) {
+        self.min = self.min.min(position);
+        self.max = self.max.max(position + 1);
+    }
+    pub fn positions(&self) -> impl Iterator<Item = UVec3> {
+        let Self { min, max } = *self;
+        (min.z..max.z).flat_map(move |z| {
+            (min.y..max.y).flat_map(move |y| (min.x..max.x).map(move |x| uvec3(x, y, z)))
+        })
+    }
+}
+
+// Brushes clip to the volume, so shapes may extend past its bounds or lie entirely outside of it.
+// Each returns the region of voxels whose value changed, or None if nothing changed.
+impl<T: VoxelElement> Voxel<T> {
+    pub(super) fn paint(&mut self, position: UVec3, value: T, changed: &mut Option<VoxelRegion>) {
+        if self.get(position) == Some(&value) {
+            return;
+        }
+        *self.get_mut(position).unwrap() = value;
+        match changed {
+            Some(region) => region.extend(position),
+            None => *changed = Some(VoxelRegion::from_position(position)),
+        }
+    }
+    // Clips the inclusive box between a and b to the volume.
+    pub(super) fn clip(&self, a: IVec3, b: IVec3) -> Option<VoxelRegion> {
+        let min = a.min(b).max(IVec3::ZERO);
+        let max = (a.max(b) + 1).min(self.dimension().as_ivec3());
+        let region = VoxelRegion::new(min.as_uvec3(), max.max(min).as_uvec3());
+        (!region.is_empty()).then_some(region)
+    }
+    fn fill_where(
+        &mut self,
+        bounds: Option<VoxelRegion>,
+        value: T,
+        mut inside: impl FnMut(Vec3) -> bool,
+    ) -> Option<VoxelRegion> {
+        let mut changed = None;
+        for position in bounds.iter().flat_map(VoxelRegion::positions) {
+            if inside(position.as_vec3() + 0.5) {
+                self.paint(position, value, &mut changed);
+            }
+        }
+        changed
+    }
+
+    // Fills every voxel between the corners a and b, both inclusive.
+    pub fn fill_box(&mut self, a: IVec3, b: IVec3, value: T) -> Option<VoxelRegion> {
+        self.fill_where(self.clip(a, b), value, |_| true)
+    }
+    // Fills the voxels whose centers are within radius of center, in voxel units.
+    pub fn fill_sphere(&mut self, center: Vec3, radius: f32, value: T) -> Option<VoxelRegion> {
+        let bounds = self.clip(
+            (center - radius).floor().as_ivec3(),
+            (center + radius).floor().as_ivec3(),
+        );
+        self.fill_where(bounds, value, |point| {
+            point.distance_squared(center) <= radius * radius
+        })
+    }
+    // Fills the voxels whose centers are within radius of the segment from base to top,
+    // without going past the flat caps at either end.
+    pub fn fill_cylinder(
+        &mut self,
+        base: Vec3,
+        top: Vec3,
+        radius: f32,
+        value: T,
+    ) -> Option<VoxelRegion> {
+        let bounds = self.clip(
+            (base.min(top) - radius).floor().as_ivec3(),
+            (base.max(top) + radius).floor().as_ivec3(),
+        );
+        let axis = top - base;
+        let length_squared = axis.length_squared();
+        self.fill_where(bounds, value, |point| {
+            let t = if length_squared > 0.0 {
+                (point - base).dot(axis) / length_squared
+            } else {
+                0.0
+            };
+            (0.0..=1.0).contains(&t) && point.distance_squared(base + axis * t) <= radius * radius
+        })
+    }
+    // Draws a 6-connected line between the voxels from and to, both inclusive.
+    pub fn line(&mut self, from: IVec3, to: IVec3, value: T) -> Option<VoxelRegion> {
+        let dimension = self.dimension().as_ivec3();
+        let delta = to - from;
+        let step = delta.signum();
+        let size = delta.abs();
+        let mut position = from;
+        // error terms of a 3D Bresenham walk that moves along a single axis per step
+        let mut progress = IVec3::ZERO;
+        let mut changed = None;
+        loop {
+            if position.cmpge(IVec3::ZERO).all() && position.cmplt(dimension).all() {
+                self.paint(position.as_uvec3(), value, &mut changed);
+            }
+            if position == to {
+                return changed;
+            }
+            // step along the axis that is furthest behind the ideal line
+            let lag = (progress + 1).as_vec3() / size.max(IVec3::ONE).as_vec3();
+            let mut axis = 0;
+            for i in 1..3 {
+                if size[i] > 0 && (size[axis] == 0 || lag[i] < lag[axis]) {
+                    axis = i;
+                }
+            }
+            position[axis] += step[axis];
+            progress[axis] += 1;
+        }
+    }
+    // Replaces the 6-connected area of voxels that have the same value as start.
+    pub fn flood_fill(&mut self, start: UVec3, value: T) -> Option<VoxelRegion> {
+        let target = *self.get(start)?;
+        if target == value {
+            return None;
+        }
+        let dimension = self.dimension();
+        let mut changed = None;
+        let mut stack = vec![start];
+        while let Some(position) = stack.pop() {
+            if self.get(position) != Some(&target) {
+                continue;
+            }
+            self.paint(position, value, &mut changed);
+            for axis in 0..3 {
+                if position[axis] > 0 {
+                    let mut next = position;
+                    next[axis] -= 1;
+                    stack.push(next);
+                }
+                if position[axis] + 1 < dimension[axis] {
+                    let mut next = position;
+                    next[axis] += 1;
+                    stack.push(next);
+                }
+            }
+        }
+        changed
+    }
+    // Replaces every voxel of the value from with to.
+    pub fn replace(&mut self, from: T, to: T) -> Option<VoxelRegion> {
+        if from == to {
+            return None;
+        }
+        let region = VoxelRegion::new(UVec3::ZERO, self.dimension());
+        let mut changed = None;
+        for position in region.positions() {
+            if self.get(position) == Some(&from) {
+                self.paint(position, to, &mut changed);
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIMENSION: UVec3 = UVec3::splat(8);
+
+    // Checks every voxel, found through get_position, against expected, and returns the region
+    // around the voxels that have value.
+    fn check(voxel: &Voxel, value: u8, expected: impl Fn(UVec3) -> bool) -> Option<VoxelRegion> {
+        let count = (DIMENSION.x * DIMENSION.y * DIMENSION.z) as usize;
+        let mut region: Option<VoxelRegion> = None;
+        for i in 0..count {
+            let position = Voxel::get_position(DIMENSION, i).unwrap();
+            assert_eq!(Voxel::get_index(DIMENSION, position), Some(i));
+            let filled = *voxel.get(position).unwrap() == value;
+            assert_eq!(filled, expected(position), "{position}");
+            if filled {
+                match &mut region {
+                    Some(region) => region.extend(position),
+                    None => region = Some(VoxelRegion::from_position(position)),
+                }
+            }
+        }
+        region
+    }
+
+    #[test]
+    fn box_clips_to_the_volume() {
+        let mut voxel: Voxel = Voxel::new(DIMENSION);
+        let region = voxel.fill_box(ivec3(2, 1, 20), ivec3(-3, -3, -3), 1);
+        assert_eq!(region, Some(VoxelRegion::new(UVec3::ZERO, uvec3(3, 2, 8))));
+        assert_eq!(check(&voxel, 1, |p| p.x <= 2 && p.y <= 1), region);
+
+        // nothing changes the second time or outside of the volume
+        assert_eq!(voxel.fill_box(ivec3(2, 1, 20), ivec3(-3, -3, -3), 1), None);
+        assert_eq!(voxel.fill_box(ivec3(8, 0, 0), ivec3(12, 4, 4), 2), None);
+        assert_eq!(voxel.fill_box(ivec3(-5, -5, -5), ivec3(-1, 4, 4), 2), None);
+        check(&voxel, 2, |_| false);
+    }
+
+    #[test]
+    fn sphere_clips_to_the_volume() {
+        let mut voxel: Voxel = Voxel::new(DIMENSION);
+        let center = vec3(1.0, 7.5, 2.0);
+        let region = voxel.fill_sphere(center, 3.0, 1);
+        let inside = |p: UVec3| (p.as_vec3() + 0.5).distance_squared(center) <= 9.0;
+        assert_eq!(check(&voxel, 1, inside), region);
+        assert_eq!(region.unwrap().max.y, 8);
+
+        assert_eq!(voxel.fill_sphere(vec3(-4.0, 4.0, 4.0), 3.0, 2), None);
+        assert_eq!(voxel.fill_sphere(vec3(4.0, 4.0, 20.0), 3.0, 2), None);
+        check(&voxel, 2, |_| false);
+    }
+
+    #[test]
+    fn cylinder_clips_to_the_volume() {
+        let mut voxel: Voxel = Voxel::new(DIMENSION);
+        let region = voxel.fill_cylinder(vec3(4.0, -10.0, 4.0), vec3(4.0, 20.0, 4.0), 1.5, 1);
+        let inside = |p: UVec3| (p.as_vec3() + 0.5).xz().distance_squared(vec2(4.0, 4.0)) <= 2.25;
+        assert_eq!(check(&voxel, 1, inside), region);
+        assert_eq!(
+            region,
+            Some(VoxelRegion::new(uvec3(3, 0, 3), uvec3(5, 8, 5)))
+        );
+
+        // the flat caps end the cylinder
+        let region = voxel.fill_cylinder(vec3(2.5, 5.5, 4.5), vec3(4.5, 5.5, 4.5), 0.5, 3);
+        assert_eq!(
+            check(&voxel, 3, |p| p.y == 5
+                && p.z == 4
+                && (2..=4).contains(&p.x)),
+            region
+        );
+
+        assert_eq!(
+            voxel.fill_cylinder(vec3(-5.0, 0.0, 0.0), vec3(-5.0, 8.0, 0.0), 2.0, 2),
+            None
+        );
+        check(&voxel, 2, |_| false);
+    }
+
+    #[test]
+    fn line_includes_both_endpoints() {
+        let mut voxel: Voxel = Voxel::new(DIMENSION);
+        let region = voxel.line(ivec3(1, 6, 0), ivec3(6, 3, 2), 1);
+        assert_eq!(voxel.get(uvec3(1, 6, 0)), Some(&1));
+        assert_eq!(voxel.get(uvec3(6, 3, 2)), Some(&1));
+        assert_eq!(
+            region,
+            Some(VoxelRegion::new(uvec3(1, 3, 0), uvec3(7, 7, 3)))
+        );
+        // 6-connected, so one voxel per step along each axis
+        let count = VoxelRegion::new(UVec3::ZERO, DIMENSION)
+            .positions()
+            .filter(|&p| voxel.get(p) == Some(&1))
+            .count();
+        assert_eq!(count, 5 + 3 + 2 + 1);
+
+        // only the part inside the volume is drawn
+        let mut voxel: Voxel = Voxel::new(DIMENSION);
+        let region = voxel.line(ivec3(-3, 2, 2), ivec3(2, 2, 2), 1);
+        assert_eq!(
+            region,
+            Some(VoxelRegion::new(uvec3(0, 2, 2), uvec3(3, 3, 3)))
+        );
+        assert_eq!(
+            check(&voxel, 1, |p| p.x <= 2 && p.y == 2 && p.z == 2),
+            region
+        );
+        assert_eq!(voxel.line(ivec3(-3, 2, 2), ivec3(-1, 9, 2), 1), None);
+
+        let region = voxel.line(ivec3(5, 5, 5), ivec3(5, 5, 5), 2);
+        assert_eq!(region, Some(VoxelRegion::from_position(uvec3(5, 5, 5))));
+    }
+
+    #[test]
+    fn flood_fill_stays_inside_its_area() {
+        let mut voxel: Voxel = Voxel::new(DIMENSION);
+        // a wall at x = 3 splits the volume in two
+        voxel.fill_box(ivec3(3, 0, 0), ivec3(3, 7, 7), 9);
+        let region = voxel.flood_fill(uvec3(1, 4, 6), 5);
+        assert_eq!(region, Some(VoxelRegion::new(UVec3::ZERO, uvec3(3, 8, 8))));
+        assert_eq!(check(&voxel, 5, |p| p.x < 3), region);
+        check(&voxel, 9, |p| p.x == 3);
+        check(&voxel, 0, |p| p.x > 3);
+
+        assert_eq!(voxel.flood_fill(uvec3(1, 4, 6), 5), None);
+        assert_eq!(voxel.flood_fill(uvec3(8, 0, 0), 5), None);
+    }
+
+    #[test]
+    fn replace_changes_only_matching_voxels() {
+        let mut voxel: Voxel = Voxel::new(DIMENSION);
+        voxel.fill_box(ivec3(1, 2, 3), ivec3(2, 2, 3), 4);
+        voxel.fill_box(ivec3(6, 5, 1), ivec3(6, 5, 1), 4);
+        voxel.fill_box(ivec3(0, 0, 0), ivec3(0, 0, 7), 6);
+
+        let region = voxel.replace(4, 7);
+        assert_eq!(
+            region,
+            Some(VoxelRegion::new(uvec3(1, 2, 1), uvec3(7, 6, 4)))
+        );
+        let was_four =
+            |p: UVec3| (p.y == 2 && p.z == 3 && (1..=2).contains(&p.x)) || p == uvec3(6, 5, 1);
+        assert_eq!(
+            check(&voxel, 7, was_four),
+            Some(VoxelRegion::new(uvec3(1, 2, 1), uvec3(7, 6, 4)))
+        );
+        check(&voxel, 4, |_| false);
+        check(&voxel, 6, |p| p.x == 0 && p.y == 0);
+
+        assert_eq!(voxel.replace(4, 8), None);
+        assert_eq!(voxel.replace(6, 6), None);
+    }
+}
@@ -1,63 +1,228 @@
 use crate::*;
 use wgpu::*;
 
+use std::marker::PhantomData;
 use std::mem::MaybeUninit;
+use std::ops::Range;
+
+// The w component of the buffer header tells the shader how the voxels are stored.
+// Its low 8 bits are the storage kind, for dense storage the next 8 bits are the bits per voxel.
+pub const VOXEL_STORAGE_DENSE: u32 = 0;
+pub const VOXEL_STORAGE_OCTREE: u32 = 1;
+
+// Element type of a dense voxel, which decides how many palette entries a volume can use.
+// 8 bit voxels use VoxelColors and 16 bit voxels use LargeVoxelColors.
+pub trait VoxelElement: bytemuck::Pod + Ord + Send + Sync + 'static {
+    const BITS: u32;
+}
+impl VoxelElement for u8 {
+    const BITS: u32 = 8;
+}
+impl VoxelElement for u16 {
+    const BITS: u32 = 16;
+}
+
+// The header is the dimension and storage kind, followed by the highlighted voxel.
+const HEADER_SIZE: u64 = 2 * size_of::<UVec4>() as u64;
 
 #[derive(Component, Deref)]
 pub struct VoxelBuffer {
     #[deref]
     buffer: Buffer,
     dimension: UVec3,
+    capacity: u64,
 }
 impl VoxelBuffer {
-    pub fn new(renderer: &Renderer, dimension: UVec3) -> Self {
+    fn create(renderer: &Renderer, dimension: UVec3, capacity: u64, storage: u32) -> Self {
         let buffer = renderer.device.create_buffer(&BufferDescriptor {
             label: Some("Voxel buffer"),
-            size: (dimension.x * dimension.y * dimension.z) as u64 + size_of::<UVec4>() as u64, // despite dimension being uvec3, on the gpu side, its alignment is 16 so there is an extra padding.
+            size: capacity + HEADER_SIZE, // the voxels start after the dimension and highlight uvec4s
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
         renderer
             .queue
-            .write_buffer(&buffer, 0, bytemuck::bytes_of(&dimension));
+            .write_buffer(&buffer, 0, bytemuck::bytes_of(&dimension.extend(storage)));
 
-        Self { buffer, dimension }
+        Self {
+            buffer,
+            dimension,
+            capacity,
+        }
     }
-    pub fn update(&self, renderer: &Renderer, voxel: &Voxel) {
+    pub fn new<T: VoxelElement>(renderer: &Renderer, dimension: UVec3) -> Self {
+        let voxel_count = dimension.x as u64 * dimension.y as u64 * dimension.z as u64;
+        Self::create(
+            renderer,
+            dimension,
+            voxel_count.div_ceil(Voxel::<T>::PER_WORD as u64) * size_of::<u32>() as u64,
+            VOXEL_STORAGE_DENSE | T::BITS << 8,
+        )
+    }
+    pub const fn dimension(&self) -> UVec3 {
+        self.dimension
+    }
+    // Uploads the parts of the voxel modified since the last update.
+    pub fn update<T: VoxelElement>(&self, renderer: &Renderer, voxel: &mut Voxel<T>) {
         if self.dimension != voxel.dimension() {
             panic!("Cannot update buffer with voxel whose dimension does not match the buffer's dimension. Resize the buffer with the matching dimension and then update.");
         }
+        for range in voxel.dirty.drain(..) {
+            renderer.queue.write_buffer(
+                &self.buffer,
+                HEADER_SIZE + (range.start * size_of::<u32>()) as u64,
+                bytemuck::cast_slice(&voxel.data[range]),
+            );
+        }
+    }
+    pub fn from_octree(renderer: &Renderer, octree: &VoxelOctree) -> Self {
+        // leave room for the octree to grow so that edits don't reallocate every time
+        let capacity = (octree.nodes().len().max(64) * 2 * size_of::<u32>()) as u64;
+        let buffer = Self::create(
+            renderer,
+            octree.dimension(),
+            capacity,
+            VOXEL_STORAGE_OCTREE | octree.depth() << 8,
+        );
+        buffer.update_octree(renderer, octree);
+        buffer
+    }
+    pub fn set_highlight(&self, renderer: &Renderer, highlight: Option<UVec3>) {
+        // w tells the shader whether anything is highlighted
+        let value = highlight.map_or(UVec4::ZERO, |position| position.extend(1));
         renderer.queue.write_buffer(
-            &*self,
+            &self.buffer,
             size_of::<UVec4>() as u64,
-            bytemuck::cast_slice(&voxel.data),
+            bytemuck::bytes_of(&value),
         );
     }
+    // Returns false when the octree no longer fits, in which case the buffer has to be recreated.
+    pub fn update_octree(&self, renderer: &Renderer, octree: &VoxelOctree) -> bool {
+        let nodes: &[u8] = bytemuck::cast_slice(octree.nodes());
+        if self.dimension != octree.dimension() || nodes.len() as u64 > self.capacity {
+            return false;
+        }
+        renderer
+            .queue
+            .write_buffer(&self.buffer, HEADER_SIZE, nodes);
+        true
+    }
 }
+// Voxel drawn with an outline, like the one under the cursor. Works for both dense voxels and octrees.
+#[derive(Component, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VoxelHighlight(pub Option<UVec3>);
+
+// Past this many separate dirty ranges, uploading everything between them is cheaper than tracking them.
+const MAX_DIRTY_RANGES: usize = 64;
+
 #[derive(Component, Clone)]
-pub struct Voxel {
+pub struct Voxel<T: VoxelElement = u8> {
     dimension: UVec3,
     data: Box<[u32]>,
+    dirty: Vec<Range<usize>>, // sorted and non-overlapping ranges of data modified since the last upload
+    element: PhantomData<T>,
 }
-impl Voxel {
+impl<T: VoxelElement> Voxel<T> {
+    const PER_WORD: usize = size_of::<u32>() / size_of::<T>();
+
     pub fn new(dimension: UVec3) -> Self {
         let UVec3 { x, y, z } = dimension;
-        assert!(
-            x % 2 == 0 && y % 2 == 0 && z % 2 == 0,
-            "dimension fields must be divisable by 2!"
-        );
+        let len = x as usize * y as usize * z as usize;
         Self {
             dimension,
-            data: vec![0; x as usize * y as usize * z as usize / 4].into_boxed_slice(),
+            // PER_WORD voxels are packed in each element, the last one is padded when the voxel count doesn't fill it
+            data: vec![0; len.div_ceil(Self::PER_WORD)].into_boxed_slice(),
+            dirty: vec![],
+            element: PhantomData,
         }
     }
+    fn mark_dirty(&mut self, words: Range<usize>) {
+        let start = self.dirty.partition_point(|range| range.end < words.start);
+        let mut end = start;
+        let mut merged = words;
+        while end < self.dirty.len() && self.dirty[end].start <= merged.end {
+            merged.start = merged.start.min(self.dirty[end].start);
+            merged.end = merged.end.max(self.dirty[end].end);
+            end += 1;
+        }
+        self.dirty.splice(start..end, [merged]);
+
+        if self.dirty.len() > MAX_DIRTY_RANGES {
+            let span = self.dirty[0].start..self.dirty[self.dirty.len() - 1].end;
+            self.dirty.clear();
+            self.dirty.push(span);
+        }
+    }
+    // Forces the next update to upload the whole voxel.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty.push(0..self.data.len());
+    }
+    // Changes the dimension while keeping the existing voxels, anchor decides where the content ends up
+    // on each axis, from 0.0 keeping it at the lowest coordinates to 1.0 keeping it at the highest.
+    // The new space is empty and whatever no longer fits is cropped.
+    pub fn resize(&mut self, dimension: UVec3, anchor: Vec3) {
+        if dimension == self.dimension {
+            return;
+        }
+        let offset = ((dimension.as_vec3() - self.dimension.as_vec3()) * anchor)
+            .round()
+            .as_ivec3();
+        let mut resized = Self::new(dimension);
+        let min = offset.max(IVec3::ZERO).as_uvec3();
+        let max = (self.dimension.as_ivec3() + offset)
+            .min(dimension.as_ivec3())
+            .max(IVec3::ZERO)
+            .as_uvec3();
+        for z in min.z..max.z {
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let position = uvec3(x, y, z);
+                    let source = (position.as_ivec3() - offset).as_uvec3();
+                    *resized.get_mut(position).unwrap() = *self.get(source).unwrap();
+                }
+            }
+        }
+        resized.mark_all_dirty();
+        *self = resized;
+    }
     pub const fn len(&self) -> usize {
         self.data.len()
     }
     pub const fn dimension(&self) -> UVec3 {
         self.dimension
     }
+    pub fn for_each_mut(&mut self, mut callback: impl FnMut(&mut T, UVec3)) {
+        self.mark_all_dirty();
+        let dimension = self.dimension;
+        for z in 0..dimension.z {
+            for y in 0..dimension.y {
+                for x in 0..dimension.x {
+                    let position = uvec3(x, y, z);
+                    let i = Voxel::get_index(dimension, position).unwrap();
+                    callback(self.element_mut(i), position);
+                }
+            }
+        }
+    }
+    pub fn get(&self, position: UVec3) -> Option<&T> {
+        Voxel::get_index(self.dimension, position)
+            .map(|i| &bytemuck::cast_slice::<_, T>(&self.data)[i])
+    }
+    fn element_mut(&mut self, i: usize) -> &mut T {
+        &mut bytemuck::cast_slice_mut::<_, T>(&mut self.data)[i]
+    }
+    pub fn get_mut(&mut self, position: UVec3) -> Option<&mut T> {
+        let i = Voxel::get_index(self.dimension, position)?;
+        let word = i / Self::PER_WORD;
+        self.mark_dirty(word..word + 1);
+        Some(self.element_mut(i))
+    }
+}
+
+// Indexing doesn't depend on the element type, so it lives on the default voxel type.
+impl Voxel {
     pub fn get_index(dimension: UVec3, position: UVec3) -> Option<usize> {
         let UVec3 { x, y, z } = position;
         let UVec3 {
@@ -85,33 +250,14 @@ impl Voxel {
             index as u32 / dx / dy,
         ))
     }
-    pub fn for_each_mut(&mut self, mut callback: impl FnMut(&mut u8, UVec3)) {
-        let dimension = self.dimension;
-        for z in 0..dimension.z {
-            for y in 0..dimension.y {
-                for x in 0..dimension.x {
-                    let position = uvec3(x, y, z);
-                    let v = self.get_mut(position).unwrap();
-                    callback(v, position);
-                }
-            }
-        }
-    }
-    pub fn get(&self, position: UVec3) -> Option<&u8> {
-        Self::get_index(self.dimension, position).map(|i| unsafe {
-            &std::mem::transmute::<_, &[u8; 4]>(self.data.get_unchecked(i / 4))[i % 4]
-        })
-    }
-    pub fn get_mut(&mut self, position: UVec3) -> Option<&mut u8> {
-        Self::get_index(self.dimension, position).map(|i| unsafe {
-            &mut std::mem::transmute::<_, &mut [u8; 4]>(self.data.get_unchecked_mut(i / 4))[i % 4]
-        })
-    }
 }
 
-#[derive(Component, Deref, Clone, Copy)]
+#[derive(Component, Deref, DerefMut, Clone, Copy, Debug)]
 pub struct VoxelColors([[u8; 4]; 256]);
 impl VoxelColors {
+    pub const fn new(colors: [[u8; 4]; 256]) -> Self {
+        Self(colors)
+    }
     // Color palette that contains every color of RGBA channels where each channel has 2bits
     pub fn all_color() -> Self {
         #[allow(invalid_value)]
@@ -124,6 +270,39 @@ impl VoxelColors {
         }
         itself
     }
+    // Index of the entry closest to color in RGBA. Index 0 and transparent entries are skipped since
+    // voxels using them are empty, 0 if every entry is transparent.
+    pub fn nearest(&self, color: [u8; 4]) -> u8 {
+        let distance = |entry: &[u8; 4]| {
+            (0..4)
+                .map(|i| (entry[i] as i32 - color[i] as i32).pow(2))
+                .sum::<i32>()
+        };
+        (1..256)
+            .filter(|&i| self.0[i][3] != 0)
+            .min_by_key(|&i| distance(&self.0[i]))
+            .unwrap_or(0) as u8
+    }
+}
+
+// Palette of 16 bit voxels, stored in a storage buffer since it can hold up to 65536 colors.
+#[derive(Component, Deref, DerefMut, Clone)]
+pub struct LargeVoxelColors(Vec<[u8; 4]>);
+impl LargeVoxelColors {
+    pub const MAX_LEN: usize = 1 << u16::BITS;
+
+    pub fn new(colors: Vec<[u8; 4]>) -> Self {
+        assert!(
+            colors.len() <= Self::MAX_LEN,
+            "large palettes can have at most 65536 colors!"
+        );
+        Self(colors)
+    }
+}
+impl From<VoxelColors> for LargeVoxelColors {
+    fn from(value: VoxelColors) -> Self {
+        Self(value.to_vec())
+    }
 }
 
 #[derive(Resource, Deref)]
@@ -134,6 +313,12 @@ impl FromWorld for MainVoxelColors {
         Self(palette)
     }
 }
+impl MainVoxelColors {
+    // Renders with the VoxelColors of another entity, it's uploaded once the entity has them.
+    pub fn set(&mut self, palette: Entity) {
+        self.0 = palette;
+    }
+}
 
 #[derive(Resource, Deref)]
 pub struct MainColorBuffer(Buffer);
@@ -159,14 +344,102 @@ impl FromWorld for MainColorBuffer {
         Self::new(world.resource())
     }
 }
-pub(super) fn sync_voxel_buffers(
+
+#[derive(Resource, Deref)]
+pub struct MainLargeVoxelColors(Entity);
+impl FromWorld for MainLargeVoxelColors {
+    fn from_world(world: &mut World) -> Self {
+        let palette = world
+            .spawn(LargeVoxelColors::from(VoxelColors::all_color()))
+            .id();
+        Self(palette)
+    }
+}
+
+#[derive(Resource, Deref)]
+pub struct MainLargeColorBuffer(Buffer);
+impl MainLargeColorBuffer {
+    pub fn new(renderer: &Renderer) -> Self {
+        // always allocated at the maximum size so that the bind group never has to be recreated
+        let buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("Main large color buffer"),
+            size: (LargeVoxelColors::MAX_LEN * size_of::<[u8; 4]>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self(buffer)
+    }
+    pub fn update(&self, renderer: &Renderer, colors: &LargeVoxelColors) {
+        renderer
+            .queue
+            .write_buffer(&self.0, 0, bytemuck::cast_slice(colors));
+    }
+}
+impl FromWorld for MainLargeColorBuffer {
+    fn from_world(world: &mut World) -> Self {
+        Self::new(world.resource())
+    }
+}
+#[allow(clippy::type_complexity)]
+pub(super) fn sync_voxel_buffers<T: VoxelElement>(
     renderer: Res<Renderer>,
-    voxel_q: Query<(&Voxel, &VoxelBuffer), Changed<Voxel>>,
+    pipeline: Res<Pipeline>,
+    mut voxel_q: Query<
+        (
+            &mut Voxel<T>,
+            &mut VoxelBuffer,
+            &ModelBuffer,
+            &mut PerInstanceBindGroup,
+        ),
+        Changed<Voxel<T>>,
+    >,
 ) {
-    for (voxel, buffer) in voxel_q.iter() {
+    for (mut voxel, mut buffer, model_buffer, mut bind_group) in voxel_q.iter_mut() {
+        // clearing the dirty ranges is not a change anyone else needs to react to
+        let voxel = voxel.bypass_change_detection();
+        if buffer.dimension() != voxel.dimension() {
+            *buffer = VoxelBuffer::new::<T>(&renderer, voxel.dimension());
+            *bind_group = PerInstanceBindGroup::new(&renderer, &pipeline, model_buffer, &buffer);
+            voxel.mark_all_dirty();
+        } else if buffer.is_added() {
+            voxel.mark_all_dirty();
+        }
         buffer.update(&renderer, voxel);
     }
 }
+pub(super) fn sync_octree_buffers(
+    renderer: Res<Renderer>,
+    pipeline: Res<Pipeline>,
+    mut octree_q: Query<
+        (
+            &VoxelOctree,
+            &mut VoxelBuffer,
+            &ModelBuffer,
+            &mut PerInstanceBindGroup,
+        ),
+        Changed<VoxelOctree>,
+    >,
+) {
+    for (octree, mut buffer, model_buffer, mut bind_group) in octree_q.iter_mut() {
+        if buffer.update_octree(&renderer, octree) {
+            continue;
+        }
+        *buffer = VoxelBuffer::from_octree(&renderer, octree);
+        *bind_group = PerInstanceBindGroup::new(&renderer, &pipeline, model_buffer, &buffer);
+    }
+}
+pub(super) fn sync_highlights(
+    renderer: Res<Renderer>,
+    highlight_q: Query<(Ref<VoxelHighlight>, Ref<VoxelBuffer>)>,
+) {
+    for (highlight, buffer) in highlight_q.iter() {
+        // recreated buffers start without a highlight
+        if highlight.is_changed() || buffer.is_changed() {
+            buffer.set_highlight(&renderer, highlight.0);
+        }
+    }
+}
 pub(super) fn sync_color_buffer(
     renderer: Res<Renderer>,
     color_q: Query<Ref<VoxelColors>>,
@@ -182,3 +455,116 @@ pub(super) fn sync_color_buffer(
 
     color_buffer.update(&renderer, &color);
 }
+pub(super) fn sync_large_color_buffer(
+    renderer: Res<Renderer>,
+    color_q: Query<Ref<LargeVoxelColors>>,
+    main_color: Res<MainLargeVoxelColors>,
+    color_buffer: Res<MainLargeColorBuffer>,
+) {
+    let Ok(color) = color_q.get(**main_color) else {
+        return;
+    };
+    if !color.is_changed() && !main_color.is_changed() {
+        return;
+    }
+
+    color_buffer.update(&renderer, &color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every voxel of a 4x4x4 volume has a different value.
+    fn numbered() -> Voxel {
+        let mut voxel = Voxel::new(UVec3::splat(4));
+        voxel.for_each_mut(|value, position| {
+            *value = (1 + position.x + position.y * 4 + position.z * 16) as u8;
+        });
+        voxel
+    }
+    fn dirty(voxel: &Voxel) -> Vec<(usize, usize)> {
+        voxel
+            .dirty
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect()
+    }
+    fn assert_moved(voxel: &Voxel, offset: IVec3) {
+        let original = numbered();
+        for position in VoxelRegion::new(UVec3::ZERO, voxel.dimension()).positions() {
+            let source = position.as_ivec3() - offset;
+            let expected = if source.cmpge(IVec3::ZERO).all() {
+                original.get(source.as_uvec3()).copied().unwrap_or(0)
+            } else {
+                0
+            };
+            assert_eq!(voxel.get(position), Some(&expected), "{position}");
+        }
+    }
+
+    #[test]
+    fn touching_dirty_words_merge() {
+        // four voxels per word along a single row
+        let mut voxel: Voxel = Voxel::new(uvec3(64, 1, 1));
+        assert!(voxel.dirty.is_empty());
+        *voxel.get_mut(uvec3(0, 0, 0)).unwrap() = 1;
+        *voxel.get_mut(uvec3(4, 0, 0)).unwrap() = 1;
+        assert_eq!(dirty(&voxel), [(0, 2)]);
+        *voxel.get_mut(uvec3(12, 0, 0)).unwrap() = 1;
+        *voxel.get_mut(uvec3(20, 0, 0)).unwrap() = 1;
+        assert_eq!(dirty(&voxel), [(0, 2), (3, 4), (5, 6)]);
+        *voxel.get_mut(uvec3(9, 0, 0)).unwrap() = 1;
+        assert_eq!(dirty(&voxel), [(0, 4), (5, 6)]);
+    }
+
+    #[test]
+    fn too_many_dirty_ranges_become_one() {
+        let mut voxel: Voxel = Voxel::new(uvec3(1024, 1, 1));
+        for i in 0..MAX_DIRTY_RANGES as u32 {
+            *voxel.get_mut(uvec3(i * 8, 0, 0)).unwrap() = 1;
+        }
+        assert_eq!(voxel.dirty.len(), MAX_DIRTY_RANGES);
+        *voxel
+            .get_mut(uvec3(MAX_DIRTY_RANGES as u32 * 8, 0, 0))
+            .unwrap() = 1;
+        assert_eq!(dirty(&voxel), [(0, MAX_DIRTY_RANGES * 2 + 1)]);
+    }
+
+    #[test]
+    fn everything_can_be_marked_dirty() {
+        let mut voxel: Voxel = Voxel::new(uvec3(3, 5, 7));
+        *voxel.get_mut(uvec3(2, 4, 6)).unwrap() = 1;
+        voxel.mark_all_dirty();
+        // 105 voxels are padded to 27 words
+        assert_eq!(dirty(&voxel), [(0, 27)]);
+    }
+
+    #[test]
+    fn padding_follows_the_anchor() {
+        for (anchor, offset) in [(0.0, 0), (0.5, 2), (1.0, 4)] {
+            let mut voxel = numbered();
+            voxel.resize(UVec3::splat(8), Vec3::splat(anchor));
+            assert_eq!(voxel.dimension(), UVec3::splat(8));
+            assert_moved(&voxel, IVec3::splat(offset));
+        }
+    }
+
+    #[test]
+    fn cropping_follows_the_anchor() {
+        for (anchor, offset) in [(0.0, 0), (0.5, -1), (1.0, -2)] {
+            let mut voxel = numbered();
+            voxel.resize(UVec3::splat(2), Vec3::splat(anchor));
+            assert_eq!(voxel.dimension(), UVec3::splat(2));
+            assert_moved(&voxel, IVec3::splat(offset));
+        }
+    }
+
+    #[test]
+    fn each_axis_has_its_own_anchor() {
+        let mut voxel = numbered();
+        voxel.resize(uvec3(8, 2, 4), vec3(0.5, 1.0, 0.0));
+        assert_moved(&voxel, ivec3(2, -2, 0));
+        assert_eq!(dirty(&voxel), [(0, voxel.len())]);
+    }
+}
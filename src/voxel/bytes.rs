@@ -0,0 +1,56 @@
+use crate::*;
+
+// Larger volumes are rejected as corrupted rather than allocated, 2^28 voxels is 512x512x1024.
+pub(super) const MAX_VOXEL_COUNT: usize = 1 << 28;
+
+// Number of voxels in a volume of dimension read from a file, None if it's too large to load.
+pub(super) fn voxel_count(dimension: UVec3) -> Option<usize> {
+    (dimension.x as usize)
+        .checked_mul(dimension.y as usize)?
+        .checked_mul(dimension.z as usize)
+        .filter(|&count| count <= MAX_VOXEL_COUNT)
+}
+
+// Returned when a ByteReader runs out of bytes, each file format turns it into its own error.
+pub(super) struct UnexpectedEof;
+
+// Little endian reader over the bytes of a binary file.
+pub(super) struct ByteReader<'a>(pub(super) &'a [u8]);
+impl<'a> ByteReader<'a> {
+    pub(super) fn take(&mut self, len: usize) -> Result<&'a [u8], UnexpectedEof> {
+        if self.0.len() < len {
+            return Err(UnexpectedEof);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+    pub(super) fn u8(&mut self) -> Result<u8, UnexpectedEof> {
+        Ok(self.take(1)?[0])
+    }
+    pub(super) fn u16(&mut self) -> Result<u16, UnexpectedEof> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    pub(super) fn u32(&mut self) -> Result<u32, UnexpectedEof> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub(super) fn i32(&mut self) -> Result<i32, UnexpectedEof> {
+        Ok(self.u32()? as i32)
+    }
+    pub(super) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voxel_count_rejects_large_volumes() {
+        assert_eq!(voxel_count(uvec3(512, 512, 1024)), Some(1 << 28));
+        assert_eq!(voxel_count(uvec3(0, 7, 7)), Some(0));
+        assert_eq!(voxel_count(UVec3::splat(2048)), None);
+        assert_eq!(voxel_count(UVec3::splat(u32::MAX)), None);
+    }
+}
@@ -0,0 +1,320 @@
+use crate::*;
+
+// Worms start inside cells of this many voxels per side and can reach into the neighbouring cells,
+// which keeps them in the same place no matter how the world is split into volumes.
+const WORM_CELL_SIZE: i32 = 64;
+
+// 3D terrain made from a density field, which unlike a heightmap can have overhangs, arches and caves.
+// Only basic float arithmetic is used so that a seed generates the same voxels on every platform.
+#[derive(Resource, Clone, Debug)]
+pub struct CaveGenerator {
+    pub terrain: TerrainGenerator, // seed, surface height, water and layers
+    pub overhang: f32, // how far in voxels the surface is pushed around, 0.0 is a plain heightmap
+    pub overhang_frequency: f32,
+    pub cave_density: f32, // 0.0 has no noise caves, 1.0 hollows out most of the ground
+    pub cave_frequency: f32,
+    pub min_thickness: u32, // solid voxels that are always left above a cave
+    pub worms_per_cell: u32,
+    pub worm_length: u32,
+    pub worm_radius: f32,
+}
+impl Default for CaveGenerator {
+    fn default() -> Self {
+        Self {
+            terrain: TerrainGenerator::default(),
+            overhang: 6.0,
+            overhang_frequency: 1.0 / 24.0,
+            cave_density: 0.3,
+            cave_frequency: 1.0 / 32.0,
+            min_thickness: 3,
+            worms_per_cell: 2,
+            worm_length: 48,
+            worm_radius: 2.5,
+        }
+    }
+}
+
+struct CaveNoise {
+    terrain: PerlinNoise,
+    overhang: PerlinNoise,
+    tunnels: [PerlinNoise; 2],
+}
+
+fn random_direction(rng: &mut SplitMix64) -> Vec3 {
+    loop {
+        let direction = vec3(
+            rng.range(-1.0..1.0),
+            rng.range(-1.0..1.0),
+            rng.range(-1.0..1.0),
+        );
+        let length_squared = direction.length_squared();
+        if length_squared > 0.01 && length_squared <= 1.0 {
+            return direction / length_squared.sqrt();
+        }
+    }
+}
+
+impl CaveGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            terrain: TerrainGenerator::new(seed),
+            ..Default::default()
+        }
+    }
+    fn noise(&self) -> CaveNoise {
+        let seed = self.terrain.seed;
+        CaveNoise {
+            terrain: self.terrain.noise(),
+            overhang: PerlinNoise::new(seed ^ 0x6f7665726861),
+            tunnels: [
+                PerlinNoise::new(seed ^ 0x74756e6e656c),
+                PerlinNoise::new(seed ^ 0x74756e6e656d),
+            ],
+        }
+    }
+    // Positive inside the ground, height is the terrain height of the position's column.
+    fn density(&self, noise: &CaveNoise, position: IVec3, height: f32) -> f32 {
+        let point = position.as_vec3() * self.overhang_frequency;
+        height - position.y as f32 + noise.overhang.fbm(point, 3, 2.0, 0.5) * self.overhang
+    }
+    // Tunnels form where two noise fields are both close to zero.
+    fn is_cave(&self, noise: &CaveNoise, position: IVec3) -> bool {
+        let threshold = self.cave_density * 0.3;
+        noise.tunnels.iter().all(|tunnels| {
+            let point = position.as_vec3() * self.cave_frequency;
+            tunnels.fbm(point, 2, 2.0, 0.5).abs() < threshold
+        })
+    }
+    // Marks the voxels carved by worms passing through the volume.
+    fn carve_worms(&self, dimension: UVec3, origin: IVec3) -> Vec<bool> {
+        let len = dimension.x as usize * dimension.y as usize * dimension.z as usize;
+        let mut carved = vec![false; len];
+        let reach = self.worm_length as i32 + self.worm_radius.ceil() as i32;
+        let min_cell = (origin - reach).div_euclid(IVec3::splat(WORM_CELL_SIZE));
+        let max_cell =
+            (origin + dimension.as_ivec3() + reach).div_euclid(IVec3::splat(WORM_CELL_SIZE));
+
+        for cz in min_cell.z..=max_cell.z {
+            for cy in min_cell.y..=max_cell.y {
+                for cx in min_cell.x..=max_cell.x {
+                    let cell = ivec3(cx, cy, cz);
+                    let hash = [cell.x, cell.y, cell.z]
+                        .iter()
+                        .fold(self.terrain.seed, |hash, &c| {
+                            SplitMix64::new(hash ^ c as u32 as u64).next_u64()
+                        });
+                    let mut rng = SplitMix64::new(hash);
+                    for _ in 0..self.worms_per_cell {
+                        let start = vec3(rng.next_f32(), rng.next_f32(), rng.next_f32());
+                        let mut position = (cell.as_vec3() + start) * WORM_CELL_SIZE as f32;
+                        let mut direction = random_direction(&mut rng);
+                        for _ in 0..self.worm_length {
+                            self.carve_sphere(&mut carved, dimension, origin, position);
+                            position += direction;
+                            // wander, flattened so that worms mostly run sideways
+                            direction += random_direction(&mut rng) * 0.3;
+                            direction.y *= 0.7;
+                            direction = direction.normalize_or_zero();
+                        }
+                    }
+                }
+            }
+        }
+        carved
+    }
+    fn carve_sphere(&self, carved: &mut [bool], dimension: UVec3, origin: IVec3, center: Vec3) {
+        let radius = self.worm_radius;
+        let local = center - origin.as_vec3();
+        let min = (local - radius).floor().as_ivec3().max(IVec3::ZERO);
+        let max = ((local + radius).floor().as_ivec3() + 1).min(dimension.as_ivec3());
+        for z in min.z..max.z {
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let position = ivec3(x, y, z);
+                    if (position.as_vec3() + 0.5).distance_squared(local) <= radius * radius {
+                        carved[Voxel::get_index(dimension, position.as_uvec3()).unwrap()] = true;
+                    }
+                }
+            }
+        }
+    }
+    // Fills the voxel as the part of the world whose lowest corner is at origin, like TerrainGenerator::fill.
+    pub fn fill(&self, voxel: &mut Voxel, origin: IVec3) {
+        let noise = self.noise();
+        let dimension = voxel.dimension();
+        let layers = self.terrain.layers;
+        let water_level = self.terrain.water_level;
+
+        // the ground above the volume still decides the layers and cave ceilings near its top
+        let extra = layers.dirt_depth.max(self.min_thickness) + 1;
+        let extended = dimension + UVec3::Y * extra;
+        let worms = self.carve_worms(extended, origin);
+        let mut rows = vec![(0, false); extended.y as usize];
+        for z in 0..dimension.z {
+            for x in 0..dimension.x {
+                let column = origin + ivec3(x as i32, 0, z as i32);
+                let height = self.terrain.height(&noise.terrain, column.x, column.z);
+
+                // depth is the number of solid voxels from a voxel up to the air above it, 0 for air,
+                // and roof is the same after carving. Caves are only carved below min_thickness
+                // voxels of roof, or right below another carved voxel.
+                let mut depth = 0;
+                let mut roof = 0;
+                for y in (0..extended.y).rev() {
+                    let position = column + IVec3::Y * y as i32;
+                    let solid = self.density(&noise, position, height) > 0.0;
+                    depth = if solid { depth + 1 } else { 0 };
+                    let carved = depth > self.min_thickness
+                        && (roof == 0 || roof >= self.min_thickness)
+                        && (worms[Voxel::get_index(extended, uvec3(x, y, z)).unwrap()]
+                            || self.is_cave(&noise, position));
+                    roof = if solid && !carved { roof + 1 } else { 0 };
+                    rows[y as usize] = (depth, carved);
+                }
+
+                for y in 0..dimension.y {
+                    let world = column + IVec3::Y * y as i32;
+                    let (depth, carved) = rows[y as usize];
+                    *voxel.get_mut(uvec3(x, y, z)).unwrap() = if depth == 0 {
+                        if (world.y as f32) < water_level {
+                            layers.water
+                        } else {
+                            0
+                        }
+                    } else if carved {
+                        0
+                    } else if depth == 1 && world.y as f32 >= water_level {
+                        layers.grass
+                    } else if depth <= layers.dirt_depth {
+                        layers.dirt
+                    } else {
+                        layers.stone
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(generator: &CaveGenerator, dimension: UVec3, origin: IVec3) -> Voxel {
+        let mut voxel = Voxel::new(dimension);
+        generator.fill(&mut voxel, origin);
+        voxel
+    }
+    fn values(voxel: &Voxel) -> Vec<u8> {
+        VoxelRegion::new(UVec3::ZERO, voxel.dimension())
+            .positions()
+            .map(|position| *voxel.get(position).unwrap())
+            .collect()
+    }
+    // Top down solid runs of every column, air and water split them.
+    fn runs(generator: &CaveGenerator, voxel: &Voxel) -> Vec<Vec<u32>> {
+        let water = generator.terrain.layers.water;
+        let dimension = voxel.dimension();
+        let mut runs = vec![];
+        for z in 0..dimension.z {
+            for x in 0..dimension.x {
+                let mut column = vec![0];
+                for y in (0..dimension.y).rev() {
+                    let value = *voxel.get(uvec3(x, y, z)).unwrap();
+                    if value != 0 && value != water {
+                        *column.last_mut().unwrap() += 1;
+                    } else if *column.last().unwrap() > 0 {
+                        column.push(0);
+                    }
+                }
+                column.retain(|&run| run > 0);
+                runs.push(column);
+            }
+        }
+        runs
+    }
+    // Columns with empty space below solid ground.
+    fn hollow_columns(generator: &CaveGenerator, voxel: &Voxel) -> usize {
+        let dimension = voxel.dimension();
+        runs(generator, voxel)
+            .iter()
+            .enumerate()
+            .filter(|(i, column)| {
+                let (x, z) = (*i as u32 % dimension.x, *i as u32 / dimension.x);
+                // the bottom run doesn't count if it reaches the bottom of the volume
+                let bottom = *voxel.get(uvec3(x, 0, z)).unwrap();
+                let reaches_bottom = bottom != 0 && bottom != generator.terrain.layers.water;
+                column.len() > reaches_bottom as usize
+            })
+            .count()
+    }
+
+    #[test]
+    fn seeds_decide_the_caves() {
+        let dimension = uvec3(32, 48, 32);
+        let origin = ivec3(0, -16, 0);
+        let a = generate(&CaveGenerator::new(5), dimension, origin);
+        let b = generate(&CaveGenerator::new(5), dimension, origin);
+        let c = generate(&CaveGenerator::new(6), dimension, origin);
+        assert_eq!(values(&a), values(&b));
+        assert_ne!(values(&a), values(&c));
+    }
+
+    #[test]
+    fn chunks_line_up_with_one_volume() {
+        let generator = CaveGenerator::new(9);
+        let whole = generate(&generator, uvec3(48, 48, 16), ivec3(-24, -24, 0));
+        for x in [0, 24] {
+            for y in [0, 24] {
+                let origin = ivec3(x - 24, y - 24, 0);
+                let chunk = generate(&generator, uvec3(24, 24, 16), origin);
+                for position in VoxelRegion::new(UVec3::ZERO, chunk.dimension()).positions() {
+                    let offset = uvec3(x as u32, y as u32, 0);
+                    assert_eq!(
+                        chunk.get(position),
+                        whole.get(position + offset),
+                        "{position}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cave_roofs_are_at_least_min_thickness() {
+        // overhangs aren't caves, so they are left out
+        let generator = CaveGenerator {
+            overhang: 0.0,
+            cave_density: 0.8,
+            worms_per_cell: 6,
+            ..CaveGenerator::new(11)
+        };
+        let voxel = generate(&generator, uvec3(32, 64, 32), ivec3(0, -32, 0));
+        let mut roofs = 0;
+        for column in runs(&generator, &voxel) {
+            // every run but the lowest, which may continue below the volume, lies above a cave
+            for &run in column.iter().rev().skip(1) {
+                assert!(run >= generator.min_thickness, "{column:?}");
+                roofs += 1;
+            }
+        }
+        assert!(roofs > 50, "{roofs}");
+    }
+
+    #[test]
+    fn cave_density_hollows_out_the_ground() {
+        let generate_with = |cave_density| {
+            let generator = CaveGenerator {
+                overhang: 0.0,
+                cave_density,
+                worms_per_cell: 0,
+                ..CaveGenerator::new(2)
+            };
+            let voxel = generate(&generator, uvec3(32, 48, 32), ivec3(0, -16, 0));
+            hollow_columns(&generator, &voxel)
+        };
+        assert_eq!(generate_with(0.0), 0);
+        let (sparse, dense) = (generate_with(0.3), generate_with(1.0));
+        assert!(sparse > 0 && dense > sparse, "{sparse} {dense}");
+    }
+}
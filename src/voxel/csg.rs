@@ -0,0 +1,347 @@
+use crate::*;
+
+// Quarter turns around an axis, counter-clockwise when looking from the positive end of the axis.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum VoxelRotation {
+    #[default]
+    None,
+    X(u32),
+    Y(u32),
+    Z(u32),
+}
+impl VoxelRotation {
+    // The two axes that rotate into each other, in the order a quarter turn moves them, and the number of turns.
+    fn plane(self) -> Option<(usize, usize, u32)> {
+        let (u, v, turns) = match self {
+            Self::None => return None,
+            Self::X(turns) => (1, 2, turns),
+            Self::Y(turns) => (2, 0, turns),
+            Self::Z(turns) => (0, 1, turns),
+        };
+        (turns % 4 != 0).then_some((u, v, turns % 4))
+    }
+    pub fn rotate_dimension(self, dimension: UVec3) -> UVec3 {
+        let mut dimension = dimension;
+        if let Some((u, v, turns)) = self.plane() {
+            if turns % 2 == 1 {
+                dimension.as_mut().swap(u, v);
+            }
+        }
+        dimension
+    }
+    // Maps a position in the rotated volume back to the volume of the given dimension it was rotated from.
+    pub fn source_position(self, position: UVec3, dimension: UVec3) -> UVec3 {
+        let Some((u, v, turns)) = self.plane() else {
+            return position;
+        };
+        // undoing the rotation is the same as finishing the full turn
+        let mut position = position;
+        let mut rotated = self.rotate_dimension(dimension);
+        for _ in turns..4 {
+            (position[u], position[v]) = (rotated[v] - 1 - position[v], position[u]);
+            rotated.as_mut().swap(u, v);
+        }
+        position
+    }
+}
+
+// Decides the palette index of a voxel that is set in both volumes.
+#[derive(Clone, Copy, Debug)]
+pub enum ConflictRule<T: VoxelElement = u8> {
+    Keep,      // the volume being modified wins
+    Overwrite, // the other volume wins
+    Max,
+    Min,
+    Custom(fn(T, T) -> T), // called with the current and the incoming value
+}
+impl<T: VoxelElement> ConflictRule<T> {
+    pub fn resolve(&self, current: T, incoming: T) -> T {
+        match self {
+            Self::Keep => current,
+            Self::Overwrite => incoming,
+            Self::Max => current.max(incoming),
+            Self::Min => current.min(incoming),
+            Self::Custom(resolve) => resolve(current, incoming),
+        }
+    }
+}
+
+// The other volume is rotated first and then placed with its lowest corner at offset.
+// Results are clipped to this volume and each operation returns the region that changed, like brushes.
+impl<T: VoxelElement> Voxel<T> {
+    fn combine(
+        &mut self,
+        other: &Voxel<T>,
+        offset: IVec3,
+        rotation: VoxelRotation,
+        whole_volume: bool, // also visit the voxels outside of the other volume
+        mut combine: impl FnMut(T, T) -> T,
+    ) -> Option<VoxelRegion> {
+        let size = rotation.rotate_dimension(other.dimension());
+        let region = if whole_volume {
+            Some(VoxelRegion::new(UVec3::ZERO, self.dimension()))
+        } else {
+            self.clip(offset, offset + size.as_ivec3() - 1)
+        };
+
+        let mut changed = None;
+        for position in region.iter().flat_map(VoxelRegion::positions) {
+            let local = position.as_ivec3() - offset;
+            let incoming = if local.cmpge(IVec3::ZERO).all() && local.cmplt(size.as_ivec3()).all() {
+                // every position within the rotated size maps to one inside of other
+                let source = rotation.source_position(local.as_uvec3(), other.dimension());
+                *other.get(source).unwrap()
+            } else {
+                T::zeroed()
+            };
+            let value = combine(*self.get(position).unwrap(), incoming);
+            self.paint(position, value, &mut changed);
+        }
+        changed
+    }
+
+    // Adds the voxels of other, rule decides voxels set in both.
+    pub fn union(
+        &mut self,
+        other: &Voxel<T>,
+        offset: IVec3,
+        rotation: VoxelRotation,
+        rule: ConflictRule<T>,
+    ) -> Option<VoxelRegion> {
+        let empty = T::zeroed();
+        self.combine(other, offset, rotation, false, |current, incoming| {
+            if incoming == empty {
+                current
+            } else if current == empty {
+                incoming
+            } else {
+                rule.resolve(current, incoming)
+            }
+        })
+    }
+    // Removes the voxels that are set in other.
+    pub fn subtract(
+        &mut self,
+        other: &Voxel<T>,
+        offset: IVec3,
+        rotation: VoxelRotation,
+    ) -> Option<VoxelRegion> {
+        let empty = T::zeroed();
+        self.combine(other, offset, rotation, false, |current, incoming| {
+            if incoming == empty {
+                current
+            } else {
+                empty
+            }
+        })
+    }
+    // Keeps only the voxels set in both, rule decides their value.
+    pub fn intersect(
+        &mut self,
+        other: &Voxel<T>,
+        offset: IVec3,
+        rotation: VoxelRotation,
+        rule: ConflictRule<T>,
+    ) -> Option<VoxelRegion> {
+        let empty = T::zeroed();
+        self.combine(other, offset, rotation, true, |current, incoming| {
+            if current == empty || incoming == empty {
+                empty
+            } else {
+                rule.resolve(current, incoming)
+            }
+        })
+    }
+    // Copies other including its empty voxels, which clear whatever they cover. Rule decides voxels set in both.
+    pub fn stamp(
+        &mut self,
+        other: &Voxel<T>,
+        offset: IVec3,
+        rotation: VoxelRotation,
+        rule: ConflictRule<T>,
+    ) -> Option<VoxelRegion> {
+        let empty = T::zeroed();
+        self.combine(other, offset, rotation, false, |current, incoming| {
+            if current == empty || incoming == empty {
+                incoming
+            } else {
+                rule.resolve(current, incoming)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every voxel of a 2x3x4 volume has a different value, so rotations can't hide behind symmetry.
+    fn numbered() -> Voxel {
+        let mut voxel = Voxel::new(uvec3(2, 3, 4));
+        voxel.for_each_mut(|value, position| {
+            *value = (1 + position.x + position.y * 2 + position.z * 6) as u8;
+        });
+        voxel
+    }
+    fn rotated(voxel: &Voxel, rotation: VoxelRotation) -> Voxel {
+        let mut result = Voxel::new(rotation.rotate_dimension(voxel.dimension()));
+        result.union(voxel, IVec3::ZERO, rotation, ConflictRule::Keep);
+        result
+    }
+    fn positions(voxel: &Voxel) -> impl Iterator<Item = UVec3> {
+        VoxelRegion::new(UVec3::ZERO, voxel.dimension()).positions()
+    }
+    fn filled(voxel: &Voxel) -> usize {
+        positions(voxel)
+            .filter(|&position| voxel.get(position) != Some(&0))
+            .count()
+    }
+
+    #[test]
+    fn quarter_turns_are_counter_clockwise() {
+        let voxel = numbered();
+        let d = voxel.dimension();
+        for rotation in [
+            VoxelRotation::X(1),
+            VoxelRotation::Y(1),
+            VoxelRotation::Z(1),
+        ] {
+            let result = rotated(&voxel, rotation);
+            assert_eq!(result.dimension(), rotation.rotate_dimension(d));
+            for p in positions(&voxel) {
+                // where a quarter turn around the axis moves the voxel
+                let turned = match rotation {
+                    VoxelRotation::X(_) => uvec3(p.x, d.z - 1 - p.z, p.y),
+                    VoxelRotation::Y(_) => uvec3(p.z, p.y, d.x - 1 - p.x),
+                    _ => uvec3(d.y - 1 - p.y, p.x, p.z),
+                };
+                assert_eq!(result.get(turned), voxel.get(p), "{rotation:?} {p}");
+            }
+        }
+    }
+
+    #[test]
+    fn more_turns_repeat_the_quarter_turn() {
+        let voxel = numbered();
+        for axis in [VoxelRotation::X, VoxelRotation::Y, VoxelRotation::Z] {
+            let mut expected = voxel.clone();
+            for turns in 1..=4 {
+                expected = rotated(&expected, axis(1));
+                let result = rotated(&voxel, axis(turns));
+                assert_eq!(result.dimension(), expected.dimension(), "{turns}");
+                for position in positions(&result) {
+                    assert_eq!(result.get(position), expected.get(position), "{turns}");
+                }
+            }
+            // the full turn is back where it started
+            for position in positions(&voxel) {
+                assert_eq!(expected.get(position), voxel.get(position));
+            }
+        }
+    }
+
+    #[test]
+    fn negative_offsets_are_clipped() {
+        let mut voxel: Voxel = Voxel::new(UVec3::splat(4));
+        let other = numbered();
+        let changed = voxel.union(
+            &other,
+            ivec3(-1, -2, 2),
+            VoxelRotation::None,
+            ConflictRule::Keep,
+        );
+        assert_eq!(
+            changed,
+            Some(VoxelRegion::new(uvec3(0, 0, 2), uvec3(1, 1, 4)))
+        );
+        assert_eq!(filled(&voxel), 2);
+        assert_eq!(voxel.get(uvec3(0, 0, 2)), other.get(uvec3(1, 2, 0)));
+
+        let changed = voxel.subtract(&other, ivec3(-1, -2, 3), VoxelRotation::None);
+        assert_eq!(changed, Some(VoxelRegion::from_position(uvec3(0, 0, 3))));
+        assert_eq!(filled(&voxel), 1);
+        // entirely outside of the volume
+        let changed = voxel.union(
+            &other,
+            ivec3(-2, 0, 0),
+            VoxelRotation::None,
+            ConflictRule::Keep,
+        );
+        assert_eq!(changed, None);
+    }
+
+    #[test]
+    fn intersect_clears_everything_outside_other() {
+        let mut voxel: Voxel = Voxel::new(UVec3::splat(4));
+        voxel.fill_box(IVec3::ZERO, IVec3::splat(3), 9);
+        let other = numbered();
+        let changed = voxel.intersect(
+            &other,
+            ivec3(1, 1, 0),
+            VoxelRotation::None,
+            ConflictRule::Keep,
+        );
+        assert_eq!(
+            changed,
+            Some(VoxelRegion::new(UVec3::ZERO, UVec3::splat(4)))
+        );
+        for position in positions(&voxel) {
+            let inside =
+                position.cmpge(uvec3(1, 1, 0)).all() && position.cmplt(uvec3(3, 4, 4)).all();
+            assert_eq!(
+                voxel.get(position),
+                Some(&if inside { 9 } else { 0 }),
+                "{position}"
+            );
+        }
+    }
+
+    #[test]
+    fn stamp_copies_empty_voxels() {
+        let mut voxel: Voxel = Voxel::new(UVec3::splat(4));
+        voxel.fill_box(IVec3::ZERO, IVec3::splat(3), 9);
+        let mut other: Voxel = Voxel::new(UVec3::splat(2));
+        *other.get_mut(UVec3::ZERO).unwrap() = 5;
+        voxel.stamp(
+            &other,
+            IVec3::ONE,
+            VoxelRotation::None,
+            ConflictRule::Overwrite,
+        );
+        assert_eq!(voxel.get(UVec3::ONE), Some(&5));
+        assert_eq!(voxel.get(UVec3::splat(2)), Some(&0));
+        assert_eq!(voxel.get(UVec3::ZERO), Some(&9));
+        assert_eq!(filled(&voxel), 64 - 7);
+    }
+
+    #[test]
+    fn conflict_rules_decide_overlapping_voxels() {
+        let rules = [
+            (ConflictRule::Keep, 3),
+            (ConflictRule::Overwrite, 7),
+            (ConflictRule::Max, 7),
+            (ConflictRule::Min, 3),
+            (
+                ConflictRule::Custom(|current, incoming| current * 10 + incoming),
+                37,
+            ),
+        ];
+        let mut other: Voxel = Voxel::new(uvec3(2, 1, 1));
+        other.fill_box(IVec3::ZERO, IVec3::X, 7);
+        for (rule, expected) in rules {
+            for intersect in [false, true] {
+                let mut voxel: Voxel = Voxel::new(uvec3(2, 1, 1));
+                *voxel.get_mut(UVec3::ZERO).unwrap() = 3;
+                if intersect {
+                    voxel.intersect(&other, IVec3::ZERO, VoxelRotation::None, rule);
+                    // only set in other
+                    assert_eq!(voxel.get(UVec3::X), Some(&0), "{rule:?}");
+                } else {
+                    voxel.union(&other, IVec3::ZERO, VoxelRotation::None, rule);
+                    assert_eq!(voxel.get(UVec3::X), Some(&7), "{rule:?}");
+                }
+                assert_eq!(voxel.get(UVec3::ZERO), Some(&expected), "{rule:?}");
+            }
+        }
+    }
+}
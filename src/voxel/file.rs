@@ -0,0 +1,345 @@
+use super::bytes::{voxel_count, ByteReader, UnexpectedEof};
+use crate::*;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// Layout (little endian):
+// magic "VXLR", version u16, flags u16, dimension 3 x u32,
+// palette 256 x RGBA if FLAG_PALETTE is set,
+// payload length u32, payload as (value u8, run length LEB128) pairs in index order,
+// CRC32 of everything before it.
+const MAGIC: &[u8; 4] = b"VXLR";
+const VERSION: u16 = 1;
+const FLAG_PALETTE: u16 = 1;
+
+#[derive(Debug)]
+pub enum VoxelFileError {
+    Io(io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Corrupted(&'static str),
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+impl fmt::Display for VoxelFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::InvalidMagic => write!(f, "not a voxel file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported voxel file version {version}")
+            }
+            Self::Truncated => write!(f, "voxel file is truncated"),
+            Self::Corrupted(reason) => write!(f, "voxel file is corrupted: {reason}"),
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch, expected {expected:#010x} but got {actual:#010x}"
+            ),
+        }
+    }
+}
+impl std::error::Error for VoxelFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for VoxelFileError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<UnexpectedEof> for VoxelFileError {
+    fn from(_: UnexpectedEof) -> Self {
+        Self::Truncated
+    }
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+fn read_leb128(bytes: &mut &[u8]) -> Result<u32, VoxelFileError> {
+    let mut value = 0u32;
+    for shift in (0..32).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(VoxelFileError::Corrupted(
+            "payload ends in the middle of a run",
+        ))?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(VoxelFileError::Corrupted("run length is too long"))
+}
+
+fn compress(voxel: &Voxel) -> Vec<u8> {
+    let dimension = voxel.dimension();
+    let len = dimension.x as usize * dimension.y as usize * dimension.z as usize;
+    let mut values = (0..len).map(|i| {
+        *voxel
+            .get(Voxel::get_position(dimension, i).unwrap())
+            .unwrap()
+    });
+
+    let mut out = vec![];
+    let Some(mut current) = values.next() else {
+        return out;
+    };
+    let mut run = 1u32;
+    for value in values {
+        if value == current && run < u32::MAX {
+            run += 1;
+            continue;
+        }
+        out.push(current);
+        write_leb128(&mut out, run);
+        current = value;
+        run = 1;
+    }
+    out.push(current);
+    write_leb128(&mut out, run);
+    out
+}
+// Runs are counted before the volume is allocated, so a corrupted dimension can't allocate more
+// than the payload describes.
+fn decompress(mut payload: &[u8], dimension: UVec3) -> Result<Voxel, VoxelFileError> {
+    let len = voxel_count(dimension).ok_or(VoxelFileError::Corrupted("dimension is too large"))?;
+    let mut runs = vec![];
+    let mut total = 0;
+    while let Some((&value, rest)) = payload.split_first() {
+        payload = rest;
+        let run = read_leb128(&mut payload)? as usize;
+        if run > len - total {
+            return Err(VoxelFileError::Corrupted(
+                "payload has more voxels than the dimension",
+            ));
+        }
+        runs.push((value, run));
+        total += run;
+    }
+    if total != len {
+        return Err(VoxelFileError::Corrupted(
+            "payload has fewer voxels than the dimension",
+        ));
+    }
+
+    let mut voxel = Voxel::new(dimension);
+    let mut index = 0;
+    for (value, run) in runs {
+        for i in index..index + run {
+            *voxel
+                .get_mut(Voxel::get_position(dimension, i).unwrap())
+                .unwrap() = value;
+        }
+        index += run;
+    }
+    Ok(voxel)
+}
+
+impl Voxel {
+    pub fn save(&self, colors: Option<&VoxelColors>, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write(colors, io::BufWriter::new(fs::File::create(path)?))
+    }
+    pub fn write(&self, colors: Option<&VoxelColors>, mut writer: impl Write) -> io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        let flags = if colors.is_some() { FLAG_PALETTE } else { 0 };
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        for value in self.dimension().to_array() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        if let Some(colors) = colors {
+            bytes.extend(colors.iter().flatten());
+        }
+        let payload = compress(self);
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes.extend_from_slice(&crc32(&bytes).to_le_bytes());
+
+        writer.write_all(&bytes)?;
+        writer.flush()
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<(Self, Option<VoxelColors>), VoxelFileError> {
+        Self::read(io::BufReader::new(fs::File::open(path)?))
+    }
+    pub fn read(mut reader: impl Read) -> Result<(Self, Option<VoxelColors>), VoxelFileError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        let mut reader = ByteReader(&bytes);
+        if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(VoxelFileError::InvalidMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(VoxelFileError::UnsupportedVersion(version));
+        }
+        let flags = reader.u16()?;
+        let dimension = uvec3(reader.u32()?, reader.u32()?, reader.u32()?);
+        let palette = if flags & FLAG_PALETTE != 0 {
+            Some(reader.take(size_of::<[[u8; 4]; 256]>())?)
+        } else {
+            None
+        };
+        let payload_len = reader.u32()? as usize;
+        let payload = reader.take(payload_len)?;
+        let expected = reader.u32()?;
+        if !reader.is_empty() {
+            return Err(VoxelFileError::Corrupted("trailing data after checksum"));
+        }
+
+        let actual = crc32(&bytes[..bytes.len() - 4]);
+        if expected != actual {
+            return Err(VoxelFileError::ChecksumMismatch { expected, actual });
+        }
+        if dimension.cmpeq(UVec3::ZERO).any() {
+            return Err(VoxelFileError::Corrupted("invalid dimension"));
+        }
+        let colors = palette.map(|palette| {
+            let mut colors = [[0; 4]; 256];
+            for (color, bytes) in colors.iter_mut().zip(palette.chunks_exact(4)) {
+                *color = bytes.try_into().unwrap();
+            }
+            VoxelColors::new(colors)
+        });
+
+        Ok((decompress(payload, dimension)?, colors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_voxel() -> Voxel {
+        let mut voxel = Voxel::new(uvec3(5, 3, 4));
+        voxel.for_each_mut(|value, position| *value = (position.x * position.z % 3) as u8);
+        voxel
+    }
+    fn test_file() -> Vec<u8> {
+        let mut bytes = vec![];
+        test_voxel().write(None, &mut bytes).unwrap();
+        bytes
+    }
+    // Replaces the checksum of a file whose contents were changed.
+    fn fix_checksum(mut bytes: Vec<u8>) -> Vec<u8> {
+        let len = bytes.len() - 4;
+        let crc = crc32(&bytes[..len]);
+        bytes[len..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+    fn set_dimension(bytes: &mut [u8], dimension: UVec3) {
+        for (i, value) in dimension.to_array().into_iter().enumerate() {
+            bytes[8 + i * 4..12 + i * 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let voxel = test_voxel();
+        let mut palette = [[0; 4]; 256];
+        palette[1] = [255, 0, 0, 255];
+        palette[2] = [0, 128, 255, 64];
+        let mut bytes = vec![];
+        voxel
+            .write(Some(&VoxelColors::new(palette)), &mut bytes)
+            .unwrap();
+
+        let (read, colors) = Voxel::read(bytes.as_slice()).unwrap();
+        assert_eq!(read.dimension(), voxel.dimension());
+        for position in VoxelRegion::new(UVec3::ZERO, voxel.dimension()).positions() {
+            assert_eq!(read.get(position), voxel.get(position));
+        }
+        assert_eq!(*colors.unwrap(), palette);
+        assert!(Voxel::read(test_file().as_slice()).unwrap().1.is_none());
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = test_file();
+        for len in [6, 20, bytes.len() - 1] {
+            assert!(matches!(
+                Voxel::read(&bytes[..len]),
+                Err(VoxelFileError::Truncated)
+            ));
+        }
+    }
+
+    #[test]
+    fn corrupted_files_are_rejected() {
+        let mut bytes = test_file();
+        bytes[26] ^= 1;
+        assert!(matches!(
+            Voxel::read(bytes.as_slice()),
+            Err(VoxelFileError::ChecksumMismatch { .. })
+        ));
+
+        // the runs of the payload have to cover the dimension exactly
+        for dimension in [uvec3(5, 3, 3), uvec3(5, 3, 5), uvec3(1024, 1024, 256)] {
+            let mut bytes = test_file();
+            set_dimension(&mut bytes, dimension);
+            assert!(matches!(
+                Voxel::read(fix_checksum(bytes).as_slice()),
+                Err(VoxelFileError::Corrupted(_))
+            ));
+        }
+        let mut bytes = test_file();
+        set_dimension(&mut bytes, UVec3::splat(u32::MAX));
+        assert!(matches!(
+            Voxel::read(fix_checksum(bytes).as_slice()),
+            Err(VoxelFileError::Corrupted("dimension is too large"))
+        ));
+    }
+
+    #[test]
+    fn unsupported_versions_are_rejected() {
+        let mut bytes = test_file();
+        bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(
+            Voxel::read(fix_checksum(bytes).as_slice()),
+            Err(VoxelFileError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            Voxel::read(&b"RIFF"[..]),
+            Err(VoxelFileError::InvalidMagic)
+        ));
+    }
+}
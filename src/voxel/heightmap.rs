@@ -0,0 +1,152 @@
+use crate::*;
+
+// Decides the palette index of the surface voxel of each column from a second image.
+#[derive(Clone, Debug)]
+pub enum SurfaceMap {
+    // the color of the column matched to the nearest color of the palette
+    Color(RgbaImage, Box<VoxelColors>),
+    // the strongest of the red, green, blue and alpha channels picks one of four palette indices
+    Splat(RgbaImage, [u8; 4]),
+}
+impl SurfaceMap {
+    fn index(&self, uv: Vec2) -> Option<u8> {
+        match self {
+            Self::Color(image, colors) => {
+                let color = image.sample_rgba8(uv);
+                if color[3] == 0 {
+                    return None;
+                }
+                Some(colors.nearest(color)).filter(|&index| index != 0)
+            }
+            Self::Splat(image, indices) => {
+                let weights = image.sample_rgba8(uv);
+                let strongest = (0..4).max_by_key(|&i| (weights[i], std::cmp::Reverse(i)))?;
+                (weights[strongest] > 0).then_some(indices[strongest])
+            }
+        }
+    }
+}
+
+// Terrain authored as a grayscale heightmap image, stretched over the width and depth of the volume.
+// Image rows go along Z, so the top of the image is at the lowest Z. Values are in voxels.
+#[derive(Clone, Debug)]
+pub struct HeightmapImporter {
+    pub base_height: f32,  // height of black
+    pub height_scale: f32, // added to base_height for white
+    pub water_level: f32,  // empty space below this height is filled with water
+    pub layers: TerrainLayers,
+    pub surface: Option<SurfaceMap>, // replaces the grass layer where it has a value
+}
+impl Default for HeightmapImporter {
+    fn default() -> Self {
+        Self {
+            base_height: 1.0,
+            height_scale: 48.0,
+            water_level: 0.0,
+            layers: TerrainLayers::default(),
+            surface: None,
+        }
+    }
+}
+impl HeightmapImporter {
+    // Fills every column of the voxel from the heightmap, height 0 is the bottom of the volume.
+    pub fn fill(&self, voxel: &mut Voxel, heightmap: &RgbaImage) {
+        let dimension = voxel.dimension();
+        let columns: Vec<(f32, Option<u8>)> = (0..dimension.z)
+            .flat_map(|z| (0..dimension.x).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                let uv =
+                    (vec2(x as f32, z as f32) + 0.5) / vec2(dimension.x as f32, dimension.z as f32);
+                let height = self.base_height + heightmap.sample_luminance(uv) * self.height_scale;
+                (
+                    height,
+                    self.surface.as_ref().and_then(|surface| surface.index(uv)),
+                )
+            })
+            .collect();
+        voxel.for_each_mut(|value, position| {
+            let (height, surface) = columns[(position.x + position.z * dimension.x) as usize];
+            *value = self
+                .layers
+                .layer(position.y as i32, height, self.water_level, surface);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(voxel: &Voxel, position: UVec3) -> u8 {
+        *voxel.get(position).unwrap()
+    }
+
+    #[test]
+    fn columns_are_filled_up_to_their_height() {
+        // black on the left, white on the right
+        let heightmap = RgbaImage::from_rgba8(uvec2(2, 1), [[0, 0, 0, 255], [255, 255, 255, 255]]);
+        let importer = HeightmapImporter {
+            base_height: 2.0,
+            height_scale: 10.0,
+            water_level: 4.0,
+            ..Default::default()
+        };
+        let layers = importer.layers;
+        let mut voxel = Voxel::new(uvec3(16, 16, 4));
+        importer.fill(&mut voxel, &heightmap);
+        // surface at 2 under water, too shallow for stone
+        assert_eq!(value(&voxel, uvec3(0, 0, 0)), layers.dirt);
+        assert_eq!(value(&voxel, uvec3(0, 2, 0)), layers.dirt);
+        assert_eq!(value(&voxel, uvec3(0, 3, 0)), layers.water);
+        assert_eq!(value(&voxel, uvec3(0, 4, 0)), 0);
+        // surface at 12 above the water
+        assert_eq!(value(&voxel, uvec3(15, 8, 0)), layers.stone);
+        assert_eq!(value(&voxel, uvec3(15, 10, 0)), layers.dirt);
+        assert_eq!(value(&voxel, uvec3(15, 12, 0)), layers.grass);
+        assert_eq!(value(&voxel, uvec3(15, 13, 0)), 0);
+        // every column is the same along z
+        for position in VoxelRegion::new(UVec3::ZERO, voxel.dimension()).positions() {
+            let front = uvec3(position.x, position.y, 0);
+            assert_eq!(voxel.get(position), voxel.get(front), "{position}");
+        }
+    }
+
+    #[test]
+    fn splat_maps_pick_the_strongest_channel() {
+        let heightmap = RgbaImage::from_rgba8(uvec2(1, 1), [[255; 4]]);
+        // red at the lowest z, blue at the highest and nothing in the middle
+        let splat = RgbaImage::from_rgba8(uvec2(1, 3), [[255, 0, 0, 0], [0; 4], [0, 100, 200, 0]]);
+        let importer = HeightmapImporter {
+            base_height: 2.0,
+            height_scale: 10.0,
+            surface: Some(SurfaceMap::Splat(splat, [7, 8, 9, 10])),
+            ..Default::default()
+        };
+        let mut voxel = Voxel::new(uvec3(1, 16, 3));
+        importer.fill(&mut voxel, &heightmap);
+        assert_eq!(value(&voxel, uvec3(0, 12, 0)), 7);
+        assert_eq!(value(&voxel, uvec3(0, 12, 1)), importer.layers.grass);
+        assert_eq!(value(&voxel, uvec3(0, 12, 2)), 9);
+        // only the top voxel is replaced
+        assert_eq!(value(&voxel, uvec3(0, 11, 2)), importer.layers.dirt);
+    }
+
+    #[test]
+    fn color_maps_pick_the_nearest_entry() {
+        let colors = VoxelColors::all_color();
+        let heightmap = RgbaImage::from_rgba8(uvec2(1, 1), [[255; 4]]);
+        let stone = TerrainLayers::default().stone;
+        // transparent pixels keep the grass
+        let surface = RgbaImage::from_rgba8(uvec2(1, 2), [colors[stone as usize], [0; 4]]);
+        let importer = HeightmapImporter {
+            base_height: 2.0,
+            height_scale: 10.0,
+            surface: Some(SurfaceMap::Color(surface, Box::new(colors))),
+            ..Default::default()
+        };
+        let mut voxel = Voxel::new(uvec3(1, 16, 2));
+        importer.fill(&mut voxel, &heightmap);
+        assert_eq!(value(&voxel, uvec3(0, 12, 0)), stone);
+        assert_eq!(value(&voxel, uvec3(0, 12, 1)), importer.layers.grass);
+    }
+}
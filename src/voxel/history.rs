@@ -0,0 +1,321 @@
+use crate::*;
+use std::collections::VecDeque;
+
+// Values of one region of a voxel entity before and after an edit, in VoxelRegion::positions order.
+struct RegionDiff<T: VoxelElement> {
+    entity: Entity,
+    dimension: UVec3, // of the voxel when recorded, edits to a resized voxel are skipped
+    region: VoxelRegion,
+    before: Vec<T>,
+    after: Vec<T>,
+}
+impl<T: VoxelElement> RegionDiff<T> {
+    fn memory(&self) -> usize {
+        size_of::<Self>() + (self.before.len() + self.after.len()) * size_of::<T>()
+    }
+    fn apply(&self, voxels: &mut Query<&mut Voxel<T>>, undo: bool) {
+        let Ok(mut voxel) = voxels.get_mut(self.entity) else {
+            return;
+        };
+        if voxel.dimension() != self.dimension {
+            return;
+        }
+        let values = if undo { &self.before } else { &self.after };
+        for (position, &value) in self.region.positions().zip(values) {
+            *voxel.get_mut(position).unwrap() = value;
+        }
+    }
+}
+
+// A single undo step, which can span several voxel entities.
+struct Operation<T: VoxelElement> {
+    diffs: Vec<RegionDiff<T>>,
+    memory: usize,
+}
+
+// Edit history of voxel entities that stores only the regions each edit changed.
+// Edits made between begin and end are undone and redone together as one operation.
+// When the stored operations, including the open one, use more than max_memory bytes the oldest
+// ones are forgotten, first those that can be undone and then those furthest from being redone.
+#[derive(Resource)]
+pub struct VoxelHistory<T: VoxelElement = u8> {
+    undo: VecDeque<Operation<T>>,
+    redo: VecDeque<Operation<T>>, // the next operation to redo is at the back
+    open: Option<Operation<T>>,
+    memory: usize,
+    max_memory: usize,
+}
+impl<T: VoxelElement> Default for VoxelHistory<T> {
+    fn default() -> Self {
+        Self::new(64 << 20)
+    }
+}
+impl<T: VoxelElement> VoxelHistory<T> {
+    pub fn new(max_memory: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: VecDeque::new(),
+            open: None,
+            memory: 0,
+            max_memory,
+        }
+    }
+
+    pub fn begin(&mut self) {
+        self.end();
+        self.open = Some(Operation {
+            diffs: Vec::new(),
+            memory: 0,
+        });
+    }
+    pub fn end(&mut self) {
+        if let Some(operation) = self.open.take() {
+            if !operation.diffs.is_empty() {
+                self.undo.push_back(operation);
+                self.trim();
+            }
+        }
+    }
+
+    // Runs an edit that returns the region it changed, like the brush and CSG operations.
+    // Only bounds, the region the edit can touch, is copied while it runs, so the returned region
+    // has to lie within it.
+    pub fn edit(
+        &mut self,
+        entity: Entity,
+        voxel: &mut Voxel<T>,
+        bounds: VoxelRegion,
+        edit: impl FnOnce(&mut Voxel<T>) -> Option<VoxelRegion>,
+    ) -> Option<VoxelRegion> {
+        let dimension = voxel.dimension();
+        let bounds = VoxelRegion::new(bounds.min, bounds.max.min(dimension));
+        let before: Vec<T> = bounds
+            .positions()
+            .map(|position| *voxel.get(position).unwrap())
+            .collect();
+        let region = edit(voxel)?;
+        // the regions of a resized voxel no longer line up, so its edit can't be undone
+        if voxel.dimension() != dimension {
+            return Some(region);
+        }
+        // changes outside of bounds can't be undone and aren't recorded
+        let recorded = VoxelRegion::new(region.min.max(bounds.min), region.max.min(bounds.max));
+        let size = bounds.size();
+        self.record(entity, voxel, recorded, |position| {
+            let local = position - bounds.min;
+            before[(local.x + local.y * size.x + local.z * size.x * size.y) as usize]
+        });
+        Some(region)
+    }
+    // Runs an edit that only changes voxels inside region, copying just that region.
+    pub fn edit_region(
+        &mut self,
+        entity: Entity,
+        voxel: &mut Voxel<T>,
+        region: VoxelRegion,
+        edit: impl FnOnce(&mut Voxel<T>),
+    ) {
+        self.edit(entity, voxel, region, |voxel| {
+            edit(voxel);
+            Some(region)
+        });
+    }
+    fn record(
+        &mut self,
+        entity: Entity,
+        voxel: &Voxel<T>,
+        region: VoxelRegion,
+        before: impl FnMut(UVec3) -> T,
+    ) {
+        if region.is_empty() {
+            return;
+        }
+        let diff = RegionDiff {
+            entity,
+            dimension: voxel.dimension(),
+            region,
+            before: region.positions().map(before).collect(),
+            after: region
+                .positions()
+                .map(|position| *voxel.get(position).unwrap())
+                .collect(),
+        };
+        if diff.before == diff.after {
+            return;
+        }
+        // a new edit makes the undone operations unreachable
+        for operation in self.redo.drain(..) {
+            self.memory -= operation.memory;
+        }
+        self.memory += diff.memory();
+        match &mut self.open {
+            Some(operation) => {
+                operation.memory += diff.memory();
+                operation.diffs.push(diff);
+            }
+            None => self.undo.push_back(Operation {
+                memory: diff.memory(),
+                diffs: vec![diff],
+            }),
+        }
+        self.trim();
+    }
+    // The open operation counts toward memory but is only forgotten once it has ended.
+    fn trim(&mut self) {
+        while self.memory > self.max_memory {
+            let Some(operation) = self.undo.pop_front().or_else(|| self.redo.pop_front()) else {
+                break;
+            };
+            self.memory -= operation.memory;
+        }
+    }
+
+    // Reverts the last operation, returns false if there was nothing to undo.
+    // Diffs of entities that were despawned or resized since are skipped.
+    pub fn undo(&mut self, voxels: &mut Query<&mut Voxel<T>>) -> bool {
+        self.end();
+        let Some(operation) = self.undo.pop_back() else {
+            return false;
+        };
+        for diff in operation.diffs.iter().rev() {
+            diff.apply(voxels, true);
+        }
+        self.redo.push_back(operation);
+        true
+    }
+    // Reapplies the last undone operation, returns false if there was nothing to redo.
+    pub fn redo(&mut self, voxels: &mut Query<&mut Voxel<T>>) -> bool {
+        self.end();
+        let Some(operation) = self.redo.pop_back() else {
+            return false;
+        };
+        for diff in &operation.diffs {
+            diff.apply(voxels, false);
+        }
+        self.undo.push_back(operation);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::SystemState;
+
+    fn set(history: &mut VoxelHistory, entity: Entity, voxel: &mut Voxel, x: u32, value: u8) {
+        history.edit_region(
+            entity,
+            voxel,
+            VoxelRegion::from_position(uvec3(x, 0, 0)),
+            |voxel| *voxel.get_mut(uvec3(x, 0, 0)).unwrap() = value,
+        );
+    }
+    fn values(world: &World, entity: Entity) -> Vec<u8> {
+        let voxel = world.get::<Voxel>(entity).unwrap();
+        (0..4)
+            .map(|x| *voxel.get(uvec3(x, 0, 0)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut world = World::new();
+        let entity = world.spawn(Voxel::<u8>::new(uvec3(4, 1, 1))).id();
+        let mut history = VoxelHistory::default();
+        let mut voxel = world.get_mut::<Voxel>(entity).unwrap();
+        set(&mut history, entity, &mut voxel, 0, 1);
+        set(&mut history, entity, &mut voxel, 1, 2);
+
+        let mut state: SystemState<Query<&mut Voxel>> = SystemState::new(&mut world);
+        assert!(history.undo(&mut state.get_mut(&mut world)));
+        assert_eq!(values(&world, entity), [1, 0, 0, 0]);
+        assert!(history.redo(&mut state.get_mut(&mut world)));
+        assert_eq!(values(&world, entity), [1, 2, 0, 0]);
+        assert!(!history.redo(&mut state.get_mut(&mut world)));
+    }
+
+    #[test]
+    fn trim_keeps_the_next_redo() {
+        let mut world = World::new();
+        let entity = world.spawn(Voxel::<u8>::new(uvec3(4, 1, 1))).id();
+        let mut history = VoxelHistory::default();
+        let mut voxel = world.get_mut::<Voxel>(entity).unwrap();
+        set(&mut history, entity, &mut voxel, 0, 1);
+        set(&mut history, entity, &mut voxel, 1, 2);
+
+        let mut state: SystemState<Query<&mut Voxel>> = SystemState::new(&mut world);
+        assert!(history.undo(&mut state.get_mut(&mut world)));
+        assert!(history.undo(&mut state.get_mut(&mut world)));
+        assert_eq!(values(&world, entity), [0; 4]);
+
+        // room for one of the two operations, the second edit is furthest from being redone
+        history.max_memory = history.memory - 1;
+        history.trim();
+        assert!(history.redo(&mut state.get_mut(&mut world)));
+        assert_eq!(values(&world, entity), [1, 0, 0, 0]);
+        assert!(history.redo.is_empty());
+    }
+
+    #[test]
+    fn open_operation_counts_toward_memory() {
+        let mut world = World::new();
+        let entity = world.spawn(Voxel::<u8>::new(uvec3(4, 1, 1))).id();
+        let mut history = VoxelHistory::default();
+        let mut voxel = world.get_mut::<Voxel>(entity).unwrap();
+        set(&mut history, entity, &mut voxel, 0, 1);
+        let single = history.memory;
+
+        history.begin();
+        set(&mut history, entity, &mut voxel, 1, 2);
+        set(&mut history, entity, &mut voxel, 2, 3);
+        assert_eq!(history.memory, single * 3);
+        // the open operation survives, the finished one is forgotten
+        history.max_memory = single * 2;
+        history.trim();
+        assert_eq!(history.memory, single * 2);
+        history.end();
+
+        let mut state: SystemState<Query<&mut Voxel>> = SystemState::new(&mut world);
+        assert!(history.undo(&mut state.get_mut(&mut world)));
+        assert_eq!(values(&world, entity), [1, 0, 0, 0]);
+        assert!(history.undo.is_empty());
+    }
+
+    #[test]
+    fn edit_records_only_the_returned_region() {
+        let mut world = World::new();
+        let entity = world.spawn(Voxel::<u8>::new(uvec3(4, 1, 1))).id();
+        let mut history = VoxelHistory::default();
+        let mut voxel = world.get_mut::<Voxel>(entity).unwrap();
+        let bounds = VoxelRegion::new(uvec3(1, 0, 0), uvec3(4, 1, 1));
+        let region = history.edit(entity, &mut voxel, bounds, |voxel| {
+            *voxel.get_mut(uvec3(2, 0, 0)).unwrap() = 5;
+            *voxel.get_mut(uvec3(3, 0, 0)).unwrap() = 6;
+            Some(VoxelRegion::new(uvec3(2, 0, 0), uvec3(4, 1, 1)))
+        });
+        assert_eq!(
+            region,
+            Some(VoxelRegion::new(uvec3(2, 0, 0), uvec3(4, 1, 1)))
+        );
+
+        let mut state: SystemState<Query<&mut Voxel>> = SystemState::new(&mut world);
+        assert!(history.undo(&mut state.get_mut(&mut world)));
+        assert_eq!(values(&world, entity), [0; 4]);
+    }
+
+    #[test]
+    fn resizing_edits_are_not_recorded() {
+        let mut world = World::new();
+        let entity = world.spawn(Voxel::<u8>::new(uvec3(4, 1, 1))).id();
+        let mut history = VoxelHistory::default();
+        let mut voxel = world.get_mut::<Voxel>(entity).unwrap();
+        let bounds = VoxelRegion::new(UVec3::ZERO, uvec3(4, 1, 1));
+        let region = history.edit(entity, &mut voxel, bounds, |voxel| {
+            voxel.resize(uvec3(2, 1, 1), Vec3::ZERO);
+            voxel.fill_box(IVec3::ZERO, IVec3::X, 1)
+        });
+        assert_eq!(region, Some(VoxelRegion::new(UVec3::ZERO, uvec3(2, 1, 1))));
+        assert!(history.undo.is_empty());
+        assert_eq!(history.memory, 0);
+    }
+}
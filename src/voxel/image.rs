@@ -0,0 +1,409 @@
+use crate::*;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// 8192 by 8192, PNM headers are trusted no further than that
+const MAX_PNM_PIXELS: usize = 1 << 26;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    PngDecoding(png::DecodingError),
+    PngEncoding(png::EncodingError),
+    InvalidPnm(&'static str),
+    UnknownFormat,
+}
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::PngDecoding(e) => write!(f, "png decoding error: {e}"),
+            Self::PngEncoding(e) => write!(f, "png encoding error: {e}"),
+            Self::InvalidPnm(reason) => write!(f, "invalid pnm image: {reason}"),
+            Self::UnknownFormat => write!(f, "not a png or pnm image"),
+        }
+    }
+}
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::PngDecoding(e) => Some(e),
+            Self::PngEncoding(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for ImageError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<png::DecodingError> for ImageError {
+    fn from(value: png::DecodingError) -> Self {
+        Self::PngDecoding(value)
+    }
+}
+impl From<png::EncodingError> for ImageError {
+    fn from(value: png::EncodingError) -> Self {
+        Self::PngEncoding(value)
+    }
+}
+
+// Decoded PNG, PGM or PPM image with 16 bits per channel, so that 16-bit heightmaps keep their precision.
+// 8-bit images are scaled up, rows go from top to bottom.
+#[derive(Clone, Debug)]
+pub struct RgbaImage {
+    pub size: UVec2,
+    pub pixels: Vec<[u16; 4]>,
+}
+impl RgbaImage {
+    pub fn from_rgba8(size: UVec2, pixels: impl IntoIterator<Item = [u8; 4]>) -> Self {
+        let pixels: Vec<[u16; 4]> = pixels
+            .into_iter()
+            .map(|pixel| pixel.map(|c| c as u16 * 257))
+            .collect();
+        assert_eq!(pixels.len(), size.x as usize * size.y as usize);
+        Self { size, pixels }
+    }
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+    // The format is detected from the first bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ImageError> {
+        if bytes.starts_with(b"\x89PNG") {
+            Self::from_png(bytes)
+        } else if bytes.starts_with(b"P") {
+            Self::from_pnm(bytes)
+        } else {
+            Err(ImageError::UnknownFormat)
+        }
+    }
+    pub fn from_png(bytes: &[u8]) -> Result<Self, ImageError> {
+        let mut decoder = png::Decoder::new(bytes);
+        // palettes, bit depths below 8 and transparency chunks become plain channels
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let channels = info.color_type.samples();
+        let samples: Vec<u16> = match info.bit_depth {
+            png::BitDepth::Sixteen => buffer[..info.buffer_size()]
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect(),
+            _ => buffer[..info.buffer_size()]
+                .iter()
+                .map(|&c| c as u16 * 257)
+                .collect(),
+        };
+        Ok(Self {
+            size: uvec2(info.width, info.height),
+            pixels: samples
+                .chunks_exact(channels)
+                .map(expand_channels)
+                .collect(),
+        })
+    }
+    // Binary and plain PGM (P5, P2) and PPM (P6, P3).
+    pub fn from_pnm(bytes: &[u8]) -> Result<Self, ImageError> {
+        let mut reader = PnmReader(bytes);
+        let magic = reader.token()?;
+        let (channels, binary) = match magic {
+            b"P2" => (1, false),
+            b"P3" => (3, false),
+            b"P5" => (1, true),
+            b"P6" => (3, true),
+            _ => return Err(ImageError::InvalidPnm("unsupported magic")),
+        };
+        let width = reader.number()?;
+        let height = reader.number()?;
+        let max = reader.number()?;
+        if max == 0 || max > u16::MAX as u32 {
+            return Err(ImageError::InvalidPnm("invalid maximum value"));
+        }
+        let count = (width as usize)
+            .checked_mul(height as usize)
+            .filter(|&pixels| pixels <= MAX_PNM_PIXELS)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .ok_or(ImageError::InvalidPnm("image too large"))?;
+        let samples: Vec<u32> = if binary {
+            // exactly one whitespace byte separates the header from the data
+            let data = reader.0.get(1..).unwrap_or_default();
+            let sample_len = if max < 256 { 1 } else { 2 };
+            let len = count
+                .checked_mul(sample_len)
+                .ok_or(ImageError::InvalidPnm("image too large"))?;
+            if data.len() < len {
+                return Err(ImageError::InvalidPnm("not enough samples"));
+            }
+            data.chunks_exact(sample_len)
+                .take(count)
+                .map(|c| c.iter().fold(0, |sample, &b| sample << 8 | b as u32))
+                .collect()
+        } else {
+            (0..count)
+                .map(|_| reader.number())
+                .collect::<Result<_, _>>()?
+        };
+        let samples: Vec<u16> = samples
+            .into_iter()
+            .map(|sample| (sample.min(max) * u16::MAX as u32 / max) as u16)
+            .collect();
+        Ok(Self {
+            size: uvec2(width, height),
+            pixels: samples
+                .chunks_exact(channels)
+                .map(expand_channels)
+                .collect(),
+        })
+    }
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        fs::write(path, self.to_png()?)?;
+        Ok(())
+    }
+    // 8-bit RGBA.
+    pub fn to_png(&self) -> Result<Vec<u8>, ImageError> {
+        let mut bytes = vec![];
+        let mut encoder = png::Encoder::new(&mut bytes, self.size.x, self.size.y);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|&pixel| to_rgba8(pixel))
+            .collect();
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(bytes)
+    }
+
+    pub fn get(&self, position: UVec2) -> Option<[u16; 4]> {
+        if position.cmpge(self.size).any() {
+            return None;
+        }
+        Some(self.pixels[(position.x + position.y * self.size.x) as usize])
+    }
+    pub fn get_rgba8(&self, position: UVec2) -> Option<[u8; 4]> {
+        self.get(position).map(to_rgba8)
+    }
+    // Rec. 709 luma in 0.0..=1.0, ignoring alpha.
+    pub fn luminance(&self, position: UVec2) -> Option<f32> {
+        let [r, g, b, _] = self.get(position)?;
+        let rgb = vec3(r as f32, g as f32, b as f32) / u16::MAX as f32;
+        Some(rgb.dot(vec3(0.2126, 0.7152, 0.0722)))
+    }
+    // Bilinear luminance where uv spans from the top left to the bottom right corner of the image.
+    pub fn sample_luminance(&self, uv: Vec2) -> f32 {
+        let max = self.size.max(UVec2::ONE) - 1;
+        let texel = (uv * self.size.as_vec2() - 0.5).clamp(Vec2::ZERO, max.as_vec2());
+        let low = texel.floor().as_uvec2();
+        let high = (low + 1).min(max);
+        let t = texel - low.as_vec2();
+        let luminance = |x, y| self.luminance(uvec2(x, y)).unwrap_or_default();
+        let top = luminance(low.x, low.y) * (1.0 - t.x) + luminance(high.x, low.y) * t.x;
+        let bottom = luminance(low.x, high.y) * (1.0 - t.x) + luminance(high.x, high.y) * t.x;
+        top * (1.0 - t.y) + bottom * t.y
+    }
+    // Nearest pixel where uv spans from the top left to the bottom right corner of the image.
+    pub fn sample_rgba8(&self, uv: Vec2) -> [u8; 4] {
+        let position = (uv * self.size.as_vec2())
+            .as_uvec2()
+            .min(self.size.max(UVec2::ONE) - 1);
+        self.get_rgba8(position).unwrap_or_default()
+    }
+}
+
+fn expand_channels(samples: &[u16]) -> [u16; 4] {
+    match *samples {
+        [gray] => [gray, gray, gray, u16::MAX],
+        [gray, alpha] => [gray, gray, gray, alpha],
+        [r, g, b] => [r, g, b, u16::MAX],
+        [r, g, b, a] => [r, g, b, a],
+        _ => unreachable!(),
+    }
+}
+fn to_rgba8(pixel: [u16; 4]) -> [u8; 4] {
+    pixel.map(|c| ((c as u32 + 128) / 257) as u8)
+}
+
+struct PnmReader<'a>(&'a [u8]);
+impl<'a> PnmReader<'a> {
+    // Next whitespace separated token, skipping # comments.
+    fn token(&mut self) -> Result<&'a [u8], ImageError> {
+        loop {
+            let skip = self
+                .0
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .unwrap_or(self.0.len());
+            self.0 = &self.0[skip..];
+            if self.0.first() != Some(&b'#') {
+                break;
+            }
+            let end = self
+                .0
+                .iter()
+                .position(|&b| b == b'\n')
+                .unwrap_or(self.0.len());
+            self.0 = &self.0[end..];
+        }
+        let len = self
+            .0
+            .iter()
+            .position(|b| b.is_ascii_whitespace())
+            .unwrap_or(self.0.len());
+        if len == 0 {
+            return Err(ImageError::InvalidPnm("unexpected end of file"));
+        }
+        let (token, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(token)
+    }
+    fn number(&mut self) -> Result<u32, ImageError> {
+        std::str::from_utf8(self.token()?)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or(ImageError::InvalidPnm("invalid number"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(size: UVec2, color: png::ColorType, depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut encoder = png::Encoder::new(&mut bytes, size.x, size.y);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+    fn invalid_pnm(bytes: &[u8]) -> &'static str {
+        match RgbaImage::from_bytes(bytes) {
+            Err(ImageError::InvalidPnm(reason)) => reason,
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let pixels = [
+            [1, 2, 3, 4],
+            [5, 6, 7, 8],
+            [9, 10, 11, 12],
+            [255, 0, 0, 255],
+        ];
+        let image = RgbaImage::from_rgba8(uvec2(2, 2), pixels);
+        let decoded = RgbaImage::from_bytes(&image.to_png().unwrap()).unwrap();
+        assert_eq!(decoded.size, uvec2(2, 2));
+        assert_eq!(decoded.pixels, image.pixels);
+        assert_eq!(decoded.get_rgba8(uvec2(1, 1)), Some([255, 0, 0, 255]));
+        assert_eq!(decoded.get(uvec2(2, 0)), None);
+    }
+
+    #[test]
+    fn png_channels_are_expanded() {
+        // 16 bit gray keeps its precision
+        let bytes = png(
+            uvec2(2, 1),
+            png::ColorType::Grayscale,
+            png::BitDepth::Sixteen,
+            &[0x12, 0x34, 0xff, 0xff],
+        );
+        let image = RgbaImage::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            image.pixels,
+            [[0x1234, 0x1234, 0x1234, 0xffff], [0xffff; 4]]
+        );
+
+        let bytes = png(
+            uvec2(1, 1),
+            png::ColorType::GrayscaleAlpha,
+            png::BitDepth::Eight,
+            &[0x80, 0x40],
+        );
+        let image = RgbaImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.get_rgba8(UVec2::ZERO), Some([0x80, 0x80, 0x80, 0x40]));
+
+        let bytes = png(
+            uvec2(1, 1),
+            png::ColorType::Rgb,
+            png::BitDepth::Eight,
+            &[1, 2, 3],
+        );
+        let image = RgbaImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.get_rgba8(UVec2::ZERO), Some([1, 2, 3, 255]));
+    }
+
+    #[test]
+    fn plain_pnm() {
+        let image = RgbaImage::from_bytes(b"P2\n# comment\n2 2\n10\n0 5\n10 12\n").unwrap();
+        assert_eq!(image.size, uvec2(2, 2));
+        assert_eq!(image.luminance(uvec2(0, 0)), Some(0.0));
+        assert!((image.luminance(uvec2(1, 0)).unwrap() - 0.5).abs() < 1e-3);
+        // samples above the maximum are clamped
+        assert_eq!(image.get(uvec2(1, 1)), Some([u16::MAX; 4]));
+
+        let image = RgbaImage::from_bytes(b"P3 1 1 255 255 128 0").unwrap();
+        assert_eq!(image.get_rgba8(UVec2::ZERO), Some([255, 128, 0, 255]));
+    }
+
+    #[test]
+    fn binary_pnm() {
+        let mut bytes = b"P5 2 1 255\n".to_vec();
+        bytes.extend([0, 255]);
+        let image = RgbaImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.pixels, [[0, 0, 0, u16::MAX], [u16::MAX; 4]]);
+
+        // more than 8 bits are big endian
+        let mut bytes = b"P5 1 1 65535\n".to_vec();
+        bytes.extend([0x80, 0x00]);
+        let image = RgbaImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.get(UVec2::ZERO).unwrap()[0], 0x8000);
+
+        let mut bytes = b"P6 1 1 255\n".to_vec();
+        bytes.extend([10, 20, 30]);
+        let image = RgbaImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.get_rgba8(UVec2::ZERO), Some([10, 20, 30, 255]));
+    }
+
+    #[test]
+    fn invalid_images_are_errors() {
+        assert_eq!(invalid_pnm(b"P5 2 2 255\n\0"), "not enough samples");
+        assert_eq!(invalid_pnm(b"P2 2 1 255 0"), "unexpected end of file");
+        assert_eq!(invalid_pnm(b"P5 1 1 0\n\0"), "invalid maximum value");
+        assert_eq!(invalid_pnm(b"P4 1 1\n\0"), "unsupported magic");
+        assert_eq!(invalid_pnm(b"P2 x 1 255 0"), "invalid number");
+        assert!(matches!(
+            RgbaImage::from_bytes(b"GIF89a"),
+            Err(ImageError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn huge_pnm_headers_are_rejected() {
+        assert_eq!(
+            invalid_pnm(b"P6 4294967295 4294967295 65535\n\0"),
+            "image too large"
+        );
+        assert_eq!(invalid_pnm(b"P2 8193 8192 255 0"), "image too large");
+        // the largest allowed image only fails for its missing samples
+        assert_eq!(invalid_pnm(b"P5 8192 8192 255\n\0"), "not enough samples");
+    }
+
+    #[test]
+    fn bilinear_luminance() {
+        let image = RgbaImage::from_rgba8(uvec2(2, 1), [[0, 0, 0, 255], [255, 255, 255, 255]]);
+        assert_eq!(image.sample_luminance(vec2(0.0, 0.5)), 0.0);
+        assert!((image.sample_luminance(vec2(0.5, 0.5)) - 0.5).abs() < 1e-3);
+        assert!((image.sample_luminance(vec2(1.0, 0.5)) - 1.0).abs() < 1e-3);
+        assert_eq!(image.sample_rgba8(vec2(0.75, 0.0)), [255; 4]);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::*;
+use bytemuck::NoUninit;
+use wgpu::*;
+
+// Surface properties of a palette index, the color itself still comes from VoxelColors.
+#[repr(C, align(16))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct VoxelMaterial {
+    pub emissive: f32, // light emitted in multiples of the voxel's color, unaffected by lighting
+    pub roughness: f32, // 0.0 is a sharp highlight, 1.0 is fully diffuse
+    pub metalness: f32, // metals tint their highlight with the voxel's color and have no diffuse light
+    pub flags: u32,
+}
+// SAFETY: the fields are four 4 byte values, which fill the 16 byte alignment without padding, and
+// every one of them is plain data.
+unsafe impl NoUninit for VoxelMaterial {}
+impl VoxelMaterial {
+    // Light passes through the voxel and bends at its surface instead of only being blended by alpha.
+    pub const TRANSPARENT: u32 = 1;
+
+    pub const DEFAULT: Self = Self {
+        emissive: 0.0,
+        roughness: 1.0,
+        metalness: 0.0,
+        flags: 0,
+    };
+
+    pub const fn is_transparent(&self) -> bool {
+        self.flags & Self::TRANSPARENT != 0
+    }
+}
+impl Default for VoxelMaterial {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// Materials of every palette index, parallel to VoxelColors.
+#[derive(Component, Deref, DerefMut, Clone, Copy)]
+pub struct VoxelMaterials([VoxelMaterial; 256]);
+impl VoxelMaterials {
+    pub const fn new(materials: [VoxelMaterial; 256]) -> Self {
+        Self(materials)
+    }
+}
+impl Default for VoxelMaterials {
+    fn default() -> Self {
+        Self([VoxelMaterial::DEFAULT; 256])
+    }
+}
+
+#[derive(Resource, Deref)]
+pub struct MainVoxelMaterials(Entity);
+impl FromWorld for MainVoxelMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let materials = world.spawn(VoxelMaterials::default()).id();
+        Self(materials)
+    }
+}
+impl MainVoxelMaterials {
+    // Renders with the VoxelMaterials of another entity, it's uploaded once the entity has them.
+    pub fn set(&mut self, materials: Entity) {
+        self.0 = materials;
+    }
+}
+
+#[derive(Resource, Deref)]
+pub struct MainMaterialBuffer(Buffer);
+impl MainMaterialBuffer {
+    pub fn new(renderer: &Renderer) -> Self {
+        let buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("Main material buffer"),
+            size: size_of::<[VoxelMaterial; 256]>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self(buffer)
+    }
+    pub fn update(&self, renderer: &Renderer, materials: &VoxelMaterials) {
+        renderer
+            .queue
+            .write_buffer(&self.0, 0, bytemuck::cast_slice(&**materials));
+    }
+}
+impl FromWorld for MainMaterialBuffer {
+    fn from_world(world: &mut World) -> Self {
+        Self::new(world.resource())
+    }
+}
+
+pub(super) fn sync_material_buffer(
+    renderer: Res<Renderer>,
+    material_q: Query<Ref<VoxelMaterials>>,
+    main_material: Res<MainVoxelMaterials>,
+    material_buffer: Res<MainMaterialBuffer>,
+) {
+    let Ok(materials) = material_q.get(**main_material) else {
+        return;
+    };
+    if !materials.is_changed() && !main_material.is_changed() {
+        return;
+    }
+
+    material_buffer.update(&renderer, &materials);
+}
@@ -0,0 +1,572 @@
+use crate::*;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+// How exported meshes carry the voxel colors.
+#[derive(Clone, Copy, Debug)]
+pub enum MeshColors<'a> {
+    Vertex,
+    // a 256x1 texture of the palette, with each vertex's texture coordinate on its palette index
+    PaletteTexture(&'a VoxelColors),
+}
+
+// Indexed triangle mesh made from a voxel, faces are counterclockwise when seen from outside.
+#[derive(Clone, Default, Debug)]
+pub struct VoxelMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub colors: Vec<[u8; 4]>,
+    pub palette_indices: Vec<u8>,
+    pub indices: Vec<u32>,
+}
+impl VoxelMesh {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+    pub fn push_quad(&mut self, corners: [Vec3; 4], normal: Vec3, index: u8, color: [u8; 4]) {
+        let first = self.positions.len() as u32;
+        self.positions.extend(corners);
+        self.normals.extend([normal; 4]);
+        self.colors.extend([color; 4]);
+        self.palette_indices.extend([index; 4]);
+        self.indices
+            .extend([0, 1, 2, 0, 2, 3].map(|corner| first + corner));
+    }
+    pub fn append(&mut self, other: &VoxelMesh) {
+        let first = self.positions.len() as u32;
+        self.positions.extend(&other.positions);
+        self.normals.extend(&other.normals);
+        self.colors.extend(&other.colors);
+        self.palette_indices.extend(&other.palette_indices);
+        self.indices.extend(other.indices.iter().map(|i| first + i));
+    }
+    pub fn transform(&mut self, matrix: Mat4) {
+        let normal_matrix = Mat3::from_mat4(matrix).inverse().transpose();
+        for position in &mut self.positions {
+            *position = matrix.transform_point3(*position);
+        }
+        for normal in &mut self.normals {
+            *normal = (normal_matrix * *normal).normalize_or_zero();
+        }
+        // mirroring transforms turn the faces inside out
+        if matrix.determinant() < 0.0 {
+            for triangle in self.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+    // Moves a mesh in voxel space to where the renderer draws the voxel, the unit cube around the
+    // origin scaled, rotated and moved by the volume's transform.
+    pub fn transform_to_world(&mut self, transform: &GlobalTransform, dimension: UVec3) {
+        self.transform(
+            transform.compute_matrix()
+                * Mat4::from_translation(Vec3::splat(-0.5))
+                * Mat4::from_scale(1.0 / dimension.as_vec3()),
+        );
+    }
+
+    // Vertex colors are written after the positions, texture coordinates are added for PaletteTexture
+    // together with a reference to material, which save_obj writes next to the .obj.
+    pub fn write_obj(
+        &self,
+        mut writer: impl Write,
+        colors: MeshColors,
+        material: Option<&str>,
+    ) -> io::Result<()> {
+        writeln!(writer, "# voxel mesh")?;
+        if let (MeshColors::PaletteTexture(_), Some(material)) = (colors, material) {
+            writeln!(writer, "mtllib {material}.mtl")?;
+            writeln!(writer, "usemtl {material}")?;
+        }
+        for (position, color) in self.positions.iter().zip(&self.colors) {
+            let [r, g, b, _] = color.map(|c| c as f32 / 255.0);
+            writeln!(
+                writer,
+                "v {} {} {} {r} {g} {b}",
+                position.x, position.y, position.z
+            )?;
+        }
+        for normal in &self.normals {
+            writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+        let textured = matches!(colors, MeshColors::PaletteTexture(_));
+        if textured {
+            for &index in &self.palette_indices {
+                let uv = palette_uv(index);
+                writeln!(writer, "vt {} {}", uv.x, uv.y)?;
+            }
+        }
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i + 1);
+            if textured {
+                writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+            } else {
+                writeln!(writer, "f {a}//{a} {b}//{b} {c}//{c}")?;
+            }
+        }
+        Ok(())
+    }
+    // With PaletteTexture the material and the palette texture are written next to the .obj,
+    // named after it.
+    pub fn save_obj(&self, path: impl AsRef<Path>, colors: MeshColors) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut obj = vec![];
+        self.write_obj(&mut obj, colors, Some(&name))?;
+        fs::write(path, obj)?;
+        if let MeshColors::PaletteTexture(palette) = colors {
+            fs::write(
+                path.with_extension("mtl"),
+                format!("newmtl {name}\nKd 1 1 1\nmap_Kd {name}.png\n"),
+            )?;
+            palette.image().save_png(path.with_extension("png"))?;
+        }
+        Ok(())
+    }
+
+    // Binary little endian PLY with normals and RGBA vertex colors.
+    pub fn write_ply(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "ply\nformat binary_little_endian 1.0\ncomment voxel mesh\n\
+             element vertex {}\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property float nx\nproperty float ny\nproperty float nz\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\nproperty uchar alpha\n\
+             element face {}\nproperty list uchar uint vertex_indices\nend_header\n",
+            self.positions.len(),
+            self.indices.len() / 3
+        )?;
+        let mut data = vec![];
+        for ((position, normal), color) in
+            self.positions.iter().zip(&self.normals).zip(&self.colors)
+        {
+            for value in position.to_array().into_iter().chain(normal.to_array()) {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(color);
+        }
+        for triangle in self.indices.chunks_exact(3) {
+            data.push(3);
+            for index in triangle {
+                data.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+        writer.write_all(&data)
+    }
+    pub fn save_ply(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut ply = vec![];
+        self.write_ply(&mut ply)?;
+        fs::write(path, ply)
+    }
+
+    // Binary glTF 2.0 with a single mesh, colors are converted from sRGB to glTF's linear vertex colors.
+    pub fn write_glb(&self, mut writer: impl Write, colors: MeshColors) -> Result<(), ImageError> {
+        let mut bin = GlbBuffer::default();
+        let mut accessors = vec![];
+        let mut attributes = vec![];
+        let count = self.positions.len();
+
+        if !self.is_empty() {
+            let (min, max) = self
+                .positions
+                .iter()
+                .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), &p| {
+                    (min.min(p), max.max(p))
+                });
+            let view = bin.push(bytemuck::cast_slice(&self.positions), Some(34962));
+            attributes.push(format!(r#""POSITION":{}"#, accessors.len()));
+            accessors.push(format!(
+                r#"{{"bufferView":{view},"componentType":5126,"count":{count},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+                min.x, min.y, min.z, max.x, max.y, max.z
+            ));
+            let view = bin.push(bytemuck::cast_slice(&self.normals), Some(34962));
+            attributes.push(format!(r#""NORMAL":{}"#, accessors.len()));
+            accessors.push(format!(
+                r#"{{"bufferView":{view},"componentType":5126,"count":{count},"type":"VEC3"}}"#
+            ));
+            match colors {
+                MeshColors::Vertex => {
+                    let linear: Vec<Vec4> =
+                        self.colors.iter().map(|&c| srgb_to_linear(c)).collect();
+                    let view = bin.push(bytemuck::cast_slice(&linear), Some(34962));
+                    attributes.push(format!(r#""COLOR_0":{}"#, accessors.len()));
+                    accessors.push(format!(
+                        r#"{{"bufferView":{view},"componentType":5126,"count":{count},"type":"VEC4"}}"#
+                    ));
+                }
+                MeshColors::PaletteTexture(_) => {
+                    let uvs: Vec<Vec2> = self
+                        .palette_indices
+                        .iter()
+                        .map(|&i| palette_uv(i))
+                        .collect();
+                    let view = bin.push(bytemuck::cast_slice(&uvs), Some(34962));
+                    attributes.push(format!(r#""TEXCOORD_0":{}"#, accessors.len()));
+                    accessors.push(format!(
+                        r#"{{"bufferView":{view},"componentType":5126,"count":{count},"type":"VEC2"}}"#
+                    ));
+                }
+            }
+            let view = bin.push(bytemuck::cast_slice(&self.indices), Some(34963));
+            accessors.push(format!(
+                r#"{{"bufferView":{view},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+                self.indices.len()
+            ));
+        }
+
+        let blend = self.colors.iter().any(|color| color[3] < 255);
+        let material = format!(
+            r#"{{"pbrMetallicRoughness":{{"metallicFactor":0,"roughnessFactor":1{}}}{}}}"#,
+            match colors {
+                MeshColors::Vertex => "",
+                MeshColors::PaletteTexture(_) => r#","baseColorTexture":{"index":0}"#,
+            },
+            if blend { r#","alphaMode":"BLEND""# } else { "" },
+        );
+        let mut textures = String::new();
+        if let MeshColors::PaletteTexture(palette) = colors {
+            let view = bin.push(&palette.image().to_png()?, None);
+            // nearest filtering keeps neighbouring palette entries from bleeding into each other
+            textures = format!(
+                r#","images":[{{"bufferView":{view},"mimeType":"image/png"}}],"samplers":[{{"magFilter":9728,"minFilter":9728}}],"textures":[{{"source":0,"sampler":0}}]"#
+            );
+        }
+
+        let buffer_views = bin
+            .views
+            .iter()
+            .map(|(offset, len, target)| {
+                let target = target
+                    .map(|t| format!(r#","target":{t}"#))
+                    .unwrap_or_default();
+                format!(r#"{{"buffer":0,"byteOffset":{offset},"byteLength":{len}{target}}}"#)
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let mesh = if self.is_empty() {
+            r#""nodes":[{}]"#.to_string()
+        } else {
+            format!(
+                r#""nodes":[{{"mesh":0}}],"meshes":[{{"primitives":[{{"attributes":{{{}}},"indices":{},"material":0}}]}}],"materials":[{material}],"accessors":[{}]"#,
+                attributes.join(","),
+                accessors.len() - 1,
+                accessors.join(","),
+            )
+        };
+        let buffers = if bin.data.is_empty() {
+            String::new()
+        } else {
+            format!(
+                r#","buffers":[{{"byteLength":{}}}],"bufferViews":[{buffer_views}]"#,
+                bin.data.len()
+            )
+        };
+        let mut json = format!(
+            r#"{{"asset":{{"version":"2.0","generator":"voxel-renderer"}},"scene":0,"scenes":[{{"nodes":[0]}}],{mesh}{buffers}{textures}}}"#
+        )
+        .into_bytes();
+
+        // chunks are padded to 4 bytes, JSON with spaces and binary data with zeros
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut data = bin.data;
+        data.resize(data.len().next_multiple_of(4), 0);
+        let mut length = 12 + 8 + json.len();
+        if !data.is_empty() {
+            length += 8 + data.len();
+        }
+
+        writer.write_all(b"glTF")?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(length as u32).to_le_bytes())?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(b"JSON")?;
+        writer.write_all(&json)?;
+        if !data.is_empty() {
+            writer.write_all(&(data.len() as u32).to_le_bytes())?;
+            writer.write_all(b"BIN\0")?;
+            writer.write_all(&data)?;
+        }
+        Ok(())
+    }
+    pub fn save_glb(&self, path: impl AsRef<Path>, colors: MeshColors) -> Result<(), ImageError> {
+        let mut glb = vec![];
+        self.write_glb(&mut glb, colors)?;
+        fs::write(path, glb)?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct GlbBuffer {
+    data: Vec<u8>,
+    views: Vec<(usize, usize, Option<u32>)>, // offset, length and target
+}
+impl GlbBuffer {
+    // Returns the index of the buffer view holding bytes.
+    fn push(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+        self.views.push((self.data.len(), bytes.len(), target));
+        self.data.extend_from_slice(bytes);
+        self.views.len() - 1
+    }
+}
+
+fn palette_uv(index: u8) -> Vec2 {
+    vec2((index as f32 + 0.5) / 256.0, 0.5)
+}
+pub(super) fn srgb_to_linear(color: [u8; 4]) -> Vec4 {
+    let [r, g, b, a] = color.map(|c| c as f32 / 255.0);
+    decode_srgb(vec3(r, g, b)).extend(a)
+}
+pub(super) fn decode_srgb(color: Vec3) -> Vec3 {
+    let linear = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Vec3::from_array(color.to_array().map(linear))
+}
+pub(super) fn linear_to_srgb(color: Vec4) -> [u8; 4] {
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    let [r, g, b] = color.truncate().to_array().map(encode);
+    [r, g, b, color.w.clamp(0.0, 1.0)].map(|c| (c * 255.0).round() as u8)
+}
+
+impl Voxel {
+    // Merges neighbouring faces with the same palette index into rectangles, only faces next to
+    // empty voxels or the bounds of the volume are kept. Positions are in voxel space.
+    pub fn greedy_mesh(&self, colors: &VoxelColors) -> VoxelMesh {
+        let mut mesh = VoxelMesh::default();
+        let dimension = self.dimension();
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let mut mask = vec![0u8; (dimension[u] * dimension[v]) as usize];
+            for front in [false, true] {
+                let step = if front { 1 } else { -1 };
+                for slice in 0..dimension[axis] {
+                    // palette indices of the visible faces in the slice
+                    for (i, face) in mask.iter_mut().enumerate() {
+                        let mut position = UVec3::ZERO;
+                        position[axis] = slice;
+                        position[u] = i as u32 % dimension[u];
+                        position[v] = i as u32 / dimension[u];
+                        let value = *self.get(position).unwrap();
+                        let mut neighbour = position.as_ivec3();
+                        neighbour[axis] += step;
+                        let covered = neighbour.cmpge(IVec3::ZERO).all()
+                            && self
+                                .get(neighbour.as_uvec3())
+                                .is_some_and(|&value| value != 0);
+                        *face = if covered { 0 } else { value };
+                    }
+
+                    let mut plane = Vec3::ZERO;
+                    plane[axis] = (slice + front as u32) as f32;
+                    let mut normal = Vec3::ZERO;
+                    normal[axis] = step as f32;
+                    let size = uvec2(dimension[u], dimension[v]);
+                    for (start, end, value) in greedy_rectangles(&mut mask, size) {
+                        let corner = |x: u32, y: u32| {
+                            let mut point = plane;
+                            point[u] = x as f32;
+                            point[v] = y as f32;
+                            point
+                        };
+                        let mut corners = [
+                            corner(start.x, start.y),
+                            corner(end.x, start.y),
+                            corner(end.x, end.y),
+                            corner(start.x, end.y),
+                        ];
+                        // u cross v points along the axis, so back faces are wound the other way
+                        if !front {
+                            corners.reverse();
+                        }
+                        mesh.push_quad(corners, normal, value, colors[value as usize]);
+                    }
+                }
+            }
+        }
+        mesh
+    }
+}
+
+// Takes rectangles of equal non-zero values out of the mask, growing each along x and then y,
+// returning the lowest and the exclusive highest corner of each.
+fn greedy_rectangles(mask: &mut [u8], size: UVec2) -> Vec<(UVec2, UVec2, u8)> {
+    let index = |x: u32, y: u32| (x + y * size.x) as usize;
+    let mut rectangles = vec![];
+    for y in 0..size.y {
+        let mut x = 0;
+        while x < size.x {
+            let value = mask[index(x, y)];
+            if value == 0 {
+                x += 1;
+                continue;
+            }
+            let width = (x..size.x)
+                .take_while(|&x| mask[index(x, y)] == value)
+                .count() as u32;
+            let height = (y..size.y)
+                .take_while(|&y| (x..x + width).all(|x| mask[index(x, y)] == value))
+                .count() as u32;
+            for y in y..y + height {
+                mask[index(x, y)..index(x + width, y)].fill(0);
+            }
+            rectangles.push((uvec2(x, y), uvec2(x + width, y + height), value));
+            x += width;
+        }
+    }
+    rectangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid_box(dimension: UVec3, value: u8) -> Voxel {
+        let mut voxel = Voxel::new(dimension);
+        voxel.fill_box(IVec3::ZERO, dimension.as_ivec3() - 1, value);
+        voxel
+    }
+    fn triangles(mesh: &VoxelMesh) -> impl Iterator<Item = [usize; 3]> + '_ {
+        mesh.indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|i| triangle[i] as usize))
+    }
+    // every triangle is counterclockwise around its normal
+    fn assert_wound_outwards(mesh: &VoxelMesh) {
+        for [a, b, c] in triangles(mesh) {
+            let [pa, pb, pc] = [a, b, c].map(|i| mesh.positions[i]);
+            let face = (pb - pa).cross(pc - pa).normalize();
+            assert!(
+                face.dot(mesh.normals[a]) > 0.99,
+                "{face} {}",
+                mesh.normals[a]
+            );
+        }
+    }
+    fn area(mesh: &VoxelMesh) -> f32 {
+        triangles(mesh)
+            .map(|[a, b, c]| {
+                let [pa, pb, pc] = [a, b, c].map(|i| mesh.positions[i]);
+                (pb - pa).cross(pc - pa).length() * 0.5
+            })
+            .sum()
+    }
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn boxes_of_one_value_are_six_quads() {
+        let colors = VoxelColors::all_color();
+        let mesh = solid_box(uvec3(4, 3, 5), 5).greedy_mesh(&colors);
+        assert_eq!(mesh.positions.len(), 24);
+        assert_eq!(mesh.indices.len(), 36);
+        assert_wound_outwards(&mesh);
+        assert!((area(&mesh) - 2.0 * (12.0 + 15.0 + 20.0)).abs() < 1e-3);
+        assert!(mesh.colors.iter().all(|&color| color == colors[5]));
+        assert!(Voxel::new(uvec3(2, 2, 2)).greedy_mesh(&colors).is_empty());
+    }
+
+    #[test]
+    fn faces_split_on_values_and_cavities() {
+        let colors = VoxelColors::all_color();
+        // two values side by side share no face, each side of the pair is split in two
+        let mut voxel = solid_box(uvec3(2, 1, 1), 1);
+        *voxel.get_mut(uvec3(1, 0, 0)).unwrap() = 2;
+        let mesh = voxel.greedy_mesh(&colors);
+        assert_eq!(mesh.indices.len(), 10 * 6);
+        assert_wound_outwards(&mesh);
+
+        // an enclosed cavity adds the six faces around it
+        let mut voxel = solid_box(UVec3::splat(3), 1);
+        *voxel.get_mut(UVec3::ONE).unwrap() = 0;
+        let mesh = voxel.greedy_mesh(&colors);
+        assert_wound_outwards(&mesh);
+        assert!((area(&mesh) - (6.0 * 9.0 + 6.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn mirroring_keeps_the_winding() {
+        let voxel = solid_box(uvec3(2, 1, 1), 1);
+        let mut mesh = voxel.greedy_mesh(&VoxelColors::all_color());
+        let transform =
+            Transform::from_translation(vec3(1.0, 2.0, 3.0)).with_scale(vec3(-2.0, 1.0, 1.0));
+        mesh.transform_to_world(&transform.into(), voxel.dimension());
+        let (min, max) = mesh
+            .positions
+            .iter()
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), &p| {
+                (min.min(p), max.max(p))
+            });
+        assert!(min.abs_diff_eq(vec3(0.0, 1.5, 2.5), 1e-5), "{min}");
+        assert!(max.abs_diff_eq(vec3(2.0, 2.5, 3.5), 1e-5), "{max}");
+        assert_wound_outwards(&mesh);
+    }
+
+    #[test]
+    fn glb_chunks_add_up_to_the_length() {
+        let colors = VoxelColors::all_color();
+        let mesh = solid_box(uvec3(2, 2, 2), 3).greedy_mesh(&colors);
+        for mode in [MeshColors::Vertex, MeshColors::PaletteTexture(&colors)] {
+            let mut glb = vec![];
+            mesh.write_glb(&mut glb, mode).unwrap();
+            assert_eq!(&glb[..4], b"glTF");
+            assert_eq!(u32_at(&glb, 4), 2);
+            assert_eq!(u32_at(&glb, 8) as usize, glb.len());
+            let json_len = u32_at(&glb, 12) as usize;
+            assert_eq!(json_len % 4, 0);
+            assert_eq!(&glb[16..20], b"JSON");
+            let json = std::str::from_utf8(&glb[20..20 + json_len]).unwrap();
+            let bin = 20 + json_len;
+            let bin_len = u32_at(&glb, bin) as usize;
+            assert_eq!(bin_len % 4, 0);
+            assert_eq!(&glb[bin + 4..bin + 8], b"BIN\0");
+            assert_eq!(bin + 8 + bin_len, glb.len());
+            assert!(json.contains(&format!(r#""count":{}"#, mesh.indices.len())));
+            let textured = matches!(mode, MeshColors::PaletteTexture(_));
+            assert_eq!(json.contains("TEXCOORD_0"), textured);
+            assert_eq!(json.contains("COLOR_0"), !textured);
+        }
+
+        // without triangles there's no binary chunk
+        let mut glb = vec![];
+        VoxelMesh::default()
+            .write_glb(&mut glb, MeshColors::Vertex)
+            .unwrap();
+        assert_eq!(u32_at(&glb, 8) as usize, glb.len());
+        assert_eq!(20 + u32_at(&glb, 12) as usize, glb.len());
+    }
+
+    #[test]
+    fn obj_export_reads_back() {
+        let colors = VoxelColors::all_color();
+        let mesh = solid_box(uvec3(3, 2, 1), 7).greedy_mesh(&colors);
+        let mut obj = vec![];
+        mesh.write_obj(&mut obj, MeshColors::PaletteTexture(&colors), Some("box"))
+            .unwrap();
+        let source = std::str::from_utf8(&obj).unwrap();
+        assert!(source.contains("mtllib box.mtl\nusemtl box\n"));
+        let parsed = ObjMesh::parse(source, &Default::default()).unwrap();
+        assert_eq!(parsed.triangles.len(), mesh.indices.len() / 3);
+        for (triangle, [a, b, c]) in parsed.triangles.iter().zip(triangles(&mesh)) {
+            assert_eq!(triangle.positions, [a, b, c].map(|i| mesh.positions[i]));
+        }
+    }
+}
@@ -1,14 +1,58 @@
+pub mod binvox;
+pub mod brush;
 pub mod buffer;
+pub mod bytes;
+pub mod caves;
+pub mod csg;
+pub mod file;
+pub mod heightmap;
+pub mod history;
+pub mod image;
+pub mod material;
+pub mod mesh;
+pub mod noise;
+pub mod obj;
+pub mod octree;
+pub mod palette;
 pub mod pipeline;
+pub mod qb;
+pub mod quantize;
+pub mod raycast;
+pub mod slices;
+pub mod surface;
+pub mod terrain;
+pub mod vox;
+pub mod world;
 
+pub use binvox::*;
+pub use brush::*;
 pub use buffer::*;
+pub use caves::*;
+pub use csg::*;
+pub use file::*;
+pub use heightmap::*;
+pub use history::*;
+pub use image::*;
+pub use material::*;
+pub use mesh::*;
+pub use noise::*;
+pub use obj::*;
+pub use octree::*;
+pub use palette::*;
 pub use pipeline::*;
+pub use qb::*;
+pub use quantize::*;
+pub use raycast::*;
+pub use slices::*;
+pub use terrain::*;
+pub use vox::*;
+pub use world::*;
 
 use crate::*;
 
 #[derive(Bundle)]
-pub struct VoxelBundle {
-    pub voxel: Voxel,
+pub struct VoxelBundle<T: VoxelElement = u8> {
+    pub voxel: Voxel<T>,
     pub per_instance_bind_group: PerInstanceBindGroup,
     pub model_buffer: ModelBuffer,
     pub voxel_buffer: VoxelBuffer,
@@ -16,10 +60,42 @@ pub struct VoxelBundle {
 }
 impl VoxelBundle {
     pub fn new(dimension: UVec3, renderer: &Renderer, pipeline: &Pipeline) -> Self {
+        Self::from_voxel(Voxel::new(dimension), renderer, pipeline)
+    }
+}
+impl<T: VoxelElement> VoxelBundle<T> {
+    pub fn from_voxel(voxel: Voxel<T>, renderer: &Renderer, pipeline: &Pipeline) -> Self {
         let model_buffer = ModelBuffer::new(renderer);
-        let voxel_buffer = VoxelBuffer::new(renderer, dimension);
+        let voxel_buffer = VoxelBuffer::new::<T>(renderer, voxel.dimension());
         Self {
-            voxel: Voxel::new(dimension),
+            voxel,
+            transform: TransformBundle::IDENTITY,
+            per_instance_bind_group: PerInstanceBindGroup::new(
+                renderer,
+                pipeline,
+                &model_buffer,
+                &voxel_buffer,
+            ),
+            model_buffer,
+            voxel_buffer,
+        }
+    }
+}
+
+#[derive(Bundle)]
+pub struct VoxelOctreeBundle {
+    pub octree: VoxelOctree,
+    pub per_instance_bind_group: PerInstanceBindGroup,
+    pub model_buffer: ModelBuffer,
+    pub voxel_buffer: VoxelBuffer,
+    pub transform: TransformBundle,
+}
+impl VoxelOctreeBundle {
+    pub fn new(octree: VoxelOctree, renderer: &Renderer, pipeline: &Pipeline) -> Self {
+        let model_buffer = ModelBuffer::new(renderer);
+        let voxel_buffer = VoxelBuffer::from_octree(renderer, &octree);
+        Self {
+            octree,
             transform: TransformBundle::IDENTITY,
             per_instance_bind_group: PerInstanceBindGroup::new(
                 renderer,
@@ -38,15 +114,35 @@ impl Plugin for VoxelPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<MainVoxelColors>()
             .init_resource::<MainColorBuffer>()
+            .init_resource::<MainLargeVoxelColors>()
+            .init_resource::<MainLargeColorBuffer>()
+            .init_resource::<MainVoxelMaterials>()
+            .init_resource::<MainMaterialBuffer>()
             .init_resource::<Pipeline>()
             .init_resource::<PerRenderBindGroup>();
 
         app.add_systems(
             PostUpdate,
             (
-                (sync_color_buffer, sync_voxel_buffers).before(RenderSystem::Begin),
+                (
+                    (
+                        sync_color_buffer,
+                        sync_large_color_buffer,
+                        sync_material_buffer,
+                        sync_voxel_buffers::<u8>,
+                        sync_voxel_buffers::<u16>,
+                        sync_octree_buffers,
+                    ),
+                    sync_highlights,
+                )
+                    .chain()
+                    .before(RenderSystem::Begin),
                 draw.after(RenderSystem::Begin).before(RenderSystem::End),
             ),
         );
+        app.add_systems(
+            Update,
+            stream_chunks.run_if(contains_resource::<VoxelWorld>),
+        );
     }
 }
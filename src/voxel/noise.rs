@@ -0,0 +1,97 @@
+use crate::*;
+
+// Small deterministic random number generator, so generated content only depends on its seed.
+#[derive(Clone, Copy, Debug)]
+pub struct SplitMix64(u64);
+impl SplitMix64 {
+    pub const fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    // Uniform in 0.0..1.0.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+    pub fn range(&mut self, range: std::ops::Range<f32>) -> f32 {
+        range.start + self.next_f32() * (range.end - range.start)
+    }
+}
+
+// Seeded improved Perlin noise, values are roughly within -1.0..=1.0 and 0.0 at every integer point.
+#[derive(Clone)]
+pub struct PerlinNoise {
+    permutation: [u8; 512], // doubled so that hashing neighbours never wraps
+}
+impl PerlinNoise {
+    pub fn new(seed: u64) -> Self {
+        let mut values: [u8; 256] = std::array::from_fn(|i| i as u8);
+        let mut rng = SplitMix64::new(seed);
+        for i in (1..values.len()).rev() {
+            values.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+        }
+        Self {
+            permutation: std::array::from_fn(|i| values[i % 256]),
+        }
+    }
+    fn hash(&self, cell: IVec3) -> u8 {
+        let p = |i: i32| self.permutation[(i & 0xff) as usize] as i32;
+        self.permutation[(p(p(cell.x) + (cell.y & 0xff)) + (cell.z & 0xff)) as usize]
+    }
+    pub fn get(&self, point: Vec3) -> f32 {
+        fn fade(t: Vec3) -> Vec3 {
+            t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+        }
+        // dot product with one of the 12 cube edge directions picked by the hash
+        fn gradient(hash: u8, d: Vec3) -> f32 {
+            let h = hash & 15;
+            let u = if h < 8 { d.x } else { d.y };
+            let v = if h < 4 {
+                d.y
+            } else if h == 12 || h == 14 {
+                d.x
+            } else {
+                d.z
+            };
+            (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+        }
+
+        let cell = point.floor();
+        let local = point - cell;
+        let cell = cell.as_ivec3();
+        let t = fade(local);
+
+        let corner = |offset: IVec3| gradient(self.hash(cell + offset), local - offset.as_vec3());
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let x00 = lerp(corner(ivec3(0, 0, 0)), corner(ivec3(1, 0, 0)), t.x);
+        let x10 = lerp(corner(ivec3(0, 1, 0)), corner(ivec3(1, 1, 0)), t.x);
+        let x01 = lerp(corner(ivec3(0, 0, 1)), corner(ivec3(1, 0, 1)), t.x);
+        let x11 = lerp(corner(ivec3(0, 1, 1)), corner(ivec3(1, 1, 1)), t.x);
+        lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z)
+    }
+    // Fractal Brownian motion, octaves of noise that each have lacunarity times the frequency
+    // and persistence times the amplitude of the previous one, normalized to the range of a single octave.
+    pub fn fbm(&self, point: Vec3, octaves: u32, lacunarity: f32, persistence: f32) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut total_amplitude = 0.0;
+        let mut point = point;
+        for octave in 0..octaves {
+            // shift every octave so that their zero crossings at integer points don't line up
+            sum += self.get(point + octave as f32 * 19.19) * amplitude;
+            total_amplitude += amplitude;
+            amplitude *= persistence;
+            point *= lacunarity;
+        }
+        if total_amplitude > 0.0 {
+            sum / total_amplitude
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use super::mesh::{decode_srgb, linear_to_srgb};
+use crate::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Parse { line: usize, reason: &'static str },
+    NoTriangles,
+}
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Parse { line, reason } => write!(f, "line {line}: {reason}"),
+            Self::NoTriangles => write!(f, "mesh has no triangles"),
+        }
+    }
+}
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for ObjError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+// Non-empty lines with comments removed, numbered from 1.
+fn lines(source: &str) -> impl Iterator<Item = (usize, &str)> {
+    source
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.is_empty())
+}
+fn parse_floats<const N: usize>(
+    values: &[&str],
+    line: usize,
+    reason: &'static str,
+) -> Result<[f32; N], ObjError> {
+    if values.len() < N {
+        return Err(ObjError::Parse { line, reason });
+    }
+    let mut floats = [0.0; N];
+    for (float, value) in floats.iter_mut().zip(values) {
+        *float = value
+            .parse()
+            .map_err(|_| ObjError::Parse { line, reason })?;
+    }
+    Ok(floats)
+}
+
+// Diffuse colors of the materials in an .mtl file, with the dissolve value as alpha. Kd is sRGB
+// like the colors of textures and is decoded to linear.
+pub fn parse_mtl(source: &str) -> Result<HashMap<String, Vec4>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current = None;
+    for (line, content) in lines(source) {
+        let mut words = content.split_whitespace();
+        let keyword = words.next().unwrap();
+        let values: Vec<&str> = words.collect();
+        match keyword {
+            "newmtl" => {
+                let name = values.join(" ");
+                materials.insert(name.clone(), Vec4::ONE);
+                current = Some(name);
+            }
+            "Kd" | "d" | "Tr" => {
+                let Some(color) = current.as_ref().and_then(|name| materials.get_mut(name)) else {
+                    return Err(ObjError::Parse {
+                        line,
+                        reason: "material property before newmtl",
+                    });
+                };
+                match keyword {
+                    "Kd" => {
+                        *color = decode_srgb(Vec3::from(parse_floats(&values, line, "invalid Kd")?))
+                            .extend(color.w)
+                    }
+                    "d" => color.w = parse_floats::<1>(&values, line, "invalid d")?[0],
+                    _ => color.w = 1.0 - parse_floats::<1>(&values, line, "invalid Tr")?[0],
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ObjTriangle {
+    pub positions: [Vec3; 3],
+    pub colors: [Vec4; 3], // linear RGBA in 0.0..=1.0
+}
+
+// Triangles of an .obj file, faces with more than three vertices are split into fans.
+// Vertex colors written after the position (v x y z r g b) take precedence over the material's diffuse color,
+// they're sRGB like Kd.
+pub struct ObjMesh {
+    pub triangles: Vec<ObjTriangle>,
+}
+impl ObjMesh {
+    // Also reads the .mtl files the .obj refers to, relative to its directory.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ObjError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let mut materials = HashMap::new();
+        for library in material_libraries(&source) {
+            materials.extend(parse_mtl(&fs::read_to_string(directory.join(library))?)?);
+        }
+        Self::parse(&source, &materials)
+    }
+    pub fn parse(source: &str, materials: &HashMap<String, Vec4>) -> Result<Self, ObjError> {
+        let mut positions: Vec<Vec3> = vec![];
+        let mut vertex_colors: Vec<Option<Vec4>> = vec![];
+        let mut color = Vec4::ONE;
+        let mut triangles = vec![];
+        for (line, content) in lines(source) {
+            let mut words = content.split_whitespace();
+            let keyword = words.next().unwrap();
+            let values: Vec<&str> = words.collect();
+            match keyword {
+                "v" => {
+                    positions.push(Vec3::from(parse_floats(&values, line, "invalid vertex")?));
+                    let color = match values.len() {
+                        6 | 7 => {
+                            let color = Vec4::from(parse_floats(
+                                &[&values[3..], &["1"]].concat(),
+                                line,
+                                "invalid vertex color",
+                            )?);
+                            Some(decode_srgb(color.truncate()).extend(color.w))
+                        }
+                        _ => None,
+                    };
+                    vertex_colors.push(color);
+                }
+                "usemtl" => {
+                    color = materials
+                        .get(&values.join(" "))
+                        .copied()
+                        .unwrap_or(Vec4::ONE);
+                }
+                "f" => {
+                    let indices = values
+                        .iter()
+                        .map(|value| {
+                            // v, v/vt, v//vn or v/vt/vn, negative indices count back from the last vertex
+                            let index: i64 = value.split('/').next().unwrap().parse().ok()?;
+                            let index = if index < 0 {
+                                positions.len() as i64 + index
+                            } else {
+                                index - 1
+                            };
+                            (0..positions.len() as i64)
+                                .contains(&index)
+                                .then_some(index as usize)
+                        })
+                        .collect::<Option<Vec<usize>>>()
+                        .ok_or(ObjError::Parse {
+                            line,
+                            reason: "invalid face index",
+                        })?;
+                    if indices.len() < 3 {
+                        return Err(ObjError::Parse {
+                            line,
+                            reason: "face has less than 3 vertices",
+                        });
+                    }
+                    for i in 1..indices.len() - 1 {
+                        let corners = [indices[0], indices[i], indices[i + 1]];
+                        triangles.push(ObjTriangle {
+                            positions: corners.map(|i| positions[i]),
+                            colors: corners.map(|i| vertex_colors[i].unwrap_or(color)),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        if triangles.is_empty() {
+            return Err(ObjError::NoTriangles);
+        }
+        Ok(Self { triangles })
+    }
+
+    // Rasterizes the mesh so that its longest side is resolution voxels long, keeping its Y-up orientation.
+    // Voxels touched by a triangle take the color of the closest point on it, with solid the voxels
+    // enclosed by the surface are filled as well, taking the color of the surface before them along X.
+    // The colors are reduced to a palette by quantizer.
+    pub fn voxelize(
+        &self,
+        resolution: u32,
+        solid: bool,
+        quantizer: &ColorQuantizer,
+    ) -> (Voxel, VoxelColors) {
+        let resolution = resolution.max(1);
+        let (min, max) = self
+            .triangles
+            .iter()
+            .flat_map(|triangle| triangle.positions)
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+                (min.min(p), max.max(p))
+            });
+        let extent = max - min;
+        let longest = extent.max_element();
+        let scale = if longest > 0.0 {
+            resolution as f32 / longest
+        } else {
+            1.0
+        };
+        let dimension = (extent * scale)
+            .ceil()
+            .as_uvec3()
+            .clamp(UVec3::ONE, UVec3::splat(resolution));
+
+        // sum and number of the colors of the triangles touching each voxel
+        let mut colors =
+            vec![(Vec4::ZERO, 0u32); (dimension.x * dimension.y * dimension.z) as usize];
+        for triangle in &self.triangles {
+            let corners = triangle.positions.map(|p| (p - min) * scale);
+            let low = corners[0].min(corners[1]).min(corners[2]);
+            let high = corners[0].max(corners[1]).max(corners[2]);
+            let low = low
+                .floor()
+                .as_ivec3()
+                .max(IVec3::ZERO)
+                .as_uvec3()
+                .min(dimension - 1);
+            let high = high.floor().as_uvec3().min(dimension - 1);
+            for position in VoxelRegion::new(low, high + 1).positions() {
+                let center = position.as_vec3() + 0.5;
+                if !triangle_overlaps_box(corners, center, 0.5) {
+                    continue;
+                }
+                let weights = closest_barycentric(center, corners);
+                let color = triangle.colors[0] * weights.x
+                    + triangle.colors[1] * weights.y
+                    + triangle.colors[2] * weights.z;
+                let (sum, count) = &mut colors[Voxel::get_index(dimension, position).unwrap()];
+                *sum += color;
+                *count += 1;
+            }
+        }
+        let mut surface: Vec<Option<Vec4>> = colors
+            .into_iter()
+            .map(|(sum, count)| (count > 0).then(|| sum / count as f32))
+            .collect();
+
+        if solid {
+            let outside = flood_outside(dimension, &surface);
+            for z in 0..dimension.z {
+                for y in 0..dimension.y {
+                    let mut color = None;
+                    for x in 0..dimension.x {
+                        let index = Voxel::get_index(dimension, uvec3(x, y, z)).unwrap();
+                        match surface[index] {
+                            Some(value) => color = Some(value),
+                            None if !outside[index] => surface[index] = color,
+                            None => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut volume = RgbaVolume::new(dimension);
+        for (rgba, &color) in volume.colors.iter_mut().zip(&surface) {
+            if let Some(color) = color {
+                *rgba = linear_to_srgb(color);
+                // the quantizer leaves transparent colors empty, surface voxels of fully
+                // transparent materials stay filled
+                rgba[3] = rgba[3].max(1);
+            }
+        }
+        quantizer.quantize(&volume)
+    }
+}
+
+// File names of the .mtl files an .obj refers to, a mtllib statement can list several of them.
+fn material_libraries(source: &str) -> impl Iterator<Item = &str> {
+    lines(source).flat_map(|(_, content)| {
+        let mut words = content.split_whitespace();
+        let libraries = (words.next() == Some("mtllib")).then_some(words);
+        libraries.into_iter().flatten()
+    })
+}
+
+// Separating axis test between a triangle and the cube around center, from Akenine-Möller.
+fn triangle_overlaps_box(corners: [Vec3; 3], center: Vec3, half_size: f32) -> bool {
+    let v = corners.map(|corner| corner - center);
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+    let separated = |axis: Vec3| {
+        let projected = v.map(|v| v.dot(axis));
+        let radius = half_size * axis.abs().dot(Vec3::ONE);
+        let min = projected[0].min(projected[1]).min(projected[2]);
+        let max = projected[0].max(projected[1]).max(projected[2]);
+        min > radius || max < -radius
+    };
+    let box_axes = [Vec3::X, Vec3::Y, Vec3::Z];
+    if box_axes.iter().any(|&axis| separated(axis)) {
+        return false;
+    }
+    if separated(edges[0].cross(edges[1])) {
+        return false;
+    }
+    !box_axes
+        .iter()
+        .flat_map(|&axis| edges.map(|edge| axis.cross(edge)))
+        .any(separated)
+}
+
+// Barycentric weights of the point on the triangle closest to point, from Ericson's Real-Time Collision Detection.
+fn closest_barycentric(point: Vec3, [a, b, c]: [Vec3; 3]) -> Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = point - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return Vec3::X;
+    }
+    let bp = point - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return Vec3::Y;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return vec3(1.0 - v, v, 0.0);
+    }
+    let cp = point - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return Vec3::Z;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return vec3(1.0 - w, 0.0, w);
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return vec3(0.0, 1.0 - w, w);
+    }
+    let denominator = va + vb + vc;
+    if denominator == 0.0 {
+        // degenerate triangle
+        return Vec3::X;
+    }
+    let v = vb / denominator;
+    let w = vc / denominator;
+    vec3(1.0 - v - w, v, w)
+}
+
+// Marks the empty voxels connected to the border of the volume, the rest of the empty ones are enclosed.
+fn flood_outside(dimension: UVec3, surface: &[Option<Vec4>]) -> Vec<bool> {
+    let mut outside = vec![false; surface.len()];
+    let mut queue = VecDeque::new();
+    let border = VoxelRegion::new(UVec3::ZERO, dimension)
+        .positions()
+        .filter(|p| p.cmpeq(UVec3::ZERO).any() || p.cmpeq(dimension - 1).any());
+    for position in border {
+        queue.push_back(position);
+    }
+    while let Some(position) = queue.pop_front() {
+        let index = Voxel::get_index(dimension, position).unwrap();
+        if outside[index] || surface[index].is_some() {
+            continue;
+        }
+        outside[index] = true;
+        for axis in 0..3 {
+            for step in [-1, 1] {
+                let mut neighbour = position.as_ivec3();
+                neighbour[axis] += step;
+                if neighbour.cmpge(IVec3::ZERO).all() && neighbour.cmplt(dimension.as_ivec3()).all()
+                {
+                    queue.push_back(neighbour.as_uvec3());
+                }
+            }
+        }
+    }
+    outside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE: &str = "v 0 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nv 0 0 2\nv 2 0 2\nv 2 2 2\nv 0 2 2
+usemtl red
+f 1 4 3 2
+f 5 6 7 8
+f 1 2 6 5
+usemtl blue
+f 4 8 7 3
+f 1 5 8 4
+f -7 -6 -2 -3
+";
+
+    fn materials() -> HashMap<String, Vec4> {
+        parse_mtl("newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\nd 0.5\n").unwrap()
+    }
+    fn filled(voxel: &Voxel) -> usize {
+        VoxelRegion::new(UVec3::ZERO, voxel.dimension())
+            .positions()
+            .filter(|&position| *voxel.get(position).unwrap() != 0)
+            .count()
+    }
+
+    #[test]
+    fn materials_are_decoded_from_srgb() {
+        let materials = parse_mtl("newmtl gray\nKd 0.5 1 0\nTr 0.25\n").unwrap();
+        let gray = materials["gray"];
+        assert!((gray - vec4(0.21404, 1.0, 0.0, 0.75)).abs().max_element() < 1e-4);
+        assert!(matches!(
+            parse_mtl("Kd 1 1 1\n"),
+            Err(ObjError::Parse { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn mtllib_lists_several_libraries() {
+        let source = "mtllib a.mtl  b.mtl\n# mtllib c.mtl\nv 0 0 0\nmtllib d.mtl # e.mtl\n";
+        let libraries: Vec<&str> = material_libraries(source).collect();
+        assert_eq!(libraries, ["a.mtl", "b.mtl", "d.mtl"]);
+    }
+
+    #[test]
+    fn faces_are_split_into_fans() {
+        let source =
+            "v 0 0 0\nv 1 0 0 1 0 0\nv 1 1 0\nv 0 1 0\nusemtl blue\nf 1//1 2/2 -2/3/3 -1\n";
+        let mesh = ObjMesh::parse(source, &materials()).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        assert_eq!(
+            mesh.triangles[0].positions,
+            [Vec3::ZERO, Vec3::X, vec3(1.0, 1.0, 0.0)]
+        );
+        assert_eq!(
+            mesh.triangles[1].positions,
+            [Vec3::ZERO, vec3(1.0, 1.0, 0.0), Vec3::Y]
+        );
+        // the vertex color takes precedence over the material
+        let blue = vec4(0.0, 0.0, 1.0, 0.5);
+        assert_eq!(
+            mesh.triangles[0].colors,
+            [blue, Vec4::new(1.0, 0.0, 0.0, 1.0), blue]
+        );
+    }
+
+    #[test]
+    fn invalid_faces_are_errors() {
+        let none = HashMap::new();
+        assert!(matches!(
+            ObjMesh::parse("v 0 0 0\nf 1 2 3\n", &none),
+            Err(ObjError::Parse { line: 2, .. })
+        ));
+        assert!(matches!(
+            ObjMesh::parse("v 0 0 0\nv 1 0 0\n\nf 1 2\n", &none),
+            Err(ObjError::Parse { line: 4, .. })
+        ));
+        assert!(matches!(
+            ObjMesh::parse("v 0 0 0\n", &none),
+            Err(ObjError::NoTriangles)
+        ));
+    }
+
+    #[test]
+    fn triangles_are_rasterized() {
+        let source = "v 0 0 0 0.2 0.2 0.2\nv 4 0 0 0.2 0.2 0.2\nv 0 4 0 0.2 0.2 0.2\nf 1 2 3\n";
+        let mesh = ObjMesh::parse(source, &HashMap::new()).unwrap();
+        let (voxel, colors) = mesh.voxelize(4, true, &ColorQuantizer::default());
+        assert_eq!(voxel.dimension(), uvec3(4, 4, 1));
+        // the voxels on or below the diagonal are touched
+        for position in VoxelRegion::new(UVec3::ZERO, voxel.dimension()).positions() {
+            let value = *voxel.get(position).unwrap();
+            assert_eq!(value != 0, position.x + position.y <= 4, "{position}");
+        }
+        // the sRGB vertex color comes back unchanged
+        let color = colors[*voxel.get(UVec3::ZERO).unwrap() as usize];
+        assert!(color[..3].iter().all(|&c| c.abs_diff(51) <= 1), "{color:?}");
+        assert_eq!(color[3], 255);
+    }
+
+    #[test]
+    fn closed_meshes_are_filled() {
+        let mesh = ObjMesh::parse(CUBE, &materials()).unwrap();
+        assert_eq!(mesh.triangles.len(), 12);
+        let (voxel, colors) = mesh.voxelize(8, true, &ColorQuantizer::default());
+        assert_eq!(voxel.dimension(), UVec3::splat(8));
+        assert_eq!(filled(&voxel), 512);
+        let bottom = colors[*voxel.get(uvec3(4, 0, 4)).unwrap() as usize];
+        assert_eq!(bottom, [255, 0, 0, 255]);
+        let top = colors[*voxel.get(uvec3(4, 7, 4)).unwrap() as usize];
+        assert_eq!(top, [0, 0, 255, 128]);
+
+        let (voxel, _) = mesh.voxelize(16, false, &ColorQuantizer::default());
+        assert_eq!(*voxel.get(UVec3::splat(8)).unwrap(), 0);
+        assert_ne!(*voxel.get(uvec3(8, 0, 8)).unwrap(), 0);
+        assert_eq!(filled(&voxel), 16 * 16 * 16 - 14 * 14 * 14);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::*;
+
+// Nodes are stored the same way on the cpu and the gpu. A node with this bit set is a leaf
+// whose low 8 bits are the palette index of every voxel it covers, otherwise it is the index
+// of the first of its 8 children, which are always stored next to each other.
+pub const OCTREE_LEAF: u32 = 1 << 31;
+
+fn child_offset(child: u32) -> UVec3 {
+    uvec3(child & 1, child >> 1 & 1, child >> 2 & 1)
+}
+fn child_index(position: UVec3, level: u32) -> u32 {
+    (position.x >> level & 1) | (position.y >> level & 1) << 1 | (position.z >> level & 1) << 2
+}
+
+#[derive(Component, Clone)]
+pub struct VoxelOctree {
+    dimension: UVec3,
+    depth: u32,
+    nodes: Vec<u32>, // root is always the first node
+    free: Vec<u32>,  // child blocks of collapsed nodes that can be reused
+}
+impl VoxelOctree {
+    pub fn new(dimension: UVec3) -> Self {
+        Self {
+            dimension,
+            depth: dimension.max_element().next_power_of_two().trailing_zeros(),
+            nodes: vec![OCTREE_LEAF],
+            free: vec![],
+        }
+    }
+    pub fn from_voxel(voxel: &Voxel) -> Self {
+        let mut octree = Self::new(voxel.dimension());
+        octree.nodes[0] = octree.build(voxel, UVec3::ZERO, octree.depth);
+        octree
+    }
+    fn build(&mut self, voxel: &Voxel, origin: UVec3, level: u32) -> u32 {
+        if origin.cmpge(self.dimension).any() {
+            return OCTREE_LEAF;
+        }
+        if level == 0 {
+            return OCTREE_LEAF | *voxel.get(origin).unwrap() as u32;
+        }
+        let half = 1 << (level - 1);
+        let children: [u32; 8] = std::array::from_fn(|i| {
+            self.build(voxel, origin + child_offset(i as u32) * half, level - 1)
+        });
+        if children[0] & OCTREE_LEAF != 0 && children.iter().all(|&c| c == children[0]) {
+            return children[0];
+        }
+        self.allocate(children)
+    }
+    fn allocate(&mut self, children: [u32; 8]) -> u32 {
+        let index = self.free.pop().unwrap_or_else(|| {
+            let index = self.nodes.len() as u32;
+            self.nodes.extend_from_slice(&[0; 8]);
+            index
+        });
+        self.nodes[index as usize..index as usize + 8].copy_from_slice(&children);
+        index
+    }
+    pub fn to_voxel(&self) -> Voxel {
+        let mut voxel = Voxel::new(self.dimension);
+        self.fill(&mut voxel, self.nodes[0], UVec3::ZERO, self.depth);
+        voxel
+    }
+    fn fill(&self, voxel: &mut Voxel, node: u32, origin: UVec3, level: u32) {
+        if node & OCTREE_LEAF != 0 {
+            let value = node as u8;
+            if value == 0 {
+                return;
+            }
+            let max = (origin + (1 << level)).min(self.dimension);
+            for z in origin.z..max.z {
+                for y in origin.y..max.y {
+                    for x in origin.x..max.x {
+                        *voxel.get_mut(uvec3(x, y, z)).unwrap() = value;
+                    }
+                }
+            }
+            return;
+        }
+        let half = 1 << (level - 1);
+        for child in 0..8 {
+            self.fill(
+                voxel,
+                self.nodes[(node + child) as usize],
+                origin + child_offset(child) * half,
+                level - 1,
+            );
+        }
+    }
+    pub const fn dimension(&self) -> UVec3 {
+        self.dimension
+    }
+    pub const fn depth(&self) -> u32 {
+        self.depth
+    }
+    pub fn nodes(&self) -> &[u32] {
+        &self.nodes
+    }
+    pub fn get(&self, position: UVec3) -> Option<u8> {
+        if position.cmpge(self.dimension).any() {
+            return None;
+        }
+        let mut node = self.nodes[0];
+        let mut level = self.depth;
+        while node & OCTREE_LEAF == 0 {
+            level -= 1;
+            node = self.nodes[(node + child_index(position, level)) as usize];
+        }
+        Some(node as u8)
+    }
+    // Returns the previous value, or None if the position is outside of the dimension.
+    pub fn set(&mut self, position: UVec3, value: u8) -> Option<u8> {
+        if position.cmpge(self.dimension).any() {
+            return None;
+        }
+        let leaf = OCTREE_LEAF | value as u32;
+        let mut path = Vec::with_capacity(self.depth as usize);
+        let mut slot = 0;
+        let mut level = self.depth;
+        let previous = loop {
+            let node = self.nodes[slot];
+            if node & OCTREE_LEAF == 0 {
+                path.push(slot);
+                level -= 1;
+                slot = (node + child_index(position, level)) as usize;
+                continue;
+            }
+            if node == leaf {
+                return Some(value);
+            }
+            if level == 0 {
+                self.nodes[slot] = leaf;
+                break node as u8;
+            }
+            // split the leaf so that only the voxel at position changes
+            self.nodes[slot] = self.allocate([node; 8]);
+        };
+
+        // merge every parent whose children became the same leaf
+        for &parent in path.iter().rev() {
+            let first = self.nodes[parent];
+            let children = &self.nodes[first as usize..first as usize + 8];
+            if children[0] & OCTREE_LEAF == 0 || children.iter().any(|&c| c != children[0]) {
+                break;
+            }
+            self.nodes[parent] = children[0];
+            self.free.push(first);
+        }
+        // splits reuse the blocks freed by merges, but once most of the list is unused it's rebuilt
+        if self.free.len() * 8 * 2 > self.nodes.len() {
+            self.compact();
+        }
+        Some(previous)
+    }
+    // Rebuilds the node list without the unused blocks left behind by set.
+    pub fn compact(&mut self) {
+        let mut nodes = vec![self.nodes[0]];
+        let mut stack = vec![0];
+        while let Some(slot) = stack.pop() {
+            let node = nodes[slot];
+            if node & OCTREE_LEAF != 0 {
+                continue;
+            }
+            let first = nodes.len();
+            nodes.extend_from_slice(&self.nodes[node as usize..node as usize + 8]);
+            nodes[slot] = first as u32;
+            stack.extend(first..first + 8);
+        }
+        self.nodes = nodes;
+        self.free.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(dimension: UVec3) -> Voxel {
+        let mut voxel = Voxel::new(dimension);
+        voxel.for_each_mut(|value, position| {
+            // solid below a slope with a few scattered voxels above it
+            *value = if position.y <= position.x / 2 {
+                1
+            } else if (position.x * 7 + position.y * 3 + position.z * 5) % 11 == 0 {
+                2
+            } else {
+                0
+            };
+        });
+        voxel
+    }
+    fn assert_contents(octree: &VoxelOctree, voxel: &Voxel) {
+        for position in VoxelRegion::new(UVec3::ZERO, voxel.dimension()).positions() {
+            assert_eq!(octree.get(position), voxel.get(position).copied());
+        }
+    }
+
+    #[test]
+    fn set_splits_and_merges_leaves() {
+        let mut octree = VoxelOctree::new(uvec3(5, 3, 6));
+        assert_eq!(octree.nodes().len(), 1);
+        assert_eq!(octree.set(uvec3(3, 2, 5), 7), Some(0));
+        assert_eq!(octree.get(uvec3(3, 2, 5)), Some(7));
+        assert_eq!(octree.get(uvec3(3, 2, 4)), Some(0));
+        assert_eq!(octree.get(uvec3(0, 0, 0)), Some(0));
+        assert_eq!(octree.nodes().len(), 1 + 8 * octree.depth() as usize);
+
+        assert_eq!(octree.set(uvec3(3, 2, 5), 7), Some(7));
+        assert_eq!(octree.set(uvec3(3, 2, 5), 0), Some(7));
+        assert_eq!(octree.get(uvec3(3, 2, 5)), Some(0));
+        assert_eq!(octree.nodes()[0], OCTREE_LEAF);
+
+        assert_eq!(octree.set(uvec3(5, 0, 0), 1), None);
+        assert_eq!(octree.get(uvec3(0, 3, 0)), None);
+    }
+
+    #[test]
+    fn set_matches_a_dense_volume() {
+        let mut voxel = pattern(uvec3(16, 12, 9));
+        let mut octree = VoxelOctree::from_voxel(&voxel);
+        assert_contents(&octree, &voxel);
+        assert_contents(&octree, &octree.to_voxel());
+
+        for position in VoxelRegion::new(uvec3(2, 0, 1), uvec3(14, 9, 8)).positions() {
+            let value = (position.x + position.z) as u8 % 3;
+            let previous = *voxel.get(position).unwrap();
+            assert_eq!(octree.set(position, value), Some(previous));
+            *voxel.get_mut(position).unwrap() = value;
+        }
+        assert_contents(&octree, &voxel);
+    }
+
+    #[test]
+    fn compaction_keeps_contents() {
+        let voxel = pattern(UVec3::splat(16));
+        let mut octree = VoxelOctree::from_voxel(&voxel);
+        for position in VoxelRegion::new(UVec3::ZERO, uvec3(16, 16, 8)).positions() {
+            octree.set(position, 0);
+        }
+        let mut expected = voxel.clone();
+        for position in VoxelRegion::new(UVec3::ZERO, uvec3(16, 16, 8)).positions() {
+            *expected.get_mut(position).unwrap() = 0;
+        }
+        assert_contents(&octree, &expected);
+
+        let len = octree.nodes().len();
+        octree.compact();
+        assert!(octree.free.is_empty());
+        assert!(octree.nodes().len() <= len);
+        assert_contents(&octree, &expected);
+
+        // clearing the rest merges everything back into the root, which compacts the list
+        for position in VoxelRegion::new(UVec3::ZERO, UVec3::splat(16)).positions() {
+            octree.set(position, 0);
+        }
+        assert_eq!(octree.nodes(), &[OCTREE_LEAF]);
+    }
+}
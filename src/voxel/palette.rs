@@ -0,0 +1,301 @@
+use crate::*;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(io::Error),
+    Image(ImageError),
+    Parse { line: usize, reason: &'static str },
+    TooManyColors(usize),
+    InvalidImageSize(UVec2),
+    UnknownFormat,
+}
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Image(e) => write!(f, "{e}"),
+            Self::Parse { line, reason } => write!(f, "line {line}: {reason}"),
+            Self::TooManyColors(len) => {
+                write!(f, "palette has {len} colors but at most 255 fit")
+            }
+            Self::InvalidImageSize(size) => write!(
+                f,
+                "palette image is {}x{} instead of at most 256x1",
+                size.x, size.y
+            ),
+            Self::UnknownFormat => write!(f, "not a .gpl, .hex or .png palette"),
+        }
+    }
+}
+impl std::error::Error for PaletteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for PaletteError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<ImageError> for PaletteError {
+    fn from(value: ImageError) -> Self {
+        Self::Image(value)
+    }
+}
+
+// .gpl and .hex files list colors without an empty entry, so their first color is index 1
+// and alpha is 255 unless the .hex color has one. A .png strip maps pixel x to index x directly.
+impl VoxelColors {
+    // The format is picked by the extension of path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PaletteError> {
+        let path = path.as_ref();
+        match extension(path).as_deref() {
+            Some("gpl") => Self::from_gpl(&fs::read_to_string(path)?),
+            Some("hex") => Self::from_hex(&fs::read_to_string(path)?),
+            Some("png") => Self::from_image(&RgbaImage::open(path)?),
+            _ => Err(PaletteError::UnknownFormat),
+        }
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), PaletteError> {
+        let path = path.as_ref();
+        match extension(path).as_deref() {
+            Some("gpl") => {
+                let name = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let mut gpl = vec![];
+                self.write_gpl(&mut gpl, &name)?;
+                fs::write(path, gpl)?;
+            }
+            Some("hex") => {
+                let mut hex = vec![];
+                self.write_hex(&mut hex)?;
+                fs::write(path, hex)?;
+            }
+            Some("png") => self.image().save_png(path)?,
+            _ => return Err(PaletteError::UnknownFormat),
+        }
+        Ok(())
+    }
+    fn from_list(colors: Vec<[u8; 4]>) -> Result<Self, PaletteError> {
+        if colors.len() > 255 {
+            return Err(PaletteError::TooManyColors(colors.len()));
+        }
+        let mut palette = [[0; 4]; 256];
+        palette[1..=colors.len()].copy_from_slice(&colors);
+        Ok(Self::new(palette))
+    }
+    // Entries from index 1 up to the last one that isn't [0, 0, 0, 0].
+    fn entries(&self) -> &[[u8; 4]] {
+        let len = self.iter().rposition(|&color| color != [0; 4]).unwrap_or(0);
+        &self[1..=len]
+    }
+
+    // GIMP palette.
+    pub fn from_gpl(source: &str) -> Result<Self, PaletteError> {
+        let mut lines = source
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()));
+        if lines.next().map(|(_, line)| line) != Some("GIMP Palette") {
+            return Err(PaletteError::Parse {
+                line: 1,
+                reason: "missing GIMP Palette header",
+            });
+        }
+        let mut colors = vec![];
+        for (line, content) in lines {
+            if content.is_empty()
+                || content.starts_with('#')
+                || content.starts_with("Name:")
+                || content.starts_with("Columns:")
+            {
+                continue;
+            }
+            // red, green and blue followed by an optional name
+            let rgb: Option<Vec<u8>> = content
+                .split_whitespace()
+                .take(3)
+                .map(|value| value.parse().ok())
+                .collect();
+            match rgb.as_deref() {
+                Some(&[r, g, b]) => colors.push([r, g, b, 255]),
+                _ => {
+                    return Err(PaletteError::Parse {
+                        line,
+                        reason: "invalid color",
+                    })
+                }
+            }
+        }
+        Self::from_list(colors)
+    }
+    pub fn write_gpl(&self, mut writer: impl Write, name: &str) -> io::Result<()> {
+        write!(writer, "GIMP Palette\nName: {name}\nColumns: 16\n#\n")?;
+        for (i, [r, g, b, _]) in self.entries().iter().enumerate() {
+            writeln!(writer, "{r:3} {g:3} {b:3}\tIndex {}", i + 1)?;
+        }
+        Ok(())
+    }
+
+    // Lospec's format of one RRGGBB color per line, RRGGBBAA is accepted as well.
+    pub fn from_hex(source: &str) -> Result<Self, PaletteError> {
+        let mut colors = vec![];
+        for (i, content) in source.lines().enumerate() {
+            let content = content.trim().trim_start_matches('#');
+            if content.is_empty() {
+                continue;
+            }
+            let channel = |c: usize| u8::from_str_radix(content.get(c * 2..c * 2 + 2)?, 16).ok();
+            let color = match content.len() {
+                6 => (0..3).map(channel).chain([Some(255)]).collect(),
+                8 => (0..4).map(channel).collect(),
+                _ => None,
+            };
+            let color: Vec<u8> = color.ok_or(PaletteError::Parse {
+                line: i + 1,
+                reason: "invalid hex color",
+            })?;
+            colors.push(color.try_into().unwrap());
+        }
+        Self::from_list(colors)
+    }
+    pub fn write_hex(&self, mut writer: impl Write) -> io::Result<()> {
+        for &[r, g, b, a] in self.entries() {
+            match a {
+                255 => writeln!(writer, "{r:02x}{g:02x}{b:02x}")?,
+                _ => writeln!(writer, "{r:02x}{g:02x}{b:02x}{a:02x}")?,
+            }
+        }
+        Ok(())
+    }
+
+    // Image of a single row of at most 256 pixels, missing entries are empty.
+    pub fn from_image(image: &RgbaImage) -> Result<Self, PaletteError> {
+        if image.size.y != 1 || image.size.x > 256 {
+            return Err(PaletteError::InvalidImageSize(image.size));
+        }
+        let mut palette = [[0; 4]; 256];
+        for (x, color) in palette.iter_mut().enumerate().take(image.size.x as usize) {
+            *color = image.get_rgba8(uvec2(x as u32, 0)).unwrap();
+        }
+        Ok(Self::new(palette))
+    }
+    // 256x1 image of every entry.
+    pub fn image(&self) -> RgbaImage {
+        RgbaImage::from_rgba8(uvec2(256, 1), self.iter().copied())
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut bytes = vec![];
+        write(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn gpl_round_trip() {
+        let source = "GIMP Palette\nName: test\nColumns: 4\n# comment\n  0   0   0\tBlack\n255 128 1 Orange\n\n";
+        let colors = VoxelColors::from_gpl(source).unwrap();
+        assert_eq!(
+            colors[..4],
+            [[0; 4], [0, 0, 0, 255], [255, 128, 1, 255], [0; 4]]
+        );
+        let gpl = written(|writer| colors.write_gpl(writer, "test"));
+        assert_eq!(
+            gpl,
+            "GIMP Palette\nName: test\nColumns: 16\n#\n  0   0   0\tIndex 1\n255 128   1\tIndex 2\n"
+        );
+        assert_eq!(*VoxelColors::from_gpl(&gpl).unwrap(), *colors);
+
+        // alpha is lost
+        let all = VoxelColors::all_color();
+        let gpl = written(|writer| all.write_gpl(writer, "all"));
+        let read = VoxelColors::from_gpl(&gpl).unwrap();
+        for (read, color) in read.iter().zip(all.iter()).skip(1) {
+            assert_eq!(read[..3], color[..3]);
+            assert_eq!(read[3], 255);
+        }
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let colors = VoxelColors::from_hex("ff0000\n#00ff0080\r\n\n0000FF\n").unwrap();
+        assert_eq!(
+            colors[..4],
+            [[0; 4], [255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 255]]
+        );
+        let hex = written(|writer| colors.write_hex(writer));
+        assert_eq!(hex, "ff0000\n00ff0080\n0000ff\n");
+
+        let all = VoxelColors::all_color();
+        let read = VoxelColors::from_hex(&written(|writer| all.write_hex(writer))).unwrap();
+        assert_eq!(*read, *all);
+    }
+
+    #[test]
+    fn png_strip_round_trip() {
+        let all = VoxelColors::all_color();
+        let image = RgbaImage::from_bytes(&all.image().to_png().unwrap()).unwrap();
+        assert_eq!(*VoxelColors::from_image(&image).unwrap(), *all);
+
+        // pixel x is index x, entries past the strip are empty
+        let strip = RgbaImage::from_rgba8(uvec2(3, 1), [[1, 2, 3, 4], [5, 6, 7, 8], [9; 4]]);
+        let colors = VoxelColors::from_image(&strip).unwrap();
+        assert_eq!(colors[..4], [[1, 2, 3, 4], [5, 6, 7, 8], [9; 4], [0; 4]]);
+        let column = RgbaImage::from_rgba8(uvec2(1, 2), [[0; 4]; 2]);
+        assert!(matches!(
+            VoxelColors::from_image(&column),
+            Err(PaletteError::InvalidImageSize(_))
+        ));
+    }
+
+    #[test]
+    fn at_most_255_colors_fit() {
+        let colors = VoxelColors::from_hex(&"010203\n".repeat(255)).unwrap();
+        assert_eq!(colors[0], [0; 4]);
+        assert_eq!(colors[255], [1, 2, 3, 255]);
+        assert!(matches!(
+            VoxelColors::from_hex(&"010203\n".repeat(256)),
+            Err(PaletteError::TooManyColors(256))
+        ));
+        let gpl = format!("GIMP Palette\n{}", "1 2 3\n".repeat(256));
+        assert!(matches!(
+            VoxelColors::from_gpl(&gpl),
+            Err(PaletteError::TooManyColors(256))
+        ));
+    }
+
+    #[test]
+    fn invalid_palettes_are_errors() {
+        let line = |result| match result {
+            Err(PaletteError::Parse { line, .. }) => line,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(line(VoxelColors::from_gpl("255 0 0\n")), 1);
+        assert_eq!(line(VoxelColors::from_gpl("GIMP Palette\n\n255 x 0\n")), 3);
+        assert_eq!(line(VoxelColors::from_hex("ff0000\nfff\n")), 2);
+        assert!(matches!(
+            VoxelColors::open("palette.txt"),
+            Err(PaletteError::UnknownFormat)
+        ));
+    }
+}
@@ -2,7 +2,6 @@ use bevy::math::*;
 use bevy::prelude::*;
 use wgpu::*;
 
-use crate::model::*;
 use crate::*;
 
 #[derive(Resource)]
@@ -48,7 +47,7 @@ impl FromWorld for Pipeline {
                     ],
                 });
 
-        // camera and color layout
+        // camera, color, large color and material layout
         let per_render_layout =
             renderer
                 .device
@@ -57,6 +56,12 @@ impl FromWorld for Pipeline {
                     entries: &[
                         create_entry(0, BufferBindingType::Uniform, ShaderStages::VERTEX),
                         create_entry(1, BufferBindingType::Uniform, ShaderStages::FRAGMENT),
+                        create_entry(
+                            2,
+                            BufferBindingType::Storage { read_only: true },
+                            ShaderStages::FRAGMENT,
+                        ),
+                        create_entry(3, BufferBindingType::Uniform, ShaderStages::FRAGMENT),
                     ],
                 });
 
@@ -144,6 +149,8 @@ impl PerRenderBindGroup {
         pipeline: &Pipeline,
         camera_buffer: &MainCameraBuffer,
         color_buffer: &MainColorBuffer,
+        large_color_buffer: &MainLargeColorBuffer,
+        material_buffer: &MainMaterialBuffer,
     ) -> Self {
         Self(renderer.device.create_bind_group(&BindGroupDescriptor {
             label: Some("Voxel per render bind group"),
@@ -151,6 +158,8 @@ impl PerRenderBindGroup {
             entries: &[
                 create_entry(0, &camera_buffer),
                 create_entry(1, &color_buffer),
+                create_entry(2, large_color_buffer),
+                create_entry(3, material_buffer),
             ],
         }))
     }
@@ -162,6 +171,8 @@ impl FromWorld for PerRenderBindGroup {
             world.resource(),
             world.resource(),
             world.resource(),
+            world.resource(),
+            world.resource(),
         )
     }
 }
@@ -0,0 +1,326 @@
+use super::bytes::{voxel_count, ByteReader, UnexpectedEof, MAX_VOXEL_COUNT};
+use crate::*;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Qubicle's run length encoding markers in compressed matrices.
+const CODE_FLAG: u32 = 2;
+const NEXT_SLICE_FLAG: u32 = 6;
+
+#[derive(Debug)]
+pub enum QbError {
+    Io(io::Error),
+    UnexpectedEof,
+    UnsupportedColorFormat(u32),
+    InvalidMatrix(&'static str),
+}
+impl fmt::Display for QbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::UnexpectedEof => write!(f, "unexpected end of file"),
+            Self::UnsupportedColorFormat(format) => {
+                write!(f, "unsupported color format {format}")
+            }
+            Self::InvalidMatrix(reason) => write!(f, "invalid matrix: {reason}"),
+        }
+    }
+}
+impl std::error::Error for QbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for QbError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<UnexpectedEof> for QbError {
+    fn from(_: UnexpectedEof) -> Self {
+        Self::UnexpectedEof
+    }
+}
+
+pub struct QbMatrix {
+    pub name: String,
+    pub position: IVec3, // of the lowest corner, in voxels
+    pub voxel: Voxel,
+}
+
+// Qubicle binary file, its matrices store a color per voxel, which are quantized into one shared palette.
+pub struct QbScene {
+    pub matrices: Vec<QbMatrix>,
+    pub colors: VoxelColors,
+}
+impl QbScene {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, QbError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, QbError> {
+        let mut reader = ByteReader(bytes);
+        let _version = reader.u32()?;
+        let bgra = match reader.u32()? {
+            0 => false,
+            1 => true,
+            format => return Err(QbError::UnsupportedColorFormat(format)),
+        };
+        let left_handed = reader.u32()? == 0;
+        let compressed = reader.u32()? != 0;
+        // with visibility masks alpha holds the visible sides, which is still 0 for empty voxels
+        let _visibility_mask_encoded = reader.u32()?;
+        let matrix_count = reader.u32()?;
+
+        // matrices are read as true colors and quantized together once all of them are known
+        let mut matrices = vec![];
+        let mut total_len = 0;
+        for _ in 0..matrix_count {
+            let name_len = reader.u8()? as usize;
+            let name = String::from_utf8_lossy(reader.take(name_len)?).into_owned();
+            let size = uvec3(reader.u32()?, reader.u32()?, reader.u32()?);
+            let mut position = ivec3(reader.i32()?, reader.i32()?, reader.i32()?);
+            let len = voxel_count(size)
+                .filter(|&len| len > 0)
+                .ok_or(QbError::InvalidMatrix("invalid size"))?;
+            // every matrix is kept until they are quantized, so the cap applies to all of them
+            total_len += len;
+            if total_len > MAX_VOXEL_COUNT {
+                return Err(QbError::InvalidMatrix("too many voxels"));
+            }
+
+            let mut matrix = vec![0u32; len];
+            let slice_len = size.x as usize * size.y as usize;
+            if compressed {
+                for slice in matrix.chunks_exact_mut(slice_len) {
+                    let mut index = 0;
+                    loop {
+                        let data = reader.u32()?;
+                        let (count, data) = match data {
+                            NEXT_SLICE_FLAG => break,
+                            CODE_FLAG => (reader.u32()? as usize, reader.u32()?),
+                            _ => (1, data),
+                        };
+                        slice
+                            .get_mut(index..index + count)
+                            .ok_or(QbError::InvalidMatrix("run past the end of a slice"))?
+                            .fill(data);
+                        index += count;
+                    }
+                }
+            } else {
+                for data in &mut matrix {
+                    *data = reader.u32()?;
+                }
+            }
+
+            // right-handed Qubicle files share our axes, left-handed ones have Z mirrored
+            if left_handed {
+                position.z = -(position.z + size.z as i32);
+            }
+            let mut volume = RgbaVolume::new(size);
+            for (i, &data) in matrix.iter().enumerate() {
+                let [mut r, g, mut b, a] = data.to_le_bytes();
+                if a == 0 {
+                    continue;
+                }
+                if bgra {
+                    std::mem::swap(&mut r, &mut b);
+                }
+                let mut position = uvec3(
+                    i as u32 % size.x,
+                    i as u32 / size.x % size.y,
+                    i as u32 / (size.x * size.y),
+                );
+                if left_handed {
+                    position.z = size.z - 1 - position.z;
+                }
+                *volume.get_mut(position).unwrap() = [r, g, b, 255];
+            }
+            matrices.push((name, position, volume));
+        }
+
+        let quantizer = ColorQuantizer::default();
+        let colors: Vec<[u8; 4]> = matrices
+            .iter()
+            .flat_map(|(_, _, volume)| volume.colors.iter().copied())
+            .collect();
+        let palette = quantizer.palette(&colors);
+        let matrices = matrices
+            .into_iter()
+            .map(|(name, position, volume)| QbMatrix {
+                name,
+                position,
+                voxel: quantizer.remap(&volume, &palette),
+            })
+            .collect();
+        Ok(Self {
+            matrices,
+            colors: palette,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u32 = u32::from_le_bytes([255, 0, 0, 255]);
+    const BLUE: u32 = u32::from_le_bytes([0, 0, 255, 255]);
+
+    fn header(bgra: bool, right_handed: bool, compressed: bool, matrix_count: u32) -> Vec<u8> {
+        [
+            257,
+            bgra as u32,
+            right_handed as u32,
+            compressed as u32,
+            0,
+            matrix_count,
+        ]
+        .into_iter()
+        .flat_map(u32::to_le_bytes)
+        .collect()
+    }
+    fn matrix(bytes: &mut Vec<u8>, name: &str, size: UVec3, position: IVec3, data: &[u32]) {
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend(size.to_array().into_iter().flat_map(u32::to_le_bytes));
+        bytes.extend(position.to_array().into_iter().flat_map(i32::to_le_bytes));
+        bytes.extend(data.iter().flat_map(|data| data.to_le_bytes()));
+    }
+    fn color(scene: &QbScene, matrix: usize, position: UVec3) -> [u8; 4] {
+        let index = *scene.matrices[matrix].voxel.get(position).unwrap();
+        scene.colors[index as usize]
+    }
+
+    #[test]
+    fn uncompressed_matrices_are_read() {
+        let mut bytes = header(false, true, false, 2);
+        matrix(
+            &mut bytes,
+            "a",
+            uvec3(2, 1, 2),
+            ivec3(5, 6, 7),
+            &[RED, 0, 0, BLUE],
+        );
+        matrix(&mut bytes, "b", UVec3::ONE, ivec3(-1, 0, 0), &[BLUE]);
+        let scene = QbScene::from_bytes(&bytes).unwrap();
+
+        assert_eq!(scene.matrices.len(), 2);
+        assert_eq!(scene.matrices[0].name, "a");
+        assert_eq!(scene.matrices[0].position, ivec3(5, 6, 7));
+        assert_eq!(scene.matrices[0].voxel.dimension(), uvec3(2, 1, 2));
+        assert_eq!(color(&scene, 0, uvec3(0, 0, 0)), [255, 0, 0, 255]);
+        assert_eq!(color(&scene, 0, uvec3(1, 0, 1)), [0, 0, 255, 255]);
+        assert_eq!(scene.matrices[0].voxel.get(uvec3(1, 0, 0)), Some(&0));
+        assert_eq!(scene.matrices[0].voxel.get(uvec3(0, 0, 1)), Some(&0));
+        // both matrices share one palette
+        assert_eq!(scene.matrices[1].name, "b");
+        assert_eq!(scene.matrices[1].position, ivec3(-1, 0, 0));
+        assert_eq!(
+            scene.matrices[1].voxel.get(UVec3::ZERO),
+            scene.matrices[0].voxel.get(uvec3(1, 0, 1))
+        );
+    }
+
+    #[test]
+    fn compressed_matrices_are_read() {
+        // one slice per z, a run of two reds and a single blue
+        let mut bytes = header(true, true, true, 1);
+        let slices = [CODE_FLAG, 2, RED, NEXT_SLICE_FLAG, 0, BLUE, NEXT_SLICE_FLAG];
+        matrix(&mut bytes, "a", uvec3(2, 1, 2), IVec3::ZERO, &slices);
+        let scene = QbScene::from_bytes(&bytes).unwrap();
+
+        // red and blue are swapped in BGRA files
+        assert_eq!(color(&scene, 0, uvec3(0, 0, 0)), [0, 0, 255, 255]);
+        assert_eq!(color(&scene, 0, uvec3(1, 0, 0)), [0, 0, 255, 255]);
+        assert_eq!(scene.matrices[0].voxel.get(uvec3(0, 0, 1)), Some(&0));
+        assert_eq!(color(&scene, 0, uvec3(1, 0, 1)), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn left_handed_matrices_are_mirrored() {
+        let mut bytes = header(false, false, false, 1);
+        matrix(
+            &mut bytes,
+            "a",
+            uvec3(1, 1, 3),
+            ivec3(5, 6, 7),
+            &[RED, 0, BLUE],
+        );
+        let scene = QbScene::from_bytes(&bytes).unwrap();
+
+        assert_eq!(scene.matrices[0].position, ivec3(5, 6, -10));
+        assert_eq!(color(&scene, 0, uvec3(0, 0, 0)), [0, 0, 255, 255]);
+        assert_eq!(scene.matrices[0].voxel.get(uvec3(0, 0, 1)), Some(&0));
+        assert_eq!(color(&scene, 0, uvec3(0, 0, 2)), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let mut bytes = header(false, true, true, 1);
+        matrix(
+            &mut bytes,
+            "a",
+            uvec3(2, 1, 1),
+            IVec3::ZERO,
+            &[RED, BLUE, NEXT_SLICE_FLAG],
+        );
+        assert!(QbScene::from_bytes(&bytes).is_ok());
+        for len in [0, 10, 30, bytes.len() - 1] {
+            assert!(matches!(
+                QbScene::from_bytes(&bytes[..len]),
+                Err(QbError::UnexpectedEof)
+            ));
+        }
+
+        let mut bytes = header(false, true, true, 1);
+        matrix(
+            &mut bytes,
+            "a",
+            uvec3(2, 1, 1),
+            IVec3::ZERO,
+            &[CODE_FLAG, 3, RED],
+        );
+        assert!(matches!(
+            QbScene::from_bytes(&bytes),
+            Err(QbError::InvalidMatrix("run past the end of a slice"))
+        ));
+
+        let mut bytes = header(false, true, false, 1);
+        matrix(&mut bytes, "a", uvec3(2, 0, 1), IVec3::ZERO, &[]);
+        assert!(matches!(
+            QbScene::from_bytes(&bytes),
+            Err(QbError::InvalidMatrix("invalid size"))
+        ));
+
+        let mut bytes = 257u32.to_le_bytes().to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        assert!(matches!(
+            QbScene::from_bytes(&bytes),
+            Err(QbError::UnsupportedColorFormat(2))
+        ));
+    }
+
+    #[test]
+    fn too_many_voxels_are_rejected() {
+        // each matrix fits on its own, but not together with the first one
+        let mut bytes = header(false, true, true, 2);
+        matrix(
+            &mut bytes,
+            "a",
+            UVec3::ONE,
+            IVec3::ZERO,
+            &[RED, NEXT_SLICE_FLAG],
+        );
+        matrix(&mut bytes, "b", uvec3(512, 512, 1024), IVec3::ZERO, &[]);
+        assert!(matches!(
+            QbScene::from_bytes(&bytes),
+            Err(QbError::InvalidMatrix("too many voxels"))
+        ));
+    }
+}
@@ -0,0 +1,408 @@
+use super::mesh::{linear_to_srgb, srgb_to_linear};
+use crate::*;
+use bevy::utils::HashMap;
+
+// Floyd-Steinberg in the xy and xz planes at once, each plane carrying half of the error. Every
+// offset comes later in scan order, which runs along x, then y, then z.
+const DIFFUSION: [(IVec3, f32); 7] = [
+    (ivec3(1, 0, 0), 14.0 / 32.0),
+    (ivec3(-1, 1, 0), 3.0 / 32.0),
+    (ivec3(0, 1, 0), 5.0 / 32.0),
+    (ivec3(1, 1, 0), 1.0 / 32.0),
+    (ivec3(-1, 0, 1), 3.0 / 32.0),
+    (ivec3(0, 0, 1), 5.0 / 32.0),
+    (ivec3(1, 0, 1), 1.0 / 32.0),
+];
+
+// Volume of true colors ordered like Voxel::get_index, transparent colors are empty voxels.
+#[derive(Clone, Debug)]
+pub struct RgbaVolume {
+    pub dimension: UVec3,
+    pub colors: Vec<[u8; 4]>,
+}
+impl RgbaVolume {
+    pub fn new(dimension: UVec3) -> Self {
+        let UVec3 { x, y, z } = dimension;
+        Self {
+            dimension,
+            colors: vec![[0; 4]; x as usize * y as usize * z as usize],
+        }
+    }
+    pub fn get_mut(&mut self, position: UVec3) -> Option<&mut [u8; 4]> {
+        Voxel::get_index(self.dimension, position).map(|i| &mut self.colors[i])
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Dithering {
+    #[default]
+    None,
+    // spreads the difference to the chosen palette entry over the following voxels, see DIFFUSION
+    FloydSteinberg,
+}
+
+// Reduces true colors to palette indices. Colors are compared in OKLab with alpha as a fourth
+// axis, so that distances follow how different colors look rather than their sRGB values.
+#[derive(Clone, Debug)]
+pub struct ColorQuantizer {
+    pub max_colors: usize, // entries of built palettes from index 1, at most 255
+    pub iterations: u32,   // of k-means refining the median cut
+    pub dithering: Dithering,
+}
+impl Default for ColorQuantizer {
+    fn default() -> Self {
+        Self {
+            max_colors: 255,
+            iterations: 8,
+            dithering: Dithering::None,
+        }
+    }
+}
+impl ColorQuantizer {
+    // Palette for colors by median cut, which keeps splitting the group of colors with the largest
+    // error at its weighted median, followed by k-means. Index 0 and unused entries stay empty.
+    pub fn palette(&self, colors: &[[u8; 4]]) -> VoxelColors {
+        // Colors are bucketed by their top 5 bits per channel, which keeps the number of samples
+        // for median cut and k-means small. Each bucket is sampled at the mean of its colors, so a
+        // bucket holding a single color keeps it exactly. Transparent colors are empty voxels and
+        // don't need an entry.
+        let mut histogram: HashMap<[u8; 4], ([u64; 4], u64)> = HashMap::new();
+        for &color in colors.iter().filter(|color| color[3] != 0) {
+            let (sum, count) = histogram.entry(color.map(|c| c >> 3)).or_default();
+            for (sum, c) in sum.iter_mut().zip(color) {
+                *sum += c as u64;
+            }
+            *count += 1;
+        }
+        // sorted so that the palette doesn't depend on the iteration order of the histogram
+        let mut histogram: Vec<_> = histogram.into_iter().collect();
+        histogram.sort_unstable();
+        let mut samples: Vec<(Vec4, f32)> = histogram
+            .into_iter()
+            .map(|(_, (sum, count))| {
+                let mean = sum.map(|sum| ((sum + count / 2) / count) as u8);
+                (oklab(mean), count as f32)
+            })
+            .collect();
+
+        let mut centers = median_cut(&mut samples, self.max_colors.clamp(1, 255));
+        for _ in 0..self.iterations {
+            if !refine_centers(&samples, &mut centers) {
+                break;
+            }
+        }
+        let mut palette = [[0; 4]; 256];
+        for (entry, &center) in palette[1..].iter_mut().zip(&centers) {
+            *entry = srgb(center);
+        }
+        VoxelColors::new(palette)
+    }
+    // Index of the closest entry of palette for each color, which is how a fixed palette is applied
+    // to colors without a volume to dither in. Transparent colors become 0.
+    pub fn nearest_indices(&self, colors: &[[u8; 4]], palette: &VoxelColors) -> Vec<u8> {
+        let entries = palette_entries(palette);
+        let mut cache = HashMap::new();
+        colors
+            .iter()
+            .map(|&color| match color[3] {
+                0 => 0,
+                _ => *cache
+                    .entry(color)
+                    .or_insert_with(|| nearest(&entries, oklab(color)).0),
+            })
+            .collect()
+    }
+    // Builds a palette from the colors of volume and maps volume to it.
+    pub fn quantize(&self, volume: &RgbaVolume) -> (Voxel, VoxelColors) {
+        let palette = self.palette(&volume.colors);
+        (self.remap(volume, &palette), palette)
+    }
+    // Maps volume to the entries of a fixed palette that aren't transparent, dithering if enabled.
+    pub fn remap(&self, volume: &RgbaVolume, palette: &VoxelColors) -> Voxel {
+        let dimension = volume.dimension;
+        let indices = match self.dithering {
+            Dithering::None => self.nearest_indices(&volume.colors, palette),
+            Dithering::FloydSteinberg => {
+                let entries = palette_entries(palette);
+                let mut errors = vec![Vec4::ZERO; volume.colors.len()];
+                let mut indices = vec![0; volume.colors.len()];
+                for position in VoxelRegion::new(UVec3::ZERO, dimension).positions() {
+                    let i = Voxel::get_index(dimension, position).unwrap();
+                    let color = volume.colors[i];
+                    if color[3] == 0 {
+                        continue;
+                    }
+                    let target = oklab(color) + errors[i];
+                    let (index, entry) = nearest(&entries, target);
+                    indices[i] = index;
+                    // alpha isn't dithered, it only decides which entries are close
+                    let error = (target - entry) * vec4(1.0, 1.0, 1.0, 0.0);
+                    for (offset, weight) in DIFFUSION {
+                        let neighbor = position.as_ivec3() + offset;
+                        if neighbor.cmplt(IVec3::ZERO).any() {
+                            continue;
+                        }
+                        if let Some(j) = Voxel::get_index(dimension, neighbor.as_uvec3()) {
+                            if volume.colors[j][3] != 0 {
+                                errors[j] += error * weight;
+                            }
+                        }
+                    }
+                }
+                indices
+            }
+        };
+        let mut voxel = Voxel::new(dimension);
+        voxel.for_each_mut(|value, position| {
+            *value = indices[Voxel::get_index(dimension, position).unwrap()];
+        });
+        voxel
+    }
+}
+
+// Weighted mean of each group of samples, with count groups or fewer when there aren't enough
+// different samples. Samples are reordered so that every group is a range.
+fn median_cut(samples: &mut [(Vec4, f32)], count: usize) -> Vec<Vec4> {
+    if samples.is_empty() {
+        return vec![];
+    }
+    // error of a group and the axis it varies most along
+    let spread = |group: &[(Vec4, f32)]| {
+        let mean = weighted_mean(group);
+        let variance = group
+            .iter()
+            .map(|&(sample, weight)| (sample - mean).powf(2.0) * weight)
+            .sum::<Vec4>();
+        let axis = (0..4)
+            .max_by(|&a, &b| variance[a].total_cmp(&variance[b]))
+            .unwrap();
+        (variance.dot(Vec4::ONE), axis)
+    };
+    let mut groups = vec![(0..samples.len(), spread(samples))];
+    while groups.len() < count {
+        let Some((i, _)) = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, (range, (error, _)))| range.len() > 1 && *error > 0.0)
+            .max_by(|(_, (_, (a, _))), (_, (_, (b, _)))| a.total_cmp(b))
+        else {
+            break;
+        };
+        let (range, (_, axis)) = groups[i].clone();
+        let group = &mut samples[range.clone()];
+        group.sort_by(|a, b| a.0[axis].total_cmp(&b.0[axis]));
+        let half = group.iter().map(|&(_, weight)| weight).sum::<f32>() / 2.0;
+        let mut below = 0.0;
+        let median = group
+            .iter()
+            .position(|&(_, weight)| {
+                below += weight;
+                below >= half
+            })
+            .unwrap();
+        let split = range.start + (median + 1).clamp(1, group.len() - 1);
+        let (low, high) = (range.start..split, split..range.end);
+        groups[i] = (low.clone(), spread(&samples[low]));
+        groups.push((high.clone(), spread(&samples[high])));
+    }
+    groups
+        .into_iter()
+        .map(|(range, _)| weighted_mean(&samples[range]))
+        .collect()
+}
+// One step of k-means, moves every center to the weighted mean of the samples closest to it and
+// returns whether any of them moved.
+fn refine_centers(samples: &[(Vec4, f32)], centers: &mut [Vec4]) -> bool {
+    let mut sums = vec![(Vec4::ZERO, 0.0); centers.len()];
+    for &(sample, weight) in samples {
+        let distance = |i: usize| centers[i].distance_squared(sample);
+        let closest = (0..centers.len())
+            .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+            .unwrap();
+        sums[closest].0 += sample * weight;
+        sums[closest].1 += weight;
+    }
+    let mut moved = false;
+    for (center, (sum, weight)) in centers.iter_mut().zip(sums) {
+        if weight > 0.0 {
+            let mean = sum / weight;
+            moved |= mean.distance_squared(*center) > 1e-10;
+            *center = mean;
+        }
+    }
+    moved
+}
+fn weighted_mean(samples: &[(Vec4, f32)]) -> Vec4 {
+    let (sum, weight) = samples
+        .iter()
+        .fold((Vec4::ZERO, 0.0), |(sum, total), &(sample, weight)| {
+            (sum + sample * weight, total + weight)
+        });
+    sum / weight
+}
+
+// Palette entries that voxels can be mapped to, in OKLab.
+fn palette_entries(palette: &VoxelColors) -> Vec<(Vec4, u8)> {
+    (1..256)
+        .filter(|&i| palette[i][3] != 0)
+        .map(|i| (oklab(palette[i]), i as u8))
+        .collect()
+}
+// Closest entry to color and its value, index 0 if there are no entries.
+fn nearest(entries: &[(Vec4, u8)], color: Vec4) -> (u8, Vec4) {
+    entries
+        .iter()
+        .min_by(|a, b| {
+            a.0.distance_squared(color)
+                .total_cmp(&b.0.distance_squared(color))
+        })
+        .map_or((0, color), |&(entry, index)| (index, entry))
+}
+
+// sRGB to OKLab with alpha in w, from Björn Ottosson's reference implementation.
+fn oklab(color: [u8; 4]) -> Vec4 {
+    let linear = srgb_to_linear(color);
+    let lms = Mat3::from_cols_array_2d(&[
+        [0.41222147, 0.53633254, 0.05144599],
+        [0.2119035, 0.6806995, 0.10739696],
+        [0.08830246, 0.28171884, 0.6299787],
+    ])
+    .transpose()
+        * linear.truncate();
+    let lms = Vec3::from_array(lms.to_array().map(f32::cbrt));
+    let lab = Mat3::from_cols_array_2d(&[
+        [0.21045426, 0.7936178, -0.004072047],
+        [1.9779985, -2.4285922, 0.4505937],
+        [0.025904037, 0.78277177, -0.80867577],
+    ])
+    .transpose()
+        * lms;
+    lab.extend(linear.w)
+}
+fn srgb(color: Vec4) -> [u8; 4] {
+    let lms = Mat3::from_cols_array_2d(&[
+        [1.0, 0.39633778, 0.21580376],
+        [1.0, -0.105561346, -0.06385417],
+        [1.0, -0.08948418, -1.2914855],
+    ])
+    .transpose()
+        * color.truncate();
+    let lms = lms.powf(3.0);
+    let linear = Mat3::from_cols_array_2d(&[
+        [4.0767417, -3.3077116, 0.23096994],
+        [-1.268438, 2.6097574, -0.34131938],
+        [-0.0041960864, -0.7034186, 1.7076147],
+    ])
+    .transpose()
+        * lms;
+    linear_to_srgb(linear.extend(color.w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> RgbaVolume {
+        let mut volume = RgbaVolume::new(uvec3(16, 16, 4));
+        for position in VoxelRegion::new(UVec3::ZERO, volume.dimension).positions() {
+            let UVec3 { x, y, z } = position;
+            *volume.get_mut(position).unwrap() = [x as u8 * 16, y as u8 * 16, z as u8 * 64, 255];
+        }
+        volume
+    }
+    fn entry_count(palette: &VoxelColors) -> usize {
+        palette.iter().filter(|color| color[3] != 0).count()
+    }
+
+    #[test]
+    fn palette_has_at_most_max_colors() {
+        let volume = gradient();
+        for max_colors in [1, 2, 7, 64] {
+            let quantizer = ColorQuantizer {
+                max_colors,
+                ..default()
+            };
+            let (voxel, palette) = quantizer.quantize(&volume);
+            assert_eq!(palette[0], [0; 4]);
+            assert_eq!(entry_count(&palette), max_colors);
+            for position in VoxelRegion::new(UVec3::ZERO, volume.dimension).positions() {
+                let value = *voxel.get(position).unwrap() as usize;
+                assert!((1..=max_colors).contains(&value));
+            }
+        }
+    }
+
+    #[test]
+    fn colors_that_fit_map_to_themselves() {
+        let colors = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+        ];
+        let mut volume = RgbaVolume::new(uvec3(5, 2, 1));
+        for (i, color) in volume.colors.iter_mut().enumerate() {
+            *color = colors[i % colors.len()];
+        }
+        let (voxel, palette) = ColorQuantizer::default().quantize(&volume);
+        assert_eq!(entry_count(&palette), colors.len());
+        let positions = VoxelRegion::new(UVec3::ZERO, volume.dimension).positions();
+        for (position, &color) in positions.zip(&volume.colors) {
+            let value = *voxel.get(position).unwrap();
+            assert_eq!(palette[value as usize], color);
+        }
+    }
+
+    #[test]
+    fn single_entry_palette() {
+        let mut volume = gradient();
+        volume.colors[3] = [10, 20, 30, 0];
+        let quantizer = ColorQuantizer {
+            max_colors: 1,
+            ..default()
+        };
+        let (voxel, palette) = quantizer.quantize(&volume);
+        assert_eq!(entry_count(&palette), 1);
+        for position in VoxelRegion::new(UVec3::ZERO, volume.dimension).positions() {
+            let index = Voxel::get_index(volume.dimension, position).unwrap();
+            assert_eq!(
+                *voxel.get(position).unwrap(),
+                if index == 3 { 0 } else { 1 }
+            );
+        }
+    }
+
+    #[test]
+    fn dithering_mixes_entries_and_skips_transparent_voxels() {
+        let mut volume = RgbaVolume::new(UVec3::splat(8));
+        for (i, color) in volume.colors.iter_mut().enumerate() {
+            *color = if i % 5 == 0 {
+                [255, 0, 0, 0]
+            } else {
+                [128, 128, 128, 255]
+            };
+        }
+        let mut palette = [[0; 4]; 256];
+        palette[1] = [0, 0, 0, 255];
+        palette[2] = [255, 255, 255, 255];
+        let palette = VoxelColors::new(palette);
+
+        let dithered = ColorQuantizer {
+            dithering: Dithering::FloydSteinberg,
+            ..default()
+        }
+        .remap(&volume, &palette);
+        let plain = ColorQuantizer::default().remap(&volume, &palette);
+        let mut counts = [0; 3];
+        for position in VoxelRegion::new(UVec3::ZERO, volume.dimension).positions() {
+            let index = Voxel::get_index(volume.dimension, position).unwrap();
+            let value = *dithered.get(position).unwrap();
+            assert_eq!(value == 0, index % 5 == 0);
+            assert_eq!(*plain.get(position).unwrap() == 0, index % 5 == 0);
+            counts[value as usize] += 1;
+        }
+        // a gray between both entries is spread over both of them
+        assert!(counts[1] > 100 && counts[2] > 100, "{counts:?}");
+    }
+}
@@ -0,0 +1,318 @@
+use crate::*;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct VoxelHit<T: VoxelElement = u8> {
+    pub position: UVec3, // voxel that was hit
+    pub normal: IVec3,   // face the ray entered through, zero when the ray starts inside the voxel
+    pub point: Vec3,     // where the ray entered the voxel, in the space of the ray
+    pub distance: f32,   // along the ray, in the units of the ray
+    pub value: T,
+}
+
+impl<T: VoxelElement> Voxel<T> {
+    // Walks the voxels along the ray the same way the fragment shader does and returns the first
+    // non-empty one, t is in multiples of direction. Ray space is voxel space, where the volume
+    // spans from 0 to its dimension and voxel boundaries lie on whole numbers.
+    fn traverse(&self, origin: Vec3, direction: Vec3, max_t: f32) -> Option<(UVec3, IVec3, f32)> {
+        let dimension = self.dimension().as_vec3();
+        // clip the ray to the bounds of the volume
+        let mut enter = 0.0f32;
+        let mut exit = max_t;
+        let mut enter_axis = None;
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if origin[axis] < 0.0 || origin[axis] >= dimension[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let a = -origin[axis] / direction[axis];
+            let b = (dimension[axis] - origin[axis]) / direction[axis];
+            let (near, far) = (a.min(b), a.max(b));
+            if near > enter {
+                enter = near;
+                enter_axis = Some(axis);
+            }
+            exit = exit.min(far);
+        }
+        if enter > exit {
+            return None;
+        }
+
+        let step = IVec3::select(
+            direction.cmpeq(Vec3::ZERO),
+            IVec3::ZERO,
+            direction.signum().as_ivec3(),
+        );
+        let mut position = (origin + direction * enter)
+            .floor()
+            .as_ivec3()
+            .clamp(IVec3::ZERO, self.dimension().as_ivec3() - 1);
+        let mut normal = IVec3::ZERO;
+        if let Some(axis) = enter_axis {
+            // floating point error must not put the first voxel on the wrong side of the entered face
+            position[axis] = if step[axis] > 0 {
+                0
+            } else {
+                self.dimension()[axis] as i32 - 1
+            };
+            normal[axis] = -step[axis];
+        }
+
+        let mut t = enter;
+        let mut next = Vec3::INFINITY; // t of the next boundary on each axis
+        let mut delta = Vec3::INFINITY; // t between boundaries on each axis
+        for axis in 0..3 {
+            if step[axis] != 0 {
+                let boundary = (position[axis] + (step[axis] > 0) as i32) as f32;
+                next[axis] = (boundary - origin[axis]) / direction[axis];
+                delta[axis] = 1.0 / direction[axis].abs();
+            }
+        }
+        loop {
+            let voxel = position.as_uvec3();
+            if *self.get(voxel).unwrap() != T::zeroed() {
+                return Some((voxel, normal, t));
+            }
+            let axis = if next.x <= next.y && next.x <= next.z {
+                0
+            } else if next.y <= next.z {
+                1
+            } else {
+                2
+            };
+            t = next[axis];
+            if t > exit {
+                return None;
+            }
+            position[axis] += step[axis];
+            if position[axis] < 0 || position[axis] >= self.dimension()[axis] as i32 {
+                return None;
+            }
+            next[axis] += delta[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+    }
+    // Casts a ray in voxel space and returns the first non-empty voxel within max_distance voxels.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<VoxelHit<T>> {
+        let direction = direction.try_normalize()?;
+        let (position, normal, distance) = self.traverse(origin, direction, max_distance)?;
+        Some(VoxelHit {
+            position,
+            normal,
+            point: origin + direction * distance,
+            distance,
+            value: *self.get(position).unwrap(),
+        })
+    }
+    // Casts a ray in world space against the voxel drawn with transform, mapping it into voxel space
+    // like the vertex and fragment shaders do with the model's inverse transform.
+    // The hit point and distance are in world space, position and normal are still in voxel space.
+    pub fn raycast_world(
+        &self,
+        transform: &GlobalTransform,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+    ) -> Option<VoxelHit<T>> {
+        let direction = direction.try_normalize()?;
+        let inv_transform = transform.compute_matrix().inverse();
+        let dimension = self.dimension().as_vec3();
+        // the model is a unit cube centered on its origin
+        let local_origin = (inv_transform.transform_point3(origin) + 0.5) * dimension;
+        let local_direction = inv_transform.transform_vector3(direction) * dimension;
+
+        // voxels along the ray per unit of world space
+        let scale = local_direction.length();
+        let hit = self.raycast(local_origin, local_direction, max_distance * scale)?;
+        let distance = hit.distance / scale;
+        Some(VoxelHit {
+            point: origin + direction * distance,
+            distance,
+            ..hit
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_voxel() -> Voxel {
+        let mut voxel = Voxel::new(uvec3(8, 6, 5));
+        *voxel.get_mut(uvec3(4, 2, 2)).unwrap() = 7;
+        voxel
+    }
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+    }
+
+    #[test]
+    fn axis_aligned_rays_hit_the_entered_face() {
+        let voxel = test_voxel();
+        let hit = voxel.raycast(vec3(-3.0, 2.5, 2.5), Vec3::X, 100.0).unwrap();
+        assert_eq!(hit.position, uvec3(4, 2, 2));
+        assert_eq!(hit.normal, ivec3(-1, 0, 0));
+        assert_eq!(hit.value, 7);
+        assert!((hit.distance - 7.0).abs() < 1e-5);
+        assert_near(hit.point, vec3(4.0, 2.5, 2.5));
+
+        let hit = voxel
+            .raycast(vec3(4.5, 10.0, 2.5), -Vec3::Y, 100.0)
+            .unwrap();
+        assert_eq!(hit.normal, ivec3(0, 1, 0));
+        assert!((hit.distance - 7.0).abs() < 1e-5);
+        // the length of direction doesn't matter
+        let hit = voxel.raycast(vec3(4.5, 2.5, 9.0), vec3(0.0, 0.0, -0.1), 100.0);
+        assert_eq!(hit.map(|hit| hit.normal), Some(ivec3(0, 0, 1)));
+    }
+
+    #[test]
+    fn rays_can_start_inside_the_volume() {
+        let voxel = test_voxel();
+        let hit = voxel.raycast(vec3(0.5, 2.5, 2.5), Vec3::X, 100.0).unwrap();
+        assert_eq!(hit.position, uvec3(4, 2, 2));
+        assert_eq!(hit.normal, ivec3(-1, 0, 0));
+        assert!((hit.distance - 3.5).abs() < 1e-5);
+
+        // inside the hit voxel there is no face that was entered
+        let hit = voxel.raycast(vec3(4.5, 2.5, 2.5), Vec3::Z, 1.0).unwrap();
+        assert_eq!((hit.normal, hit.distance), (IVec3::ZERO, 0.0));
+        assert_near(hit.point, vec3(4.5, 2.5, 2.5));
+    }
+
+    #[test]
+    fn rays_can_miss() {
+        let voxel = test_voxel();
+        assert_eq!(voxel.raycast(vec3(-3.0, 2.5, 2.5), -Vec3::X, 100.0), None);
+        assert_eq!(voxel.raycast(vec3(-3.0, 3.5, 2.5), Vec3::X, 100.0), None);
+        assert_eq!(voxel.raycast(vec3(0.5, 0.5, 0.5), Vec3::ONE, 100.0), None);
+        assert_eq!(voxel.raycast(vec3(-3.0, 2.5, 2.5), Vec3::ZERO, 100.0), None);
+        assert_eq!(
+            Voxel::<u8>::new(uvec3(8, 6, 5)).raycast(vec3(-3.0, 2.5, 2.5), Vec3::X, 100.0),
+            None
+        );
+    }
+
+    #[test]
+    fn max_distance_cuts_rays_off() {
+        let voxel = test_voxel();
+        assert_eq!(voxel.raycast(vec3(-3.0, 2.5, 2.5), Vec3::X, 6.9), None);
+        assert!(voxel.raycast(vec3(-3.0, 2.5, 2.5), Vec3::X, 7.1).is_some());
+        assert_eq!(voxel.raycast(vec3(-30.0, 2.5, 2.5), Vec3::X, 20.0), None);
+        assert_eq!(voxel.raycast(vec3(0.5, 2.5, 2.5), Vec3::X, 3.4), None);
+    }
+
+    #[test]
+    fn zero_direction_components_stay_in_their_slab() {
+        let voxel = test_voxel();
+        // diagonal in the xy plane, z never changes
+        let hit = voxel
+            .raycast(vec3(2.5, 0.75, 2.5), vec3(1.0, 1.0, 0.0), 100.0)
+            .unwrap();
+        assert_eq!(hit.position, uvec3(4, 2, 2));
+        assert_eq!(hit.normal, ivec3(-1, 0, 0));
+        assert!((hit.distance - 2.0f32.sqrt() * 1.5).abs() < 1e-5);
+        assert_eq!(
+            voxel.raycast(vec3(2.5, 0.75, 3.5), vec3(1.0, 1.0, 0.0), 100.0),
+            None
+        );
+
+        // outside of the volume on an axis the ray doesn't move along
+        assert_eq!(voxel.raycast(vec3(-3.0, 2.5, -0.5), Vec3::X, 100.0), None);
+        assert_eq!(voxel.raycast(vec3(-3.0, 2.5, 5.0), Vec3::X, 100.0), None);
+    }
+
+    #[test]
+    fn traversal_matches_small_steps() {
+        // brute force reference that samples the ray every step
+        fn march(voxel: &Voxel, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<UVec3> {
+            let direction = direction.normalize();
+            let mut t = 0.0;
+            while t < max_distance {
+                let point = origin + direction * t;
+                if point.cmpge(Vec3::ZERO).all() && point.cmplt(voxel.dimension().as_vec3()).all() {
+                    let position = point.floor().as_uvec3();
+                    if *voxel.get(position).unwrap() != 0 {
+                        return Some(position);
+                    }
+                }
+                t += 0.001;
+            }
+            None
+        }
+
+        let mut rng = SplitMix64::new(3);
+        let mut voxel: Voxel = Voxel::new(uvec3(9, 7, 11));
+        for position in VoxelRegion::new(UVec3::ZERO, voxel.dimension()).positions() {
+            if rng.next_f32() < 0.08 {
+                *voxel.get_mut(position).unwrap() = 1;
+            }
+        }
+        let mut agree = 0;
+        for _ in 0..100 {
+            let origin = vec3(
+                rng.range(-5.0..14.0),
+                rng.range(-5.0..12.0),
+                rng.range(-5.0..16.0),
+            );
+            let direction = vec3(
+                rng.range(-1.0..1.0),
+                rng.range(-1.0..1.0),
+                rng.range(-1.0..1.0),
+            );
+            let hit = voxel.raycast(origin, direction, 40.0);
+            if hit.map(|hit| hit.position) == march(&voxel, origin, direction, 40.0) {
+                agree += 1;
+            }
+            if let Some(hit) = hit {
+                // the hit point is on the boundary of the hit voxel, whose face looks back at the ray
+                let position = hit.position.as_vec3();
+                assert!(hit.point.cmpge(position - 1e-3).all());
+                assert!(hit.point.cmple(position + 1.0 + 1e-3).all());
+                if hit.normal != IVec3::ZERO {
+                    assert!(hit.normal.as_vec3().dot(direction) < 0.0);
+                }
+            }
+        }
+        // the steps can skip over the corner of a voxel that the ray only grazes
+        assert!(agree >= 98, "{agree}");
+    }
+
+    #[test]
+    fn world_rays_use_the_transform() {
+        let mut voxel: Voxel = Voxel::new(UVec3::splat(4));
+        *voxel.get_mut(uvec3(3, 1, 1)).unwrap() = 2;
+        // 2 units across, so voxels are 0.5 apart, and voxel x points along world -z
+        let transform = GlobalTransform::from(
+            Transform::from_translation(vec3(10.0, 0.0, 0.0))
+                .with_scale(Vec3::splat(2.0))
+                .with_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2)),
+        );
+        let hit = voxel
+            .raycast_world(&transform, vec3(9.75, -0.25, -10.0), Vec3::Z, 100.0)
+            .unwrap();
+        assert_eq!(hit.position, uvec3(3, 1, 1));
+        assert_eq!(hit.normal, ivec3(1, 0, 0));
+        assert_eq!(hit.value, 2);
+        // hit point and distance are in world space
+        assert!((hit.distance - 9.0).abs() < 1e-4);
+        assert_near(hit.point, vec3(9.75, -0.25, -1.0));
+        assert_eq!(
+            voxel.raycast_world(&transform, vec3(9.75, -0.25, -10.0), Vec3::Z, 8.9),
+            None
+        );
+
+        // scaled differently on each axis
+        let transform = GlobalTransform::from(
+            Transform::from_translation(vec3(0.0, 1.0, 0.0)).with_scale(vec3(4.0, 1.0, 2.0)),
+        );
+        let hit = voxel
+            .raycast_world(&transform, vec3(-5.0, 0.875, -0.25), Vec3::X, 100.0)
+            .unwrap();
+        assert_eq!(hit.position, uvec3(3, 1, 1));
+        assert_eq!(hit.normal, ivec3(-1, 0, 0));
+        assert!((hit.distance - 6.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum SliceError {
+    Image(ImageError),
+    NoSlices,
+    SizeMismatch {
+        slice: usize,
+        size: UVec2,
+        expected: UVec2,
+    },
+}
+impl fmt::Display for SliceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Image(e) => write!(f, "{e}"),
+            Self::NoSlices => write!(f, "no slices"),
+            Self::SizeMismatch {
+                slice,
+                size,
+                expected,
+            } => write!(
+                f,
+                "slice {slice} is {}x{} but the first slice is {}x{}",
+                size.x, size.y, expected.x, expected.y
+            ),
+        }
+    }
+}
+impl std::error::Error for SliceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<ImageError> for SliceError {
+    fn from(value: ImageError) -> Self {
+        Self::Image(value)
+    }
+}
+
+// Axis the volume is cut along, every slice is one layer of voxels perpendicular to it.
+// Y slices are seen from above with Z going down the image, X and Z slices are seen from the
+// positive side of their axis, upright with Y going up the image.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum SliceAxis {
+    X,
+    #[default]
+    Y,
+    Z,
+}
+impl SliceAxis {
+    // Size of the slices and number of layers of a volume.
+    fn slice_size(self, dimension: UVec3) -> (UVec2, u32) {
+        match self {
+            Self::X => (uvec2(dimension.z, dimension.y), dimension.x),
+            Self::Y => (uvec2(dimension.x, dimension.z), dimension.y),
+            Self::Z => (uvec2(dimension.x, dimension.y), dimension.z),
+        }
+    }
+    fn dimension(self, size: UVec2, layers: u32) -> UVec3 {
+        match self {
+            Self::X => uvec3(layers, size.y, size.x),
+            Self::Y => uvec3(size.x, layers, size.y),
+            Self::Z => uvec3(size.x, size.y, layers),
+        }
+    }
+    fn voxel_position(self, size: UVec2, layer: u32, pixel: UVec2) -> UVec3 {
+        let up = size.y - 1 - pixel.y;
+        match self {
+            Self::X => uvec3(layer, up, size.x - 1 - pixel.x),
+            Self::Y => uvec3(pixel.x, layer, pixel.y),
+            Self::Z => uvec3(pixel.x, up, layer),
+        }
+    }
+}
+
+impl Voxel {
+    // One image per layer from the lowest to the highest along axis, empty voxels are transparent.
+    pub fn to_slices(&self, colors: &VoxelColors, axis: SliceAxis) -> Vec<RgbaImage> {
+        let (size, layers) = axis.slice_size(self.dimension());
+        (0..layers)
+            .map(|layer| {
+                let pixels = (0..size.y)
+                    .flat_map(|y| (0..size.x).map(move |x| uvec2(x, y)))
+                    .map(|pixel| {
+                        let position = axis.voxel_position(size, layer, pixel);
+                        match *self.get(position).unwrap() {
+                            0 => [0; 4],
+                            value => colors[value as usize],
+                        }
+                    });
+                RgbaImage::from_rgba8(size, pixels)
+            })
+            .collect()
+    }
+    // Rebuilds a volume from slices ordered like to_slices writes them. Transparent pixels are
+    // empty and the rest take the palette index of their color, or of the closest one.
+    pub fn from_slices(
+        slices: &[RgbaImage],
+        colors: &VoxelColors,
+        axis: SliceAxis,
+    ) -> Result<Self, SliceError> {
+        let size = slices.first().ok_or(SliceError::NoSlices)?.size;
+        if let Some((slice, image)) = slices
+            .iter()
+            .enumerate()
+            .find(|(_, image)| image.size != size)
+        {
+            return Err(SliceError::SizeMismatch {
+                slice,
+                size: image.size,
+                expected: size,
+            });
+        }
+
+        // exact matches keep the index they were exported with, as long as the palette has no duplicates
+        let mut indices: HashMap<[u8; 4], u8> = HashMap::new();
+        for (i, &color) in colors.iter().enumerate().skip(1).rev() {
+            indices.insert(color, i as u8);
+        }
+        let mut voxel = Voxel::new(axis.dimension(size, slices.len() as u32));
+        for (layer, image) in slices.iter().enumerate() {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let color = image.get_rgba8(uvec2(x, y)).unwrap();
+                    if color[3] == 0 {
+                        continue;
+                    }
+                    let index = *indices
+                        .entry(color)
+                        .or_insert_with(|| colors.nearest(color));
+                    let position = axis.voxel_position(size, layer as u32, uvec2(x, y));
+                    *voxel.get_mut(position).unwrap() = index;
+                }
+            }
+        }
+        Ok(voxel)
+    }
+    // Writes the slices as slice_000.png, slice_001.png and so on into directory, which is created
+    // if needed, and returns their paths.
+    pub fn save_slices(
+        &self,
+        directory: impl AsRef<Path>,
+        colors: &VoxelColors,
+        axis: SliceAxis,
+    ) -> Result<Vec<PathBuf>, ImageError> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)?;
+        let slices = self.to_slices(colors, axis);
+        let digits = slices.len().to_string().len().max(3);
+        let mut paths = vec![];
+        for (layer, slice) in slices.iter().enumerate() {
+            let path = directory.join(format!("slice_{layer:0digits$}.png"));
+            slice.save_png(&path)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+    // Reads the slices in the order of paths.
+    pub fn load_slices(
+        paths: &[impl AsRef<Path>],
+        colors: &VoxelColors,
+        axis: SliceAxis,
+    ) -> Result<Self, SliceError> {
+        let slices = paths
+            .iter()
+            .map(RgbaImage::open)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_slices(&slices, colors, axis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AXES: [SliceAxis; 3] = [SliceAxis::X, SliceAxis::Y, SliceAxis::Z];
+
+    // every entry is a different opaque color
+    fn distinct_colors() -> VoxelColors {
+        let mut palette = [[0; 4]; 256];
+        for (i, entry) in palette.iter_mut().enumerate().skip(1) {
+            *entry = [i as u8, 255 - i as u8, (i * 7) as u8, 255];
+        }
+        VoxelColors::new(palette)
+    }
+    fn values(voxel: &Voxel) -> Vec<u8> {
+        VoxelRegion::new(UVec3::ZERO, voxel.dimension())
+            .positions()
+            .map(|position| *voxel.get(position).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn slices_round_trip_on_each_axis() {
+        let colors = distinct_colors();
+        let mut voxel = Voxel::new(uvec3(3, 4, 5));
+        voxel.for_each_mut(|value, position| {
+            *value = ((position.x + position.y * 3 + position.z * 12) * 37 % 256) as u8;
+        });
+        for axis in AXES {
+            let slices = voxel.to_slices(&colors, axis);
+            let (size, layers) = axis.slice_size(voxel.dimension());
+            assert_eq!(slices.len(), layers as usize);
+            assert!(slices.iter().all(|slice| slice.size == size));
+            let back = Voxel::from_slices(&slices, &colors, axis).unwrap();
+            assert_eq!(back.dimension(), voxel.dimension(), "{axis:?}");
+            assert_eq!(values(&back), values(&voxel), "{axis:?}");
+        }
+    }
+
+    #[test]
+    fn slices_are_oriented() {
+        let colors = distinct_colors();
+        let mut voxel = Voxel::new(uvec3(2, 2, 2));
+        *voxel.get_mut(uvec3(1, 0, 0)).unwrap() = 9;
+        let pixel =
+            |axis, layer: usize, pixel| voxel.to_slices(&colors, axis)[layer].get_rgba8(pixel);
+        // from above with Z going down the image
+        assert_eq!(pixel(SliceAxis::Y, 0, uvec2(1, 0)), Some(colors[9]));
+        // upright, seen from the positive side of the axis
+        assert_eq!(pixel(SliceAxis::Z, 0, uvec2(1, 1)), Some(colors[9]));
+        assert_eq!(pixel(SliceAxis::X, 1, uvec2(1, 1)), Some(colors[9]));
+        // empty voxels are transparent
+        assert_eq!(pixel(SliceAxis::Y, 0, uvec2(0, 0)), Some([0; 4]));
+    }
+
+    #[test]
+    fn unknown_colors_take_the_nearest_entry() {
+        let colors = distinct_colors();
+        let mut color = colors[200];
+        color[0] += 1;
+        let slice = RgbaImage::from_rgba8(uvec2(2, 1), [color, [255, 255, 255, 0]]);
+        let voxel = Voxel::from_slices(&[slice], &colors, SliceAxis::Y).unwrap();
+        assert_eq!(values(&voxel), [200, 0]);
+    }
+
+    #[test]
+    fn slices_need_the_same_size() {
+        let colors = distinct_colors();
+        let slices = [
+            RgbaImage::from_rgba8(uvec2(2, 2), [[0; 4]; 4]),
+            RgbaImage::from_rgba8(uvec2(3, 2), [[0; 4]; 6]),
+        ];
+        assert!(matches!(
+            Voxel::from_slices(&slices, &colors, SliceAxis::Y),
+            Err(SliceError::SizeMismatch { slice: 1, .. })
+        ));
+        assert!(matches!(
+            Voxel::from_slices(&[], &colors, SliceAxis::Y),
+            Err(SliceError::NoSlices)
+        ));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::*;
+use bevy::utils::HashMap;
+
+impl Voxel {
+    // Smooth surface around the non-empty voxels, see surface_nets_density.
+    pub fn surface_nets(&self, colors: &VoxelColors) -> VoxelMesh {
+        self.surface_nets_density(colors, 0.5, |position| {
+            let occupied = position.cmpge(IVec3::ZERO).all()
+                && self
+                    .get(position.as_uvec3())
+                    .is_some_and(|&value| value != 0);
+            occupied as u8 as f32
+        })
+    }
+    // Surface nets over a scalar field sampled at voxel centers, where values above iso_level are
+    // inside. density is also sampled one voxel beyond each side of the volume, so that returning
+    // empty values there closes the surface. Positions are in voxel space, normals follow the
+    // gradient of the field and colors come from the palette index of the closest inside voxel.
+    pub fn surface_nets_density(
+        &self,
+        colors: &VoxelColors,
+        iso_level: f32,
+        density: impl Fn(IVec3) -> f32,
+    ) -> VoxelMesh {
+        let dimension = self.dimension().as_ivec3();
+        // samples from -1 to dimension on each axis
+        let samples_size = dimension + 2;
+        let sample_index = |p: IVec3| {
+            ((p.x + 1) + (p.y + 1) * samples_size.x + (p.z + 1) * samples_size.x * samples_size.y)
+                as usize
+        };
+        let mut samples = vec![0.0; (samples_size.x * samples_size.y * samples_size.z) as usize];
+        for z in -1..=dimension.z {
+            for y in -1..=dimension.y {
+                for x in -1..=dimension.x {
+                    let position = ivec3(x, y, z);
+                    samples[sample_index(position)] = density(position) - iso_level;
+                }
+            }
+        }
+        let sample = |p: IVec3| samples[sample_index(p)];
+        const CORNERS: [IVec3; 8] = [
+            ivec3(0, 0, 0),
+            ivec3(1, 0, 0),
+            ivec3(0, 1, 0),
+            ivec3(1, 1, 0),
+            ivec3(0, 0, 1),
+            ivec3(1, 0, 1),
+            ivec3(0, 1, 1),
+            ivec3(1, 1, 1),
+        ];
+
+        // one vertex in every cell of 8 samples the surface passes through, cells are named after
+        // their lowest sample
+        let mut mesh = VoxelMesh::default();
+        let mut vertices = HashMap::new();
+        for z in -1..dimension.z {
+            for y in -1..dimension.y {
+                for x in -1..dimension.x {
+                    let cell = ivec3(x, y, z);
+                    let values = CORNERS.map(|corner| sample(cell + corner));
+                    if values.iter().all(|&v| v > 0.0) || values.iter().all(|&v| v <= 0.0) {
+                        continue;
+                    }
+
+                    // average of the points where the edges of the cell cross the surface
+                    let mut sum = Vec3::ZERO;
+                    let mut crossings = 0;
+                    for (a, b) in cube_edges() {
+                        let (va, vb) = (values[a], values[b]);
+                        if (va > 0.0) != (vb > 0.0) {
+                            let t = va / (va - vb);
+                            sum += CORNERS[a].as_vec3().lerp(CORNERS[b].as_vec3(), t);
+                            crossings += 1;
+                        }
+                    }
+                    let local = sum / crossings as f32;
+
+                    // the field increases inwards, so the normal points down its gradient
+                    let mut gradient = Vec3::ZERO;
+                    for (i, corner) in CORNERS.iter().enumerate() {
+                        gradient += (corner.as_vec3() * 2.0 - 1.0) * values[i];
+                    }
+                    let normal = (-gradient).normalize_or_zero();
+
+                    let index = self.closest_inside_value(cell, &values);
+                    vertices.insert(cell, mesh.positions.len() as u32);
+                    mesh.positions.push(cell.as_vec3() + 0.5 + local);
+                    mesh.normals.push(normal);
+                    mesh.colors.push(colors[index as usize]);
+                    mesh.palette_indices.push(index);
+                }
+            }
+        }
+
+        // a quad around every edge between two samples that crosses the surface
+        for z in -1..=dimension.z {
+            for y in -1..=dimension.y {
+                for x in -1..=dimension.x {
+                    let start = ivec3(x, y, z);
+                    for axis in 0..3 {
+                        let mut end = start;
+                        end[axis] += 1;
+                        if end[axis] > dimension[axis] {
+                            continue;
+                        }
+                        let inside = sample(start) > 0.0;
+                        if inside == (sample(end) > 0.0) {
+                            continue;
+                        }
+                        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                        let cell = |du: i32, dv: i32| {
+                            let mut cell = start;
+                            cell[u] -= du;
+                            cell[v] -= dv;
+                            vertices.get(&cell).copied()
+                        };
+                        // counterclockwise around the axis, reversed when the surface faces backwards
+                        let Some(mut quad) = [cell(1, 1), cell(0, 1), cell(0, 0), cell(1, 0)]
+                            .into_iter()
+                            .collect::<Option<Vec<u32>>>()
+                        else {
+                            continue;
+                        };
+                        if !inside {
+                            quad.reverse();
+                        }
+                        mesh.indices
+                            .extend([quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+                    }
+                }
+            }
+        }
+        mesh
+    }
+    // Palette index of the non-empty voxel among the corners of the cell that is furthest inside.
+    fn closest_inside_value(&self, cell: IVec3, values: &[f32; 8]) -> u8 {
+        let mut best = (f32::NEG_INFINITY, 0);
+        for (i, &value) in values.iter().enumerate() {
+            let corner = cell + ivec3(i as i32 & 1, (i as i32 >> 1) & 1, (i as i32 >> 2) & 1);
+            if corner.cmplt(IVec3::ZERO).any() {
+                continue;
+            }
+            if let Some(&index) = self.get(corner.as_uvec3()) {
+                if index != 0 && value > best.0 {
+                    best = (value, index);
+                }
+            }
+        }
+        best.1
+    }
+}
+
+// Pairs of corners that differ along a single axis, with bits 0, 1 and 2 of a corner's index as its x, y and z.
+fn cube_edges() -> impl Iterator<Item = (usize, usize)> {
+    (0..8).flat_map(|a| {
+        [1, 2, 4]
+            .into_iter()
+            .filter(move |bit| a & bit == 0)
+            .map(move |bit| (a, a | bit))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every edge is shared by exactly two triangles that run along it in opposite directions
+    fn assert_closed(mesh: &VoxelMesh) {
+        let mut edges = HashMap::new();
+        for triangle in mesh.indices.chunks_exact(3) {
+            for i in 0..3 {
+                *edges
+                    .entry((triangle[i], triangle[(i + 1) % 3]))
+                    .or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {a} {b} is used twice");
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {a} {b} is open");
+        }
+    }
+    // triangles and normals face away from center
+    fn assert_outwards(mesh: &VoxelMesh, center: Vec3) {
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[triangle[i] as usize]);
+            let face = (b - a).cross(c - a);
+            assert!(face.dot((a + b + c) / 3.0 - center) > 0.0, "{a} {b} {c}");
+        }
+        for (position, normal) in mesh.positions.iter().zip(&mesh.normals) {
+            assert!(normal.dot(*position - center) > 0.0, "{position} {normal}");
+        }
+    }
+
+    #[test]
+    fn single_voxel_is_closed() {
+        let colors = VoxelColors::all_color();
+        for dimension in [UVec3::ONE, UVec3::splat(3)] {
+            let mut voxel = Voxel::new(dimension);
+            let position = dimension / 2;
+            *voxel.get_mut(position).unwrap() = 9;
+            let mesh = voxel.surface_nets(&colors);
+            // a vertex in each of the cells around the voxel's sample, a quad on each of its sides
+            assert_eq!(mesh.positions.len(), 8);
+            assert_eq!(mesh.indices.len(), 6 * 6);
+            assert_closed(&mesh);
+            assert_outwards(&mesh, position.as_vec3() + 0.5);
+            for normal in &mesh.normals {
+                assert!((normal.length() - 1.0).abs() < 1e-5, "{normal}");
+            }
+            assert!(mesh.palette_indices.iter().all(|&index| index == 9));
+            assert!(mesh.colors.iter().all(|&color| color == colors[9]));
+        }
+    }
+
+    #[test]
+    fn spheres_touching_the_bounds_are_closed() {
+        let colors = VoxelColors::all_color();
+        let mut voxel = Voxel::new(UVec3::splat(12));
+        voxel.fill_sphere(Vec3::splat(6.0), 5.5, 3);
+        let center = Vec3::splat(6.0);
+        let mesh = voxel.surface_nets(&colors);
+        assert_closed(&mesh);
+        assert_outwards(&mesh, center);
+
+        // vertices of a density field follow its iso surface
+        let radius = 4.0;
+        let mesh = voxel.surface_nets_density(&colors, 0.0, |position| {
+            radius - (position.as_vec3() + 0.5).distance(center)
+        });
+        assert_closed(&mesh);
+        for position in &mesh.positions {
+            assert!(
+                (position.distance(center) - radius).abs() < 0.3,
+                "{position}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_voxels_have_no_surface() {
+        let colors = VoxelColors::all_color();
+        assert!(Voxel::new(UVec3::splat(3)).surface_nets(&colors).is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::*;
+
+// Palette indices used for each layer of the terrain.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainLayers {
+    pub grass: u8,
+    pub dirt: u8,
+    pub stone: u8,
+    pub water: u8,
+    pub dirt_depth: u32, // voxels of dirt between the surface and the stone below it
+}
+impl Default for TerrainLayers {
+    // colors of VoxelColors::all_color
+    fn default() -> Self {
+        Self {
+            grass: 0b11_00_10_00,
+            dirt: 0b11_00_01_11,
+            stone: 0b11_01_01_11,
+            water: 0b10_11_01_00,
+            dirt_depth: 3,
+        }
+    }
+}
+impl TerrainLayers {
+    // Palette index at height y of a column whose surface is at height, surface replaces the grass
+    // of the top voxel where it's given.
+    pub fn layer(&self, y: i32, height: f32, water_level: f32, surface: Option<u8>) -> u8 {
+        let top = height.floor() as i32;
+        if y > top {
+            return if (y as f32) < water_level {
+                self.water
+            } else {
+                0
+            };
+        }
+        let depth = (top - y) as u32;
+        match surface {
+            Some(index) if depth == 0 => index,
+            _ if depth == 0 && height >= water_level => self.grass,
+            _ if depth < self.dirt_depth.max(1) => self.dirt,
+            _ => self.stone,
+        }
+    }
+}
+
+// Heightmap terrain made of fBm noise, every value is in voxels unless noted otherwise.
+#[derive(Resource, Clone, Debug)]
+pub struct TerrainGenerator {
+    pub seed: u64,
+    pub octaves: u32,
+    pub frequency: f32, // of the first octave, in cycles per voxel
+    pub lacunarity: f32,
+    pub persistence: f32,
+    pub base_height: f32,
+    pub height_scale: f32, // how far the surface reaches above and below base_height
+    pub water_level: f32,  // empty space below this height is filled with water
+    pub layers: TerrainLayers,
+}
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            octaves: 5,
+            frequency: 1.0 / 48.0,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            base_height: 24.0,
+            height_scale: 16.0,
+            water_level: 20.0,
+            layers: TerrainLayers::default(),
+        }
+    }
+}
+impl TerrainGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+    pub fn noise(&self) -> PerlinNoise {
+        PerlinNoise::new(self.seed)
+    }
+    // Height of the surface at a world voxel column.
+    pub fn height(&self, noise: &PerlinNoise, x: i32, z: i32) -> f32 {
+        let point = vec3(x as f32, 0.0, z as f32) * self.frequency;
+        let value = noise.fbm(point, self.octaves, self.lacunarity, self.persistence);
+        self.base_height + value * self.height_scale
+    }
+    // Fills the voxel as the part of the terrain whose lowest corner is at origin, so that
+    // neighbouring volumes like the chunks of a VoxelWorld line up seamlessly.
+    pub fn fill(&self, voxel: &mut Voxel, origin: IVec3) {
+        let noise = self.noise();
+        let dimension = voxel.dimension();
+        let heights: Vec<f32> = (0..dimension.z)
+            .flat_map(|z| (0..dimension.x).map(move |x| (x, z)))
+            .map(|(x, z)| self.height(&noise, origin.x + x as i32, origin.z + z as i32))
+            .collect();
+        voxel.for_each_mut(|value, position| {
+            let height = heights[(position.x + position.z * dimension.x) as usize];
+            let y = origin.y + position.y as i32;
+            *value = self.layers.layer(y, height, self.water_level, None);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(generator: &TerrainGenerator, dimension: UVec3, origin: IVec3) -> Voxel {
+        let mut voxel = Voxel::new(dimension);
+        generator.fill(&mut voxel, origin);
+        voxel
+    }
+    fn values(voxel: &Voxel) -> Vec<u8> {
+        VoxelRegion::new(UVec3::ZERO, voxel.dimension())
+            .positions()
+            .map(|position| *voxel.get(position).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn seeds_decide_the_terrain() {
+        let dimension = uvec3(32, 48, 32);
+        let a = generate(&TerrainGenerator::new(42), dimension, IVec3::ZERO);
+        let b = generate(&TerrainGenerator::new(42), dimension, IVec3::ZERO);
+        let c = generate(&TerrainGenerator::new(43), dimension, IVec3::ZERO);
+        assert_eq!(values(&a), values(&b));
+        assert_ne!(values(&a), values(&c));
+    }
+
+    #[test]
+    fn chunks_line_up_with_one_volume() {
+        let generator = TerrainGenerator::new(7);
+        let whole = generate(&generator, uvec3(32, 48, 32), ivec3(-16, 0, -16));
+        for (x, z) in [(0, 0), (16, 0), (0, 16), (16, 16)] {
+            let chunk = generate(&generator, uvec3(16, 48, 16), ivec3(x - 16, 0, z - 16));
+            for position in VoxelRegion::new(UVec3::ZERO, chunk.dimension()).positions() {
+                let offset = uvec3(x as u32, 0, z as u32);
+                assert_eq!(
+                    chunk.get(position),
+                    whole.get(position + offset),
+                    "{position}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn columns_are_layered() {
+        let generator = TerrainGenerator::new(3);
+        let layers = generator.layers;
+        let voxel = generate(&generator, uvec3(32, 48, 32), IVec3::ZERO);
+        for z in 0..32 {
+            for x in 0..32 {
+                // stone, then dirt, then grass or dirt under water, then water or air
+                let column: Vec<u8> = (0..48)
+                    .map(|y| *voxel.get(uvec3(x, y, z)).unwrap())
+                    .collect();
+                let order = |value| {
+                    [layers.stone, layers.dirt, layers.grass, layers.water, 0]
+                        .iter()
+                        .position(|&layer| layer == value)
+                        .unwrap()
+                };
+                assert!(column
+                    .windows(2)
+                    .all(|pair| order(pair[0]) <= order(pair[1])));
+                assert_eq!(column[0], layers.stone);
+            }
+        }
+    }
+}
@@ -0,0 +1,639 @@
+use super::bytes::{ByteReader, UnexpectedEof};
+use crate::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum VoxError {
+    Io(io::Error),
+    InvalidMagic,
+    UnexpectedEof,
+    InvalidChunk(&'static str),
+    MissingChunk(&'static str),
+}
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::InvalidMagic => write!(f, "not a MagicaVoxel file"),
+            Self::UnexpectedEof => write!(f, "unexpected end of file"),
+            Self::InvalidChunk(id) => write!(f, "invalid {id} chunk"),
+            Self::MissingChunk(id) => write!(f, "missing {id} chunk"),
+        }
+    }
+}
+impl std::error::Error for VoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for VoxError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<UnexpectedEof> for VoxError {
+    fn from(_: UnexpectedEof) -> Self {
+        Self::UnexpectedEof
+    }
+}
+
+struct Chunk<'a> {
+    id: [u8; 4],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn string(&mut self) -> Result<String, VoxError> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+    fn dict(&mut self) -> Result<HashMap<String, String>, VoxError> {
+        let len = self.u32()?;
+        (0..len)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+    fn chunk(&mut self) -> Result<Chunk<'a>, VoxError> {
+        let id = self.take(4)?.try_into().unwrap();
+        let content_len = self.u32()? as usize;
+        let children_len = self.u32()? as usize;
+        Ok(Chunk {
+            id,
+            content: self.take(content_len)?,
+            children: self.take(children_len)?,
+        })
+    }
+}
+
+// MagicaVoxel's built-in palette, used when a file has no RGBA chunk.
+// Index 0 is empty, 1..=215 is a 6x6x6 color cube from white to dark red (black excluded),
+// and the remaining 40 entries are red, green, blue and gray ramps.
+pub fn default_vox_palette() -> VoxelColors {
+    const STEPS: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut colors = [[0; 4]; 256];
+    let mut i = 1;
+    for r in STEPS {
+        for g in STEPS {
+            for b in STEPS {
+                if r == 0 && g == 0 && b == 0 {
+                    continue;
+                }
+                colors[i] = [r, g, b, 0xff];
+                i += 1;
+            }
+        }
+    }
+    for channel in 0..3 {
+        for value in RAMP {
+            colors[i][channel] = value;
+            colors[i][3] = 0xff;
+            i += 1;
+        }
+    }
+    for value in RAMP {
+        colors[i] = [value, value, value, 0xff];
+        i += 1;
+    }
+    VoxelColors::new(colors)
+}
+
+// .vox files are Z-up while the renderer is Y-up, so the .vox Y axis is flipped into our Z axis to keep handedness.
+fn vox_to_voxel_position(size: UVec3, position: UVec3) -> UVec3 {
+    uvec3(position.x, position.z, size.y - 1 - position.y)
+}
+fn voxel_to_vox_position(dimension: UVec3, position: UVec3) -> UVec3 {
+    uvec3(position.x, dimension.z - 1 - position.z, position.y)
+}
+
+fn read_model(size: UVec3, content: &[u8]) -> Result<Voxel, VoxError> {
+    let mut reader = ByteReader(content);
+    let count = reader.u32()? as usize;
+    let voxels = reader.take(count.checked_mul(4).ok_or(VoxError::InvalidChunk("XYZI"))?)?;
+
+    let mut voxel = Voxel::new(uvec3(size.x, size.z, size.y));
+    for v in voxels.chunks_exact(4) {
+        let position = uvec3(v[0] as u32, v[1] as u32, v[2] as u32);
+        if position.cmpge(size).any() {
+            return Err(VoxError::InvalidChunk("XYZI"));
+        }
+        *voxel
+            .get_mut(vox_to_voxel_position(size, position))
+            .unwrap() = v[3];
+    }
+    Ok(voxel)
+}
+
+// Node of the scene graph that places the models of a .vox file.
+enum SceneNode {
+    Transform { child: i32, translation: IVec3 },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<usize> },
+}
+fn read_scene_node(chunk: &Chunk) -> Result<Option<(i32, SceneNode)>, VoxError> {
+    let mut content = ByteReader(chunk.content);
+    let node = match &chunk.id {
+        b"nTRN" => {
+            let id = content.i32()?;
+            let _attributes = content.dict()?;
+            let child = content.i32()?;
+            let _reserved = content.i32()?;
+            let _layer = content.i32()?;
+            let frames = content.u32()?;
+            let mut translation = IVec3::ZERO;
+            if frames > 0 {
+                if let Some(t) = content.dict()?.get("_t") {
+                    let t: Vec<i32> = t
+                        .split_whitespace()
+                        .map(|value| value.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| VoxError::InvalidChunk("nTRN"))?;
+                    translation =
+                        IVec3::from_slice(t.get(..3).ok_or(VoxError::InvalidChunk("nTRN"))?);
+                }
+            }
+            (id, SceneNode::Transform { child, translation })
+        }
+        b"nGRP" => {
+            let id = content.i32()?;
+            let _attributes = content.dict()?;
+            let len = content.u32()?;
+            let children = (0..len).map(|_| content.i32()).collect::<Result<_, _>>()?;
+            (id, SceneNode::Group { children })
+        }
+        b"nSHP" => {
+            let id = content.i32()?;
+            let _attributes = content.dict()?;
+            let len = content.u32()?;
+            let mut models = vec![];
+            for _ in 0..len {
+                models.push(content.u32()? as usize);
+                let _attributes = content.dict()?;
+            }
+            (id, SceneNode::Shape { models })
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(node))
+}
+// groups can share children, so a small scene graph can expand into exponentially many instances
+const MAX_VISITED_NODES: usize = 1 << 16;
+
+// Walks the scene graph from its root transform, adding up the translations on the way to each
+// model. MagicaVoxel places the center of a model, rounded down, at its translation. Rotations
+// aren't supported, rotated models are placed as if they weren't.
+fn place_models(
+    nodes: &HashMap<i32, SceneNode>,
+    sizes: &[UVec3],
+) -> Result<Vec<VoxInstance>, VoxError> {
+    let mut instances = vec![];
+    let mut stack = vec![(0, IVec3::ZERO, 0)];
+    let mut visited = 0;
+    while let Some((id, translation, depth)) = stack.pop() {
+        // deeper than there are nodes means a node is its own ancestor
+        if depth > nodes.len() {
+            return Err(VoxError::InvalidChunk("nTRN"));
+        }
+        visited += 1;
+        if visited > MAX_VISITED_NODES {
+            return Err(VoxError::InvalidChunk("nGRP"));
+        }
+        match nodes.get(&id) {
+            Some(SceneNode::Transform {
+                child,
+                translation: t,
+            }) => {
+                stack.push((*child, translation + *t, depth + 1));
+            }
+            Some(SceneNode::Group { children }) => {
+                for &child in children.iter().rev() {
+                    stack.push((child, translation, depth + 1));
+                }
+            }
+            Some(SceneNode::Shape { models }) => {
+                for &model in models {
+                    let size = *sizes.get(model).ok_or(VoxError::InvalidChunk("nSHP"))?;
+                    instances.push(VoxInstance {
+                        model,
+                        position: vox_to_voxel_corner(translation - (size / 2).as_ivec3(), size),
+                    });
+                }
+            }
+            None => return Err(VoxError::MissingChunk("nTRN")),
+        }
+    }
+    Ok(instances)
+}
+// Lowest corner in our axes of a model whose lowest corner in .vox axes is corner.
+fn vox_to_voxel_corner(corner: IVec3, size: UVec3) -> IVec3 {
+    ivec3(corner.x, corner.z, -(corner.y + size.y as i32))
+}
+
+// A model placed in the scene, position is its lowest corner in voxels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub position: IVec3,
+}
+
+pub struct VoxScene {
+    pub models: Vec<Voxel>,
+    pub instances: Vec<VoxInstance>, // every model at the origin if the file has no scene graph
+    pub colors: VoxelColors,
+}
+impl VoxScene {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, VoxError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VoxError> {
+        let mut reader = ByteReader(bytes);
+        if reader.take(4).ok() != Some(b"VOX ".as_slice()) {
+            return Err(VoxError::InvalidMagic);
+        }
+        let _version = reader.u32()?;
+
+        let main = reader.chunk()?;
+        if &main.id != b"MAIN" {
+            return Err(VoxError::MissingChunk("MAIN"));
+        }
+
+        let mut models = vec![];
+        let mut sizes = vec![];
+        let mut nodes = HashMap::new();
+        let mut colors = None;
+        let mut size = None;
+        let mut children = ByteReader(main.children);
+        while !children.is_empty() {
+            let chunk = children.chunk()?;
+            match &chunk.id {
+                b"SIZE" => {
+                    let mut content = ByteReader(chunk.content);
+                    let value = uvec3(content.u32()?, content.u32()?, content.u32()?);
+                    if value.cmpeq(UVec3::ZERO).any() || value.cmpgt(UVec3::splat(256)).any() {
+                        return Err(VoxError::InvalidChunk("SIZE"));
+                    }
+                    size = Some(value);
+                }
+                b"XYZI" => {
+                    let size = size.take().ok_or(VoxError::MissingChunk("SIZE"))?;
+                    models.push(read_model(size, chunk.content)?);
+                    sizes.push(size);
+                }
+                b"nTRN" | b"nGRP" | b"nSHP" => {
+                    let (id, node) = read_scene_node(&chunk)?.unwrap();
+                    nodes.insert(id, node);
+                }
+                b"RGBA" => {
+                    let mut content = ByteReader(chunk.content);
+                    let mut palette = [[0; 4]; 256];
+                    // color i in the chunk is used by palette index i + 1, the last entry is unused
+                    for color in palette.iter_mut().skip(1) {
+                        *color = content.take(4)?.try_into().unwrap();
+                    }
+                    colors = Some(VoxelColors::new(palette));
+                }
+                _ => {}
+            }
+        }
+        if models.is_empty() {
+            return Err(VoxError::MissingChunk("XYZI"));
+        }
+        let instances = if nodes.is_empty() {
+            let instance = |(model, &size): (usize, &UVec3)| VoxInstance {
+                model,
+                position: vox_to_voxel_corner(-(size / 2).as_ivec3(), size),
+            };
+            sizes.iter().enumerate().map(instance).collect()
+        } else {
+            place_models(&nodes, &sizes)?
+        };
+        if instances.is_empty() {
+            return Err(VoxError::MissingChunk("nSHP"));
+        }
+
+        Ok(Self {
+            models,
+            instances,
+            colors: colors.unwrap_or_else(default_vox_palette),
+        })
+    }
+    // Every instance copied into one volume that just fits them, later instances cover earlier ones.
+    pub fn merge(&self) -> Voxel {
+        let bounds = |instance: &VoxInstance| {
+            let min = instance.position;
+            (
+                min,
+                min + self.models[instance.model].dimension().as_ivec3(),
+            )
+        };
+        let (min, max) = self
+            .instances
+            .iter()
+            .map(bounds)
+            .fold((IVec3::MAX, IVec3::MIN), |(min, max), (low, high)| {
+                (min.min(low), max.max(high))
+            });
+        let mut merged = Voxel::new((max - min).as_uvec3());
+        for instance in &self.instances {
+            let model = &self.models[instance.model];
+            let offset = (instance.position - min).as_uvec3();
+            for position in VoxelRegion::new(UVec3::ZERO, model.dimension()).positions() {
+                let value = *model.get(position).unwrap();
+                if value != 0 {
+                    *merged.get_mut(offset + position).unwrap() = value;
+                }
+            }
+        }
+        merged
+    }
+}
+
+// models in a .vox file can be at most 256 voxels along each axis
+const MAX_MODEL_SIZE: u32 = 256;
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(content);
+}
+fn write_dict(out: &mut Vec<u8>, entries: &[(&str, &str)]) {
+    out.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (key, value) in entries {
+        for string in [key, value] {
+            out.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            out.extend_from_slice(string.as_bytes());
+        }
+    }
+}
+fn write_transform_node(out: &mut Vec<u8>, id: i32, child: i32, frame: &[(&str, &str)]) {
+    let mut content = id.to_le_bytes().to_vec();
+    write_dict(&mut content, &[]);
+    // child node, reserved id, layer and number of frames
+    for value in [child, -1, 0, 1] {
+        content.extend_from_slice(&value.to_le_bytes());
+    }
+    write_dict(&mut content, frame);
+    write_chunk(out, b"nTRN", &content);
+}
+
+struct VoxModel {
+    origin: UVec3,
+    size: UVec3,
+    voxels: Vec<[u8; 4]>,
+}
+
+impl Voxel {
+    pub fn save_vox(&self, colors: &VoxelColors, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_vox(colors, io::BufWriter::new(fs::File::create(path)?))
+    }
+    pub fn write_vox(&self, colors: &VoxelColors, mut writer: impl Write) -> io::Result<()> {
+        let dimension = self.dimension();
+        let size = uvec3(dimension.x, dimension.z, dimension.y);
+        let counts = (size + MAX_MODEL_SIZE - 1) / MAX_MODEL_SIZE;
+
+        let mut models: Vec<_> = (0..counts.x * counts.y * counts.z)
+            .map(|i| {
+                let origin = Voxel::get_position(counts, i as usize).unwrap() * MAX_MODEL_SIZE;
+                VoxModel {
+                    origin,
+                    size: (size - origin).min(UVec3::splat(MAX_MODEL_SIZE)),
+                    voxels: vec![],
+                }
+            })
+            .collect();
+        for z in 0..dimension.z {
+            for y in 0..dimension.y {
+                for x in 0..dimension.x {
+                    let position = uvec3(x, y, z);
+                    let value = *self.get(position).unwrap();
+                    if value == 0 {
+                        continue;
+                    }
+                    let position = voxel_to_vox_position(dimension, position);
+                    let model_position = position / MAX_MODEL_SIZE;
+                    let local = position % MAX_MODEL_SIZE;
+                    models[Voxel::get_index(counts, model_position).unwrap()]
+                        .voxels
+                        .push([local.x as u8, local.y as u8, local.z as u8, value]);
+                }
+            }
+        }
+        // empty models are written as well, so that the scene keeps the size of the volume
+
+        let mut children = vec![];
+        for model in &models {
+            let mut content = vec![];
+            for value in model.size.to_array() {
+                content.extend_from_slice(&value.to_le_bytes());
+            }
+            write_chunk(&mut children, b"SIZE", &content);
+
+            let mut content = (model.voxels.len() as u32).to_le_bytes().to_vec();
+            content.extend(model.voxels.iter().flatten());
+            write_chunk(&mut children, b"XYZI", &content);
+        }
+
+        // Models split from a single volume need a scene graph to be placed next to each other,
+        // root transform -> group -> (transform -> shape) for each model.
+        if models.len() > 1 {
+            write_transform_node(&mut children, 0, 1, &[]);
+
+            let mut content = 1i32.to_le_bytes().to_vec();
+            write_dict(&mut content, &[]);
+            content.extend_from_slice(&(models.len() as u32).to_le_bytes());
+            for i in 0..models.len() as i32 {
+                content.extend_from_slice(&(2 + i * 2).to_le_bytes());
+            }
+            write_chunk(&mut children, b"nGRP", &content);
+
+            for (i, model) in models.iter().enumerate() {
+                let i = i as i32;
+                // MagicaVoxel places a model's center (rounded down) at its translation
+                let translation =
+                    (model.origin + model.size / 2).as_ivec3() - (size / 2).as_ivec3();
+                let translation = format!("{} {} {}", translation.x, translation.y, translation.z);
+
+                write_transform_node(&mut children, 2 + i * 2, 3 + i * 2, &[("_t", &translation)]);
+
+                let mut content = vec![];
+                content.extend_from_slice(&(3 + i * 2).to_le_bytes());
+                write_dict(&mut content, &[]);
+                content.extend_from_slice(&1i32.to_le_bytes());
+                content.extend_from_slice(&i.to_le_bytes());
+                write_dict(&mut content, &[]);
+                write_chunk(&mut children, b"nSHP", &content);
+            }
+        }
+
+        // palette index i is stored as color i - 1, index 0 is always empty
+        let mut content = vec![];
+        for color in colors.iter().skip(1) {
+            content.extend_from_slice(color);
+        }
+        content.extend_from_slice(&[0; 4]);
+        write_chunk(&mut children, b"RGBA", &content);
+
+        writer.write_all(b"VOX ")?;
+        writer.write_all(&150u32.to_le_bytes())?;
+        writer.write_all(b"MAIN")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&(children.len() as u32).to_le_bytes())?;
+        writer.write_all(&children)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(dimension: UVec3) {
+        let mut voxel = Voxel::new(dimension);
+        voxel.for_each_mut(|value, position| {
+            if (position.x + 2 * position.y + 3 * position.z) % 7 < 3 {
+                *value = ((position.x + position.y * 5 + position.z * 11) % 255 + 1) as u8;
+            }
+        });
+        let mut palette = [[0; 4]; 256];
+        for (i, color) in palette.iter_mut().enumerate().skip(1) {
+            *color = [i as u8, 255 - i as u8, (i * 7) as u8, 255];
+        }
+        let colors = VoxelColors::new(palette);
+
+        let mut bytes = vec![];
+        voxel.write_vox(&colors, &mut bytes).unwrap();
+        let scene = VoxScene::from_bytes(&bytes).unwrap();
+        assert_eq!(*scene.colors, *colors);
+
+        let merged = scene.merge();
+        assert_eq!(merged.dimension(), dimension);
+        for position in VoxelRegion::new(UVec3::ZERO, dimension).positions() {
+            assert_eq!(merged.get(position), voxel.get(position), "{position}");
+        }
+    }
+
+    #[test]
+    fn round_trip_single_model() {
+        round_trip(uvec3(5, 3, 4));
+    }
+
+    #[test]
+    fn round_trip_split_models() {
+        // split into 2 models along x and 2 along the .vox y axis, which is our flipped z
+        round_trip(uvec3(300, 3, 270));
+    }
+
+    // A file of the given chunks, each without children.
+    fn vox(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut children = vec![];
+        for (id, content) in chunks {
+            write_chunk(&mut children, id, content);
+        }
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend_from_slice(&150u32.to_le_bytes());
+        bytes.extend_from_slice(b"MAIN");
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&children);
+        bytes
+    }
+    fn size(size: UVec3) -> (&'static [u8; 4], Vec<u8>) {
+        (b"SIZE", bytemuck::cast_slice(&size.to_array()).to_vec())
+    }
+    fn xyzi(voxels: &[[u8; 4]]) -> (&'static [u8; 4], Vec<u8>) {
+        let mut content = (voxels.len() as u32).to_le_bytes().to_vec();
+        content.extend(voxels.iter().flatten());
+        (b"XYZI", content)
+    }
+    fn group(id: i32, children: &[i32]) -> (&'static [u8; 4], Vec<u8>) {
+        let mut content = id.to_le_bytes().to_vec();
+        write_dict(&mut content, &[]);
+        content.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        for child in children {
+            content.extend_from_slice(&child.to_le_bytes());
+        }
+        (b"nGRP", content)
+    }
+
+    #[test]
+    fn default_palette_is_used_without_rgba() {
+        let bytes = vox(&[size(UVec3::ONE), xyzi(&[[0, 0, 0, 1]])]);
+        let scene = VoxScene::from_bytes(&bytes).unwrap();
+        assert_eq!(*scene.colors, *default_vox_palette());
+        assert_eq!(scene.colors[0], [0; 4]);
+        assert_eq!(scene.colors[1], [0xff; 4]);
+        assert_eq!(scene.colors[255], [0x11, 0x11, 0x11, 0xff]);
+    }
+
+    #[test]
+    fn z_up_models_are_turned_y_up() {
+        // .vox x, y and z become our x, flipped z and y
+        let bytes = vox(&[
+            size(uvec3(2, 3, 4)),
+            xyzi(&[[1, 0, 0, 1], [0, 2, 0, 2], [0, 0, 3, 3]]),
+        ]);
+        let model = &VoxScene::from_bytes(&bytes).unwrap().models[0];
+        assert_eq!(model.dimension(), uvec3(2, 4, 3));
+        let filled: Vec<_> = VoxelRegion::new(UVec3::ZERO, model.dimension())
+            .positions()
+            .map(|position| (position, *model.get(position).unwrap()))
+            .filter(|&(_, value)| value != 0)
+            .collect();
+        assert_eq!(
+            filled,
+            [
+                (uvec3(0, 0, 0), 2),
+                (uvec3(1, 0, 2), 1),
+                (uvec3(0, 3, 2), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_groups_are_capped() {
+        // every group lists the next one twice, doubling the paths down to the model 40 times
+        let mut chunks = vec![size(UVec3::ONE), xyzi(&[[0, 0, 0, 1]])];
+        let mut transform = vec![];
+        write_transform_node(&mut transform, 0, 1, &[]);
+        chunks.push((b"nTRN", transform[12..].to_vec()));
+        for id in 1..=40 {
+            chunks.push(group(id, &[id + 1, id + 1]));
+        }
+        let mut shape = 41i32.to_le_bytes().to_vec();
+        write_dict(&mut shape, &[]);
+        shape.extend_from_slice(&1u32.to_le_bytes());
+        shape.extend_from_slice(&0u32.to_le_bytes());
+        write_dict(&mut shape, &[]);
+        chunks.push((b"nSHP", shape));
+        assert!(matches!(
+            VoxScene::from_bytes(&vox(&chunks)),
+            Err(VoxError::InvalidChunk("nGRP"))
+        ));
+    }
+
+    #[test]
+    fn models_without_scene_graph_are_centered() {
+        let mut bytes = vec![];
+        Voxel::new(uvec3(4, 2, 6))
+            .write_vox(&default_vox_palette(), &mut bytes)
+            .unwrap();
+        let scene = VoxScene::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            scene.instances,
+            [VoxInstance {
+                model: 0,
+                position: ivec3(-2, -1, -3),
+            }]
+        );
+    }
+}
@@ -0,0 +1,454 @@
+use crate::*;
+use bevy::ecs::system::SystemParam;
+use bevy::utils::HashMap;
+
+#[derive(Component, Deref, Clone, Copy)]
+pub struct VoxelChunk(IVec3);
+
+// A chunk's voxels live in the world until it is in view, then they are moved into the chunk entity.
+// While the entity is still waiting to be spawned by commands the world keeps the voxels, and edits
+// made in the meantime are copied into the entity once it exists.
+enum Chunk {
+    Unloaded(Voxel),
+    Loading {
+        entity: Entity,
+        voxel: Voxel,
+        edited: bool,
+    },
+    Loaded(Entity),
+}
+
+#[derive(Resource)]
+pub struct VoxelWorld {
+    chunk_size: UVec3,
+    voxel_size: f32,
+    pub view_distance: u32, // in chunks
+    chunks: HashMap<IVec3, Chunk>,
+    center: Option<IVec3>, // chunk the camera was in the last time chunks were streamed
+}
+impl VoxelWorld {
+    pub fn new(chunk_size: UVec3, voxel_size: f32, view_distance: u32) -> Self {
+        Self {
+            chunk_size,
+            voxel_size,
+            view_distance,
+            chunks: HashMap::new(),
+            center: None,
+        }
+    }
+    pub const fn chunk_size(&self) -> UVec3 {
+        self.chunk_size
+    }
+    // Splits a world voxel position into the chunk containing it and the position inside that chunk.
+    pub fn split_position(&self, position: IVec3) -> (IVec3, UVec3) {
+        let chunk_size = self.chunk_size.as_ivec3();
+        (
+            position.div_euclid(chunk_size),
+            position.rem_euclid(chunk_size).as_uvec3(),
+        )
+    }
+    pub fn voxel_position(&self, translation: Vec3) -> IVec3 {
+        (translation / self.voxel_size).floor().as_ivec3()
+    }
+    pub fn chunk_transform(&self, chunk: IVec3) -> Transform {
+        let size = self.chunk_size.as_vec3() * self.voxel_size;
+        Transform::from_translation((chunk.as_vec3() + 0.5) * size).with_scale(size)
+    }
+    // Adds a chunk or replaces its voxels. A chunk that is in view keeps its entity, which gets the
+    // new voxels the next time chunks are streamed.
+    pub fn insert_chunk(&mut self, chunk: IVec3, voxel: Voxel) {
+        assert_eq!(
+            voxel.dimension(),
+            self.chunk_size,
+            "chunk dimension must match the world's chunk size!"
+        );
+        match self.chunks.get_mut(&chunk) {
+            Some(&mut Chunk::Loaded(entity)) => {
+                self.chunks.insert(
+                    chunk,
+                    Chunk::Loading {
+                        entity,
+                        voxel,
+                        edited: true,
+                    },
+                );
+            }
+            Some(Chunk::Loading {
+                voxel: old, edited, ..
+            }) => {
+                *old = voxel;
+                *edited = true;
+            }
+            _ => {
+                self.chunks.insert(chunk, Chunk::Unloaded(voxel));
+                self.center = None;
+            }
+        }
+    }
+    pub fn contains_chunk(&self, chunk: IVec3) -> bool {
+        self.chunks.contains_key(&chunk)
+    }
+}
+
+// World coordinate access to the voxels of both loaded and unloaded chunks.
+#[derive(SystemParam)]
+pub struct WorldVoxels<'w, 's> {
+    world: ResMut<'w, VoxelWorld>,
+    chunk_q: Query<'w, 's, &'static mut Voxel, With<VoxelChunk>>,
+}
+impl<'w, 's> WorldVoxels<'w, 's> {
+    pub fn world(&self) -> &VoxelWorld {
+        &self.world
+    }
+    pub fn get(&self, position: IVec3) -> u8 {
+        let (chunk, local) = self.world.split_position(position);
+        match self.world.chunks.get(&chunk) {
+            Some(Chunk::Unloaded(voxel) | Chunk::Loading { voxel, .. }) => {
+                *voxel.get(local).unwrap()
+            }
+            Some(Chunk::Loaded(entity)) => self
+                .chunk_q
+                .get(*entity)
+                .map_or(0, |voxel| *voxel.get(local).unwrap()),
+            None => 0,
+        }
+    }
+    pub fn set(&mut self, position: IVec3, value: u8) {
+        let (chunk, local) = self.world.split_position(position);
+        if value == 0 && !self.world.contains_chunk(chunk) {
+            return;
+        }
+        match self.world.chunks.get_mut(&chunk) {
+            Some(Chunk::Unloaded(voxel)) => *voxel.get_mut(local).unwrap() = value,
+            Some(Chunk::Loading { voxel, edited, .. }) => {
+                *voxel.get_mut(local).unwrap() = value;
+                *edited = true;
+            }
+            Some(&mut Chunk::Loaded(entity)) => match self.chunk_q.get_mut(entity) {
+                Ok(mut voxel) => *voxel.get_mut(local).unwrap() = value,
+                // a chunk entity that was despawned by someone else took its voxels with it,
+                // so the chunk starts over without them
+                Err(_) => {
+                    self.world.chunks.remove(&chunk);
+                    self.set(position, value);
+                }
+            },
+            None => {
+                let mut voxel = Voxel::new(self.world.chunk_size);
+                *voxel.get_mut(local).unwrap() = value;
+                self.world.insert_chunk(chunk, voxel);
+            }
+        }
+    }
+}
+
+// Chunks spawned the last time chunks were streamed have entities now, edits made to them in the
+// meantime are copied over.
+fn finish_loading(world: &mut VoxelWorld, chunk_q: &mut Query<&mut Voxel, With<VoxelChunk>>) {
+    for chunk in world.chunks.values_mut() {
+        let Chunk::Loading { entity, .. } = *chunk else {
+            continue;
+        };
+        let Ok(mut chunk_voxel) = chunk_q.get_mut(entity) else {
+            continue;
+        };
+        let Chunk::Loading { voxel, edited, .. } = std::mem::replace(chunk, Chunk::Loaded(entity))
+        else {
+            unreachable!();
+        };
+        if edited {
+            *chunk_voxel = voxel;
+            chunk_voxel.mark_all_dirty();
+        }
+    }
+}
+
+// Spawns entities for the chunks within view distance of center, with the components bundle returns
+// for the voxels and transform of each chunk, and despawns those that are further away.
+fn stream<B: Bundle>(
+    commands: &mut Commands,
+    world: &mut VoxelWorld,
+    chunk_q: &mut Query<&mut Voxel, With<VoxelChunk>>,
+    center: IVec3,
+    bundle: impl Fn(Voxel, Transform) -> B,
+) {
+    if world.center == Some(center) {
+        return;
+    }
+    world.center = Some(center);
+
+    let view_distance = world.view_distance as i32;
+    let (load, unload): (Vec<_>, Vec<_>) = world
+        .chunks
+        .iter()
+        .filter_map(|(&position, chunk)| {
+            let distance = (position - center).abs().max_element();
+            match chunk {
+                Chunk::Unloaded(_) if distance <= view_distance => Some((position, true)),
+                // unload one chunk further out than loading to avoid thrashing on chunk borders
+                Chunk::Loaded(_) | Chunk::Loading { .. } if distance > view_distance + 1 => {
+                    Some((position, false))
+                }
+                _ => None,
+            }
+        })
+        .partition(|&(_, load)| load);
+
+    for (position, _) in load {
+        let Some(Chunk::Unloaded(voxel)) = world.chunks.remove(&position) else {
+            unreachable!();
+        };
+        let transform = world.chunk_transform(position);
+        let entity = commands
+            .spawn((
+                bundle(voxel.clone(), transform),
+                VoxelChunk(position),
+                VoxelHighlight::default(),
+            ))
+            .id();
+        world.chunks.insert(
+            position,
+            Chunk::Loading {
+                entity,
+                voxel,
+                edited: false,
+            },
+        );
+    }
+    for (position, _) in unload {
+        match world.chunks.remove(&position) {
+            Some(Chunk::Loading { entity, voxel, .. }) => {
+                world.chunks.insert(position, Chunk::Unloaded(voxel));
+                commands.entity(entity).despawn();
+            }
+            Some(Chunk::Loaded(entity)) => {
+                // a chunk entity that was despawned by someone else took its voxels with it
+                if let Ok(mut voxel) = chunk_q.get_mut(entity) {
+                    let voxel =
+                        std::mem::replace(voxel.bypass_change_detection(), Voxel::new(UVec3::ZERO));
+                    world.chunks.insert(position, Chunk::Unloaded(voxel));
+                    commands.entity(entity).despawn();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub(super) fn stream_chunks(
+    mut commands: Commands,
+    renderer: Res<Renderer>,
+    pipeline: Res<Pipeline>,
+    main_camera: Res<MainCamera>,
+    mut world: ResMut<VoxelWorld>,
+    transform_q: Query<&GlobalTransform>,
+    mut chunk_q: Query<&mut Voxel, With<VoxelChunk>>,
+) {
+    finish_loading(&mut world, &mut chunk_q);
+
+    let Ok(camera) = transform_q.get(**main_camera) else {
+        return;
+    };
+    let (center, _) = world.split_position(world.voxel_position(camera.translation()));
+    stream(
+        &mut commands,
+        &mut world,
+        &mut chunk_q,
+        center,
+        |voxel, transform| {
+            let mut bundle = VoxelBundle::from_voxel(voxel, &renderer, &pipeline);
+            bundle.transform = TransformBundle::from_transform(transform);
+            bundle
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    const CHUNK_SIZE: UVec3 = uvec3(4, 2, 8);
+
+    fn world(chunks: impl IntoIterator<Item = IVec3>) -> World {
+        let mut voxel_world = VoxelWorld::new(CHUNK_SIZE, 0.5, 1);
+        for chunk in chunks {
+            voxel_world.insert_chunk(chunk, Voxel::new(CHUNK_SIZE));
+        }
+        let mut world = World::new();
+        world.insert_resource(voxel_world);
+        world
+    }
+    // Streams chunks like stream_chunks, with entities that only have the voxels of their chunk.
+    fn stream_around(world: &mut World, center: IVec3) {
+        world.run_system_once(
+            move |mut commands: Commands,
+                  mut voxel_world: ResMut<VoxelWorld>,
+                  mut chunk_q: Query<&mut Voxel, With<VoxelChunk>>| {
+                finish_loading(&mut voxel_world, &mut chunk_q);
+                stream(
+                    &mut commands,
+                    &mut voxel_world,
+                    &mut chunk_q,
+                    center,
+                    |voxel, _| voxel,
+                );
+            },
+        );
+    }
+    fn spawned(world: &mut World) -> Vec<IVec3> {
+        let mut chunks: Vec<IVec3> = world
+            .query::<&VoxelChunk>()
+            .iter(world)
+            .map(|chunk| **chunk)
+            .collect();
+        chunks.sort_by_key(|chunk| chunk.to_array());
+        chunks
+    }
+    fn is_loaded(world: &World, chunk: IVec3) -> bool {
+        matches!(
+            world.resource::<VoxelWorld>().chunks.get(&chunk),
+            Some(Chunk::Loaded(_))
+        )
+    }
+    fn get(world: &mut World, position: IVec3) -> u8 {
+        world.run_system_once(move |voxels: WorldVoxels| voxels.get(position))
+    }
+    fn set(world: &mut World, position: IVec3, value: u8) {
+        world.run_system_once(move |mut voxels: WorldVoxels| voxels.set(position, value));
+    }
+    fn chunk_value(world: &mut World, chunk: IVec3, local: UVec3) -> u8 {
+        let mut chunk_q = world.query::<(&VoxelChunk, &Voxel)>();
+        let (_, voxel) = chunk_q
+            .iter(world)
+            .find(|(position, _)| ***position == chunk)
+            .unwrap();
+        *voxel.get(local).unwrap()
+    }
+
+    #[test]
+    fn positions_split_into_chunks() {
+        let world = VoxelWorld::new(CHUNK_SIZE, 0.5, 1);
+        assert_eq!(
+            world.split_position(ivec3(3, 2, 15)),
+            (ivec3(0, 1, 1), uvec3(3, 0, 7))
+        );
+        // negative positions belong to the chunk below them rather than being rounded towards zero
+        assert_eq!(
+            world.split_position(ivec3(-1, -2, -9)),
+            (ivec3(-1, -1, -2), uvec3(3, 0, 7))
+        );
+        assert_eq!(
+            world.split_position(ivec3(-4, -3, -8)),
+            (ivec3(-1, -2, -1), uvec3(0, 1, 0))
+        );
+        assert_eq!(world.voxel_position(vec3(-0.1, 1.0, 7.9)), ivec3(-1, 2, 15));
+        assert_eq!(
+            world.chunk_transform(ivec3(-1, 0, 2)),
+            Transform::from_translation(vec3(-1.0, 0.5, 10.0)).with_scale(vec3(2.0, 1.0, 4.0))
+        );
+    }
+
+    #[test]
+    fn chunks_unload_one_chunk_further_out_than_they_load() {
+        let mut world = world((-3..=3).map(|x| ivec3(x, 0, 0)));
+        stream_around(&mut world, IVec3::ZERO);
+        assert_eq!(spawned(&mut world), [-1, 0, 1].map(|x| ivec3(x, 0, 0)));
+
+        stream_around(&mut world, ivec3(1, 0, 0));
+        assert_eq!(spawned(&mut world), [-1, 0, 1, 2].map(|x| ivec3(x, 0, 0)));
+        assert!(is_loaded(&world, ivec3(-1, 0, 0)));
+
+        stream_around(&mut world, ivec3(2, 0, 0));
+        assert_eq!(spawned(&mut world), [0, 1, 2, 3].map(|x| ivec3(x, 0, 0)));
+        // moving back across the border doesn't unload anything
+        stream_around(&mut world, ivec3(1, 0, 0));
+        assert_eq!(spawned(&mut world), [0, 1, 2, 3].map(|x| ivec3(x, 0, 0)));
+        assert!((0..=3).all(|x| is_loaded(&world, ivec3(x, 0, 0))));
+    }
+
+    #[test]
+    fn unloaded_chunks_keep_their_voxels() {
+        let mut world = world([IVec3::ZERO, ivec3(3, 0, 0)]);
+        stream_around(&mut world, IVec3::ZERO);
+        stream_around(&mut world, IVec3::ZERO);
+        set(&mut world, ivec3(1, 1, 1), 5);
+        assert_eq!(chunk_value(&mut world, IVec3::ZERO, uvec3(1, 1, 1)), 5);
+
+        stream_around(&mut world, ivec3(3, 0, 0));
+        assert_eq!(spawned(&mut world), [ivec3(3, 0, 0)]);
+        assert_eq!(get(&mut world, ivec3(1, 1, 1)), 5);
+        stream_around(&mut world, IVec3::ZERO);
+        assert_eq!(chunk_value(&mut world, IVec3::ZERO, uvec3(1, 1, 1)), 5);
+    }
+
+    #[test]
+    fn set_reaches_every_chunk() {
+        let mut world = world([IVec3::ZERO, ivec3(5, 0, 0)]);
+        stream_around(&mut world, IVec3::ZERO);
+        assert!(!is_loaded(&world, IVec3::ZERO));
+
+        // unloaded
+        set(&mut world, ivec3(21, 1, 2), 2);
+        assert_eq!(get(&mut world, ivec3(21, 1, 2)), 2);
+        // loading, the entity gets the edit once it is loaded
+        set(&mut world, ivec3(1, 1, 1), 3);
+        assert_eq!(get(&mut world, ivec3(1, 1, 1)), 3);
+        assert_eq!(chunk_value(&mut world, IVec3::ZERO, uvec3(1, 1, 1)), 0);
+        stream_around(&mut world, IVec3::ZERO);
+        assert!(is_loaded(&world, IVec3::ZERO));
+        assert_eq!(chunk_value(&mut world, IVec3::ZERO, uvec3(1, 1, 1)), 3);
+        // loaded
+        set(&mut world, ivec3(2, 0, 7), 4);
+        assert_eq!(get(&mut world, ivec3(2, 0, 7)), 4);
+        assert_eq!(chunk_value(&mut world, IVec3::ZERO, uvec3(2, 0, 7)), 4);
+
+        // missing chunks are only created for filled voxels
+        set(&mut world, ivec3(-1, 0, 0), 0);
+        assert!(!world
+            .resource::<VoxelWorld>()
+            .contains_chunk(ivec3(-1, 0, 0)));
+        set(&mut world, ivec3(-1, 0, 0), 6);
+        assert!(world
+            .resource::<VoxelWorld>()
+            .contains_chunk(ivec3(-1, 0, 0)));
+        assert_eq!(get(&mut world, ivec3(-1, 0, 0)), 6);
+        assert_eq!(get(&mut world, ivec3(-2, 0, 0)), 0);
+    }
+
+    #[test]
+    fn despawned_chunks_start_over() {
+        let mut world = world([IVec3::ZERO]);
+        stream_around(&mut world, IVec3::ZERO);
+        stream_around(&mut world, IVec3::ZERO);
+        set(&mut world, ivec3(1, 1, 1), 3);
+        let entity = world
+            .query_filtered::<Entity, With<VoxelChunk>>()
+            .single(&world);
+        world.despawn(entity);
+
+        assert_eq!(get(&mut world, ivec3(1, 1, 1)), 0);
+        set(&mut world, ivec3(2, 1, 1), 4);
+        assert_eq!(get(&mut world, ivec3(2, 1, 1)), 4);
+        assert_eq!(get(&mut world, ivec3(1, 1, 1)), 0);
+        assert!(!is_loaded(&world, IVec3::ZERO));
+    }
+
+    #[test]
+    fn inserting_a_loaded_chunk_reuses_its_entity() {
+        let mut world = world([IVec3::ZERO]);
+        stream_around(&mut world, IVec3::ZERO);
+        stream_around(&mut world, IVec3::ZERO);
+
+        let mut voxel = Voxel::new(CHUNK_SIZE);
+        *voxel.get_mut(uvec3(3, 1, 7)).unwrap() = 9;
+        world
+            .resource_mut::<VoxelWorld>()
+            .insert_chunk(IVec3::ZERO, voxel);
+        assert_eq!(get(&mut world, ivec3(3, 1, 7)), 9);
+        stream_around(&mut world, IVec3::ZERO);
+        assert_eq!(spawned(&mut world), [IVec3::ZERO]);
+        assert!(is_loaded(&world, IVec3::ZERO));
+        assert_eq!(chunk_value(&mut world, IVec3::ZERO, uvec3(3, 1, 7)), 9);
+    }
+}